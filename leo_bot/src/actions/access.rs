@@ -0,0 +1,262 @@
+use crate::{
+    actions::{action::Action, office::apply_access},
+    discord::Discord,
+    models::{OfficeMode, Room},
+    state::BotState,
+};
+use async_trait::async_trait;
+use serenity::model::channel::Message;
+
+const PREFIX: &str = "!office";
+const USAGE: &str = "Usage: `!office`, `!office mode <open|queue|invite>`, `!office limit <n|none>`, `!office invite|uninvite <@members @roles>` or `!office admit <@members>`";
+
+/// Action to let teachers change who may join their office
+pub(crate) struct AccessAction<'a> {
+    discord: &'a dyn Discord,
+    state: &'a BotState,
+    message: &'a Message,
+}
+
+/// Implement utility functions for action
+impl<'a> AccessAction<'a> {
+    pub(crate) fn new(discord: &'a dyn Discord, state: &'a BotState, message: &'a Message) -> Self {
+        AccessAction {
+            discord,
+            state,
+            message,
+        }
+    }
+
+    fn describe(room: &Room) -> String {
+        let limit = match room.access.user_limit {
+            0 => "no limit".to_string(),
+            n => format!("{} members max", n),
+        };
+        let allowed: Vec<String> = room
+            .access
+            .users
+            .iter()
+            .map(|u| format!("<@{}>", u))
+            .chain(room.access.roles.iter().map(|r| format!("<@&{}>", r)))
+            .collect();
+
+        format!(
+            "Office is {} ({}), allowed: {}",
+            room.access.mode,
+            limit,
+            allowed.join(" ")
+        )
+    }
+
+    fn invite(&self, room: &mut Room) {
+        for user in &self.message.mentions {
+            if !room.access.users.contains(&user.id.0) {
+                room.access.users.push(user.id.0);
+            }
+        }
+        for role in &self.message.mention_roles {
+            if !room.access.roles.contains(&role.0) {
+                room.access.roles.push(role.0);
+            }
+        }
+    }
+
+    fn uninvite(&self, room: &mut Room) {
+        room.access
+            .users
+            .retain(|u| !self.message.mentions.iter().any(|m| m.id.0 == *u));
+        room.access
+            .roles
+            .retain(|r| !self.message.mention_roles.iter().any(|m| m.0 == *r));
+    }
+
+    /// Move the mentioned members from the waiting room to the office
+    async fn move_admitted(&self, office_id: u64) {
+        let guild = self.state.config.read().await.guild;
+
+        for user in &self.message.mentions {
+            self.discord
+                .move_member(guild, user.id.0, office_id)
+                .await
+                .ok();
+        }
+    }
+
+    /// Change the settings of `room`, returns false when there is nothing to change
+    fn update(&self, room: &mut Room, args: &[&str]) -> Result<bool, String> {
+        match args {
+            [] => return Ok(false),
+            ["mode", mode] => room.access.mode = mode.parse::<OfficeMode>()?,
+            ["limit", "none"] => room.access.user_limit = 0,
+            ["limit", limit] => {
+                room.access.user_limit = limit
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|l| *l <= 99)
+                    .ok_or_else(|| format!("invalid limit: {}", limit))?
+            }
+            ["invite"] | ["admit"] => self.invite(room),
+            ["uninvite"] => self.uninvite(room),
+            _ => return Err(USAGE.to_string()),
+        }
+
+        Ok(true)
+    }
+}
+
+/// Implement the action trait
+#[async_trait]
+impl Action for AccessAction<'_> {
+    async fn can_execute(&self) -> bool {
+        if self.message.content.split_whitespace().next() != Some(PREFIX) {
+            return false;
+        }
+
+        let rooms = self.state.rooms.read().await;
+
+        rooms
+            .iter()
+            .any(|r| r.discord_id == self.message.author.id.0)
+    }
+
+    async fn execute(&self) {
+        let args: Vec<&str> = self.message.content.split_whitespace().skip(1).collect();
+        // Mentions are read from the message itself
+        let args: Vec<&str> = args.into_iter().filter(|a| !a.starts_with("<@")).collect();
+
+        let mut rooms = self.state.rooms.write().await;
+        let index = match rooms
+            .iter()
+            .position(|r| r.discord_id == self.message.author.id.0)
+        {
+            Some(index) => index,
+            None => return,
+        };
+
+        // The changes are made on a copy, kept only once Discord applied them
+        let mut room = rooms[index].clone();
+        let reply = match self.update(&mut room, &args) {
+            Ok(true) => match apply_access(self.discord, self.state, &mut room).await {
+                Ok(()) => {
+                    let reply = Self::describe(&room);
+                    let office_id = room.office_id;
+                    rooms[index] = room;
+                    self.state.store.save_rooms(&rooms);
+
+                    if args == ["admit"] {
+                        self.move_admitted(office_id).await;
+                    }
+                    reply
+                }
+                Err(e) => format!("Couldn't update the office: {}", e),
+            },
+            Ok(false) => Self::describe(&room),
+            Err(e) => e,
+        };
+        drop(rooms);
+
+        self.discord
+            .reply(self.message.channel_id.0, self.message.id.0, &reply)
+            .await
+            .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AccessAction;
+    use crate::{
+        actions::action::{schedule_action, Action},
+        discord::fake::{message, FakeGuild},
+        models::OfficeMode,
+        state::fake,
+    };
+
+    #[actix_rt::test]
+    async fn only_the_owner_changes_access() {
+        let fakes = fake::state(fake::config(), vec![fake::room(5, 30)]);
+        let guild = FakeGuild::with_channels(&[30]);
+
+        let msg = message(6, &[], "!office mode invite");
+        assert!(
+            !AccessAction::new(&guild, &fakes.state, &msg)
+                .can_execute()
+                .await
+        );
+    }
+
+    #[actix_rt::test]
+    async fn queue_and_admit() {
+        let fakes = fake::state(fake::config(), vec![fake::room(5, 30)]);
+        let guild = FakeGuild::with_channels(&[30]);
+
+        let msg = message(5, &[], "!office mode queue");
+        schedule_action(AccessAction::new(&guild, &fakes.state, &msg)).await;
+        let waiting_id = fakes.state.rooms.read().await[0].waiting_id;
+        assert_eq!(guild.guild().channels[&waiting_id].name, "Waiting room");
+        // @everyone is denied, the teacher is allowed
+        assert_eq!(guild.guild().channels[&30].overwrites.len(), 2);
+
+        guild.connect(8, Some(waiting_id));
+        let msg = message(5, &[], "!office admit <@8>");
+        schedule_action(AccessAction::new(&guild, &fakes.state, &msg)).await;
+        assert_eq!(guild.guild().voice[&8], 30);
+        assert_eq!(fakes.state.rooms.read().await[0].access.users, vec![8]);
+        assert_eq!(guild.guild().channels[&30].overwrites.len(), 3);
+
+        let msg = message(5, &[], "!office mode open");
+        schedule_action(AccessAction::new(&guild, &fakes.state, &msg)).await;
+        let rooms = fakes.state.rooms.read().await;
+        assert_eq!(rooms[0].access.mode, OfficeMode::Open);
+        assert_eq!(rooms[0].waiting_id, 0);
+        assert!(!guild.guild().channels.contains_key(&waiting_id));
+        assert_eq!(fakes.store.0.lock().unwrap()[0].waiting_id, 0);
+    }
+
+    #[actix_rt::test]
+    async fn failed_changes_are_not_kept() {
+        let fakes = fake::state(fake::config(), vec![fake::room(5, 30)]);
+        // The office channel is gone, Discord refuses the edit
+        let guild = FakeGuild::default();
+
+        let msg = message(5, &[], "!office mode queue");
+        schedule_action(AccessAction::new(&guild, &fakes.state, &msg)).await;
+        let msg = message(5, &[], "!office limit 5");
+        schedule_action(AccessAction::new(&guild, &fakes.state, &msg)).await;
+
+        let rooms = fakes.state.rooms.read().await;
+        assert_eq!(rooms[0].access.mode, OfficeMode::Open);
+        assert_eq!(rooms[0].access.user_limit, 0);
+        let stored = fakes.store.0.lock().unwrap();
+        assert_eq!(stored[0].access.mode, OfficeMode::Open);
+        assert_eq!(stored[0].access.user_limit, 0);
+
+        let replies = guild.messages();
+        assert_eq!(replies.len(), 2);
+        assert!(replies
+            .iter()
+            .all(|(_, m)| m.starts_with("Couldn't update the office")));
+    }
+
+    #[actix_rt::test]
+    async fn limits_are_checked() {
+        let fakes = fake::state(fake::config(), vec![fake::room(5, 30)]);
+        let guild = FakeGuild::with_channels(&[30]);
+
+        for content in ["!office limit 5", "!office limit 500", "!office"] {
+            let msg = message(5, &[], content);
+            schedule_action(AccessAction::new(&guild, &fakes.state, &msg)).await;
+        }
+
+        assert_eq!(guild.guild().channels[&30].user_limit, 5);
+        let replies: Vec<String> = guild.messages().into_iter().map(|(_, m)| m).collect();
+        assert_eq!(
+            replies,
+            vec![
+                "Office is open (5 members max), allowed: ",
+                "invalid limit: 500",
+                "Office is open (5 members max), allowed: ",
+            ]
+        );
+    }
+}
@@ -1,4 +1,8 @@
 use async_trait::async_trait;
+use shared_lib::trace::{self, RequestId};
+use tracing::Instrument;
+
+use crate::metrics;
 
 /// Give some structure to each feature
 /// It's necessary to put #[async_trait] for each implementation
@@ -8,9 +12,22 @@ pub(crate) trait Action {
     async fn execute(&self);
 }
 
+/// Run `action` in a span named after it, as a request of its own unless already in one,
+/// so the messages it sends to the backend are followed there
+///
+/// The executions are counted by action in the metrics
 //TODO error handling
 pub(crate) async fn schedule_action<A: Action>(action: A) {
-    if action.can_execute().await {
-        action.execute().await;
-    }
+    let request_id = RequestId::current().unwrap_or_default();
+    let name = std::any::type_name::<A>().rsplit("::").next().unwrap_or("");
+    let span = tracing::info_span!("action", action = name, request_id = request_id.as_str());
+
+    let scheduled = async {
+        if action.can_execute().await {
+            metrics::count_action(name, action.execute()).await;
+        }
+    };
+    trace::scope(Some(request_id), scheduled)
+        .instrument(span)
+        .await;
 }
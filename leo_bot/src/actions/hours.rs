@@ -0,0 +1,232 @@
+use crate::{actions::action::Action, discord::Discord, state::BotState};
+use async_trait::async_trait;
+use serenity::model::channel::Message;
+use shared_lib::{
+    office::{format_time, format_weekday, parse_time, parse_weekday, HoursChange, OfficeHours},
+    socket::message::BotResponse,
+};
+
+const PREFIX: &str = "!hours";
+const DEFAULT_TIMEZONE: &str = "Europe/Paris";
+const USAGE: &str = "Usage: `!hours list`, `!hours add <subject> <weekday> <HH:MM> <HH:MM> [timezone]` or `!hours remove <id>`";
+
+/// Action to let teachers manage their office hours
+pub(crate) struct HoursAction<'a> {
+    discord: &'a dyn Discord,
+    state: &'a BotState,
+    message: &'a Message,
+}
+
+/// Implement utility functions for action
+impl<'a> HoursAction<'a> {
+    pub(crate) fn new(discord: &'a dyn Discord, state: &'a BotState, message: &'a Message) -> Self {
+        HoursAction {
+            discord,
+            state,
+            message,
+        }
+    }
+
+    async fn list(&self) -> String {
+        let schedule = self.state.schedule.read().await;
+
+        let lines: Vec<String> = schedule
+            .iter()
+            .filter(|h| h.teacher == self.message.author.id.0)
+            .map(|h| {
+                format!(
+                    "`{}` {} {}-{} ({}) for subject {}",
+                    h.id,
+                    format_weekday(h.weekday),
+                    format_time(h.start),
+                    format_time(h.end),
+                    h.timezone,
+                    h.subject
+                )
+            })
+            .collect();
+
+        match lines.is_empty() {
+            true => "No office hours scheduled".to_string(),
+            false => lines.join("\n"),
+        }
+    }
+
+    /// Ask the backend to store the hours, it answers with `ServerRequest::OfficeHoursChanged`
+    async fn add(&self, args: &[&str]) -> Result<(), String> {
+        let (subject, weekday, start, end) = match args {
+            [subject, weekday, start, end] | [subject, weekday, start, end, _] => {
+                (subject, weekday, start, end)
+            }
+            _ => return Err(USAGE.to_string()),
+        };
+
+        let subject = subject
+            .parse::<u64>()
+            .map_err(|_| format!("invalid subject: {}", subject))?;
+        let known = {
+            let config = self.state.config.read().await;
+            config.subjects.iter().any(|s| s.id == subject)
+        };
+        if !known {
+            return Err(format!("unknown subject: {}", subject));
+        }
+
+        let hours = OfficeHours {
+            id: 0,
+            teacher: self.message.author.id.0,
+            subject,
+            weekday: parse_weekday(weekday)
+                .ok_or_else(|| format!("invalid weekday: {}", weekday))?,
+            start: parse_time(start).ok_or_else(|| format!("invalid time: {}", start))?,
+            end: parse_time(end).ok_or_else(|| format!("invalid time: {}", end))?,
+            timezone: args.get(4).unwrap_or(&DEFAULT_TIMEZONE).to_string(),
+        };
+        hours.validate()?;
+
+        self.state.send(BotResponse::AddOfficeHours {
+            hours,
+            channel: self.message.channel_id.0,
+            message: self.message.id.0,
+        });
+
+        Ok(())
+    }
+
+    /// Ask the backend to delete the hours, it answers with `ServerRequest::OfficeHoursChanged`
+    async fn remove(&self, args: &[&str]) -> Result<(), String> {
+        let id = match args {
+            [id] => id
+                .parse::<i64>()
+                .map_err(|_| format!("invalid id: {}", id))?,
+            _ => return Err(USAGE.to_string()),
+        };
+
+        self.state.send(BotResponse::RemoveOfficeHours {
+            id,
+            teacher: self.message.author.id.0,
+            channel: self.message.channel_id.0,
+            message: self.message.id.0,
+        });
+
+        Ok(())
+    }
+}
+
+/// Answer to a change once the backend saved it, or failed to
+pub(crate) fn format_change(change: &HoursChange, result: &Result<(), String>) -> String {
+    match (change, result) {
+        (HoursChange::Added, Ok(())) => "Office hours added".to_string(),
+        (HoursChange::Removed(_), Ok(())) => "Office hours removed".to_string(),
+        (HoursChange::Added, Err(e)) => format!("Couldn't add office hours: {}", e),
+        (HoursChange::Removed(id), Err(e)) => {
+            format!("Couldn't remove office hours `{}`: {}", id, e)
+        }
+    }
+}
+
+/// Implement the action trait
+#[async_trait]
+impl Action for HoursAction<'_> {
+    async fn can_execute(&self) -> bool {
+        if self.message.content.split_whitespace().next() != Some(PREFIX) {
+            return false;
+        }
+
+        let config = self.state.config.read().await;
+
+        match &self.message.member {
+            Some(member) => config.is_teacher(&member.roles),
+            None => false,
+        }
+    }
+
+    async fn execute(&self) {
+        let args: Vec<&str> = self.message.content.split_whitespace().skip(1).collect();
+
+        // Valid changes are answered once the backend saved them
+        let reply = match args.split_first() {
+            None | Some((&"list", [])) => Some(self.list().await),
+            Some((&"add", args)) => self.add(args).await.err(),
+            Some((&"remove", args)) => self.remove(args).await.err(),
+            _ => Some(USAGE.to_string()),
+        };
+
+        if let Some(reply) = reply {
+            self.discord
+                .reply(self.message.channel_id.0, self.message.id.0, &reply)
+                .await
+                .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HoursAction;
+    use crate::{
+        actions::action::{schedule_action, Action},
+        discord::fake::{message, FakeGuild},
+        state::fake,
+    };
+    use shared_lib::{office::OfficeHours, socket::message::BotResponse};
+
+    const TEACHER: u64 = 2;
+
+    #[actix_rt::test]
+    async fn students_cant_manage_hours() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        let msg = message(5, &[3], "!hours list");
+        assert!(
+            !HoursAction::new(&guild, &fakes.state, &msg)
+                .can_execute()
+                .await
+        );
+    }
+
+    #[actix_rt::test]
+    async fn teachers_add_and_list_hours() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        let msg = message(5, &[TEACHER], "!hours add 20 monday 10:00 12:00");
+        schedule_action(HoursAction::new(&guild, &fakes.state, &msg)).await;
+        assert!(matches!(
+            fakes.sent().await.as_slice(),
+            [BotResponse::AddOfficeHours {
+                hours: OfficeHours {
+                    teacher: 5,
+                    subject: 20,
+                    ..
+                },
+                ..
+            }]
+        ));
+
+        let msg = message(5, &[TEACHER], "!hours add 99 monday 10:00 12:00");
+        schedule_action(HoursAction::new(&guild, &fakes.state, &msg)).await;
+
+        fakes.state.schedule.write().await.push(OfficeHours {
+            id: 4,
+            teacher: 5,
+            subject: 20,
+            weekday: 0,
+            start: 600,
+            end: 720,
+            timezone: "Europe/Paris".to_string(),
+        });
+        let msg = message(5, &[TEACHER], "!hours");
+        schedule_action(HoursAction::new(&guild, &fakes.state, &msg)).await;
+
+        let replies: Vec<String> = guild.messages().into_iter().map(|(_, m)| m).collect();
+        assert_eq!(
+            replies,
+            vec![
+                "unknown subject: 99",
+                "`4` mon 10:00-12:00 (Europe/Paris) for subject 20",
+            ]
+        );
+    }
+}
@@ -1,3 +1,9 @@
+pub(crate) mod access;
 pub(crate) mod action;
+pub(crate) mod hours;
 pub(crate) mod office;
+pub(crate) mod stats;
+pub(crate) mod status;
 pub(crate) mod subject;
+pub(crate) mod welcome;
+pub(crate) mod whois;
@@ -1,17 +1,23 @@
-use crate::{actions::action::Action, get_config_lock, get_rooms_lock, models::Room};
+use crate::{
+    actions::action::Action,
+    audit::log_event,
+    discord::Discord,
+    models::{OfficeAccess, OfficeMode, Room},
+    state::BotState,
+    sweeper,
+};
 use async_trait::async_trait;
-use serenity::{
-    client::Context,
-    model::{
-        channel::ChannelType,
-        id::{ChannelId, GuildId},
-        prelude::VoiceState,
-    },
+use serenity::model::{id::GuildId, prelude::VoiceState};
+use shared_lib::{
+    audit::{AuditAction, AuditEvent},
+    socket::message::BotResponse,
 };
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 /// Action to open teachers' rooms
 pub(crate) struct OpenRoomAction<'a> {
-    context: &'a Context,
+    discord: &'a dyn Discord,
+    state: &'a BotState,
     guild_id: &'a Option<GuildId>,
     voice: &'a VoiceState,
 }
@@ -19,52 +25,124 @@ pub(crate) struct OpenRoomAction<'a> {
 /// Implement utility functions for action
 impl<'a> OpenRoomAction<'a> {
     pub(crate) fn new(
-        context: &'a Context,
+        discord: &'a dyn Discord,
+        state: &'a BotState,
         guild_id: &'a Option<GuildId>,
         voice: &'a VoiceState,
     ) -> Self {
         OpenRoomAction {
-            context,
+            discord,
+            state,
             guild_id,
             voice,
         }
     }
 
     async fn move_user(&self, guild_id: &GuildId, office_id: u64) {
-        guild_id
-            .move_member(self.context, self.voice.user_id, office_id)
+        self.discord
+            .move_member(guild_id.0, self.voice.user_id.0, office_id)
             .await
-            .unwrap();
+            .ok();
     }
 
     async fn create_rooms(&self, guild_id: &GuildId) -> Result<Room, serenity::Error> {
-        let config_lock = get_config_lock(self.context).await;
-        let config = config_lock.read().await;
-
-        let office = guild_id
-            .create_channel(self.context, |c| {
-                c.name("Channel test".to_string())
-                    .category(config.teacher_category)
-                    .permissions(Vec::new())
-                    .kind(ChannelType::Voice)
-            })
-            .await?;
-
-        Ok(Room {
-            discord_id: self.voice.user_id.0,
-            office_id: office.id.0,
-            waiting_id: 0,
-            text_id: 0,
-        })
+        create_office(self.discord, self.state, guild_id, self.voice.user_id.0).await
     }
 }
 
+/// Create the office of `teacher` in the teachers' category
+pub(crate) async fn create_office(
+    discord: &dyn Discord,
+    state: &BotState,
+    guild_id: &GuildId,
+    teacher: u64,
+) -> Result<Room, serenity::Error> {
+    let config = state.config.read().await;
+    let access = OfficeAccess::default();
+
+    let office_id = discord
+        .create_voice_channel(
+            guild_id.0,
+            "Channel test",
+            config.teacher_category,
+            access.overwrites(teacher, guild_id.0),
+        )
+        .await?;
+
+    Ok(Room {
+        discord_id: teacher,
+        office_id,
+        waiting_id: 0,
+        text_id: 0,
+        schedule: None,
+        teacher_left_at: None,
+        empty_since: None,
+        access,
+        subject: None,
+        opened_at: state.clock.timestamp(),
+        waiting: HashMap::new(),
+        present: HashMap::new(),
+        visits: Vec::new(),
+    })
+}
+
+/// Align the office channels with the access settings of `room`
+///
+/// The waiting room only exists in queue mode
+pub(crate) async fn apply_access(
+    discord: &dyn Discord,
+    state: &BotState,
+    room: &mut Room,
+) -> Result<(), serenity::Error> {
+    let (guild, category) = {
+        let config = state.config.read().await;
+        (GuildId(config.guild), config.teacher_category)
+    };
+
+    let overwrites = room.access.overwrites(room.discord_id, guild.0);
+    discord
+        .edit_voice_channel(room.office_id, overwrites, room.access.user_limit)
+        .await?;
+
+    match (room.access.mode, room.waiting_id) {
+        (OfficeMode::Queue, 0) => {
+            room.waiting_id = discord
+                .create_voice_channel(guild.0, "Waiting room", category, Vec::new())
+                .await?;
+        }
+        (OfficeMode::Open | OfficeMode::Invite, waiting) if waiting != 0 => {
+            discord.delete_channel(waiting).await?;
+            room.waiting_id = 0;
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Delete every channel of `room` and send its session to the backend
+pub(crate) async fn delete_office(
+    discord: &dyn Discord,
+    state: &BotState,
+    room: &Room,
+) -> Result<(), serenity::Error> {
+    if room.waiting_id != 0 {
+        discord.delete_channel(room.waiting_id).await.ok();
+    }
+    discord.delete_channel(room.office_id).await?;
+
+    state.send(BotResponse::OfficeSession(
+        room.session(state.clock.timestamp()),
+    ));
+
+    Ok(())
+}
+
 /// Implement the action trait
 #[async_trait]
 impl Action for OpenRoomAction<'_> {
     async fn can_execute(&self) -> bool {
-        let config_lock = get_config_lock(self.context).await;
-        let config = config_lock.read().await;
+        let config = self.state.config.read().await;
 
         match self.voice.channel_id {
             Some(id) => (config.room == id.0) && self.guild_id.is_some(),
@@ -73,7 +151,7 @@ impl Action for OpenRoomAction<'_> {
     }
 
     async fn execute(&self) {
-        let rooms_lock = get_rooms_lock(self.context).await;
+        let rooms_lock = &self.state.rooms;
         let rooms = rooms_lock.read().await;
 
         if let Some(guild_id) = self.guild_id {
@@ -83,9 +161,18 @@ impl Action for OpenRoomAction<'_> {
                     if let Ok(room) = self.create_rooms(guild_id).await {
                         self.move_user(guild_id, room.office_id).await;
 
+                        let event = AuditEvent::new(
+                            room.discord_id,
+                            room.office_id,
+                            AuditAction::OfficeOpened,
+                            "",
+                        );
+                        log_event(self.discord, self.state, event).await;
+
                         drop(rooms); //We need to drop LockReadGuard before write a new value
                         let mut room_storage = rooms_lock.write().await;
                         room_storage.push(room);
+                        self.state.store.save_rooms(&room_storage);
                     }
                 }
             }
@@ -94,21 +181,40 @@ impl Action for OpenRoomAction<'_> {
 }
 
 /// Action to close teachers' rooms
+///
+/// The office isn't deleted right away, the teacher's departure is recorded
+/// and the sweeper deletes it once the teacher has been gone for too long
 pub(crate) struct CloseRoomAction<'a> {
-    context: &'a Context,
+    /// Shared with the sweep scheduled when the teacher disconnects
+    discord: &'a Arc<dyn Discord>,
+    state: &'a BotState,
     new: &'a VoiceState,
 }
 
 /// Implement utility functions for action
 impl<'a> CloseRoomAction<'a> {
-    pub(crate) fn new(context: &'a Context, new: &'a VoiceState) -> Self {
-        CloseRoomAction { context, new }
+    pub(crate) fn new(
+        discord: &'a Arc<dyn Discord>,
+        state: &'a BotState,
+        new: &'a VoiceState,
+    ) -> Self {
+        CloseRoomAction {
+            discord,
+            state,
+            new,
+        }
     }
 
-    async fn delete_rooms(&self, room: &Room) -> Result<(), serenity::Error> {
-        ChannelId(room.office_id).delete(self.context).await?;
+    /// Sweep the offices once the grace period is over
+    async fn schedule_sweep(&self) {
+        let grace_period = self.state.config.read().await.office.grace_period;
+        let discord = self.discord.clone();
+        let state = self.state.clone();
 
-        Ok(())
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(grace_period)).await;
+            sweeper::sweep(discord.as_ref(), &state).await;
+        });
     }
 }
 
@@ -116,29 +222,208 @@ impl<'a> CloseRoomAction<'a> {
 #[async_trait]
 impl Action for CloseRoomAction<'_> {
     async fn can_execute(&self) -> bool {
-        let lock = get_rooms_lock(self.context).await;
-        let room_storage = lock.read().await;
+        let room_storage = self.state.rooms.read().await;
 
-        let has_room = room_storage
+        // Scheduled offices stay open until the end of their office hours
+        room_storage
             .iter()
-            .any(|e| e.discord_id == self.new.user_id.0);
-
-        matches!(self.new.channel_id, None if has_room)
+            .any(|e| e.discord_id == self.new.user_id.0 && e.schedule.is_none())
     }
 
     async fn execute(&self) {
-        let lock = get_rooms_lock(self.context).await;
-        let room_storage = lock.read().await;
+        let mut room_storage = self.state.rooms.write().await;
 
-        if let Some(index) = room_storage
-            .iter()
-            .position(|e| e.discord_id == self.new.user_id.0)
+        let room = match room_storage
+            .iter_mut()
+            .find(|e| e.discord_id == self.new.user_id.0 && e.schedule.is_none())
         {
-            if self.delete_rooms(&room_storage[index]).await.is_ok() {
+            Some(room) => room,
+            None => return,
+        };
+
+        match self.new.channel_id {
+            Some(id) if id.0 == room.office_id => room.teacher_left_at = None,
+            channel => {
+                room.teacher_left_at
+                    .get_or_insert_with(|| self.state.clock.timestamp());
                 drop(room_storage);
-                let mut room_storage = lock.write().await;
-                room_storage.remove(index);
+
+                if channel.is_none() {
+                    self.schedule_sweep().await;
+                }
             }
         }
     }
 }
+
+/// Action to record the students visiting the offices
+pub(crate) struct VisitAction<'a> {
+    state: &'a BotState,
+    new: &'a VoiceState,
+}
+
+impl<'a> VisitAction<'a> {
+    pub(crate) fn new(state: &'a BotState, new: &'a VoiceState) -> Self {
+        VisitAction { state, new }
+    }
+}
+
+/// Implement the action trait
+#[async_trait]
+impl Action for VisitAction<'_> {
+    async fn can_execute(&self) -> bool {
+        !self.state.rooms.read().await.is_empty()
+    }
+
+    async fn execute(&self) {
+        let mut room_storage = self.state.rooms.write().await;
+        let channel = self.new.channel_id.map(|c| c.0);
+        let now = self.state.clock.timestamp();
+
+        for room in room_storage.iter_mut() {
+            room.track(self.new.user_id.0, channel, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CloseRoomAction, OpenRoomAction, VisitAction};
+    use crate::{
+        actions::action::{schedule_action, Action},
+        discord::{
+            fake::{voice, Call, FakeGuild},
+            Discord,
+        },
+        state::fake,
+    };
+    use serenity::model::id::GuildId;
+    use shared_lib::socket::message::BotResponse;
+    use std::sync::Arc;
+
+    const GUILD: Option<GuildId> = Some(GuildId(1));
+
+    #[actix_rt::test]
+    async fn teachers_get_an_office() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::with_channels(&[10]);
+        guild.connect(5, Some(10));
+
+        let joined = voice(5, Some(10));
+        let action = OpenRoomAction::new(&guild, &fakes.state, &GUILD, &joined);
+        assert!(action.can_execute().await);
+        action.execute().await;
+
+        assert_eq!(fakes.state.rooms.read().await[0].office_id, 1001);
+        assert_eq!(guild.guild().voice[&5], 1001);
+        assert_eq!(fakes.store.0.lock().unwrap().len(), 1);
+        assert!(matches!(
+            fakes.sent().await.as_slice(),
+            [BotResponse::Audit(_)]
+        ));
+
+        // Coming back to the lobby moves the teacher to the same office
+        guild.connect(5, Some(10));
+        schedule_action(OpenRoomAction::new(&guild, &fakes.state, &GUILD, &joined)).await;
+        assert_eq!(fakes.state.rooms.read().await.len(), 1);
+        assert_eq!(guild.guild().voice[&5], 1001);
+        assert_eq!(guild.guild().channels.len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn other_channels_open_nothing() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::with_channels(&[10, 12]);
+
+        let joined = voice(5, Some(12));
+        assert!(
+            !OpenRoomAction::new(&guild, &fakes.state, &GUILD, &joined)
+                .can_execute()
+                .await
+        );
+        assert!(
+            !OpenRoomAction::new(&guild, &fakes.state, &None, &voice(5, Some(10)))
+                .can_execute()
+                .await
+        );
+        assert!(guild.calls().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn teacher_departure_is_recorded() {
+        let fakes = fake::state(fake::config(), vec![fake::room(5, 30)]);
+        let discord: Arc<dyn Discord> = Arc::new(FakeGuild::with_channels(&[30, 12]));
+
+        let student = voice(6, Some(12));
+        assert!(
+            !CloseRoomAction::new(&discord, &fakes.state, &student)
+                .can_execute()
+                .await
+        );
+
+        let away = voice(5, Some(12));
+        schedule_action(CloseRoomAction::new(&discord, &fakes.state, &away)).await;
+        assert_eq!(
+            fakes.state.rooms.read().await[0].teacher_left_at,
+            Some(1_600_000_000)
+        );
+
+        let back = voice(5, Some(30));
+        schedule_action(CloseRoomAction::new(&discord, &fakes.state, &back)).await;
+        assert_eq!(fakes.state.rooms.read().await[0].teacher_left_at, None);
+    }
+
+    #[actix_rt::test]
+    async fn scheduled_offices_stay_open() {
+        let mut room = fake::room(5, 30);
+        room.schedule = Some(1);
+        let fakes = fake::state(fake::config(), vec![room]);
+        let discord: Arc<dyn Discord> = Arc::new(FakeGuild::default());
+
+        let gone = voice(5, None);
+        assert!(
+            !CloseRoomAction::new(&discord, &fakes.state, &gone)
+                .can_execute()
+                .await
+        );
+    }
+
+    #[actix_rt::test]
+    async fn visits_are_tracked() {
+        let fakes = fake::state(fake::config(), vec![fake::room(1, 30)]);
+        let state = &fakes.state;
+
+        schedule_action(VisitAction::new(state, &voice(5, Some(30)))).await;
+        schedule_action(VisitAction::new(state, &voice(1, Some(30)))).await;
+        fakes.clock.advance(300);
+        schedule_action(VisitAction::new(state, &voice(5, None))).await;
+
+        let rooms = state.rooms.read().await;
+        let session = rooms[0].session(state.clock.timestamp());
+        assert_eq!(session.visits.len(), 1);
+        assert_eq!(session.visits[0].student, 5);
+        assert_eq!(session.visits[0].duration(), 300);
+        assert_eq!(session.visits[0].waited, None);
+    }
+
+    #[actix_rt::test]
+    async fn deleted_offices_report_their_session() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::with_channels(&[30, 31]);
+        let mut room = fake::room(5, 30);
+        room.waiting_id = 31;
+
+        super::delete_office(&guild, &fakes.state, &room)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            guild.calls(),
+            vec![Call::DeleteChannel(31), Call::DeleteChannel(30)]
+        );
+        assert!(matches!(
+            fakes.sent().await.as_slice(),
+            [BotResponse::OfficeSession(session)] if session.teacher == 5
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{actions::action::Action, discord::Discord, state::BotState};
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDate};
+use serenity::model::channel::Message;
+use shared_lib::{office::OfficeStats, socket::message::BotResponse};
+
+const PREFIX: &str = "!stats";
+const USAGE: &str =
+    "Usage: `!stats [from YYYY-MM-DD] [to YYYY-MM-DD]`, the last 30 days by default";
+/// Days covered when no start date is given
+const DEFAULT_DAYS: i64 = 30;
+
+/// Action to let teachers see the stats of their office sessions
+pub(crate) struct StatsAction<'a> {
+    discord: &'a dyn Discord,
+    state: &'a BotState,
+    message: &'a Message,
+}
+
+/// Implement utility functions for action
+impl<'a> StatsAction<'a> {
+    pub(crate) fn new(discord: &'a dyn Discord, state: &'a BotState, message: &'a Message) -> Self {
+        StatsAction {
+            discord,
+            state,
+            message,
+        }
+    }
+
+    /// Range of unix timestamps covered by the command, both days are included
+    fn range(&self, args: &[&str]) -> Result<(i64, i64), String> {
+        let parse = |s: &str| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| format!("invalid date: {}", s))
+        };
+        let today = self.state.clock.now().date_naive();
+
+        let (from, to) = match args {
+            [] => (today - Duration::days(DEFAULT_DAYS), today),
+            [from] => (parse(from)?, today),
+            [from, to] => (parse(from)?, parse(to)?),
+            _ => return Err(USAGE.to_string()),
+        };
+        let start = |day: NaiveDate| day.and_time(Default::default()).and_utc().timestamp();
+
+        Ok((start(from), start(to + Duration::days(1)) - 1))
+    }
+}
+
+/// Human readable duration
+fn format_duration(seconds: i64) -> String {
+    match seconds / 60 {
+        minutes if minutes >= 60 => format!("{}h{:02}", minutes / 60, minutes % 60),
+        minutes => format!("{}min", minutes),
+    }
+}
+
+/// Message answering the command of `teacher`
+pub(crate) fn format_stats(teacher: u64, stats: &OfficeStats) -> String {
+    format!(
+        "<@{}>: {} sessions open for {}, {} visits by {} students, {} from the waiting room\n\
+         Average wait {}, average visit {}",
+        teacher,
+        stats.sessions,
+        format_duration(stats.open_time),
+        stats.visits,
+        stats.students,
+        stats.admitted,
+        format_duration(stats.average_wait),
+        format_duration(stats.average_visit)
+    )
+}
+
+/// Implement the action trait
+#[async_trait]
+impl Action for StatsAction<'_> {
+    async fn can_execute(&self) -> bool {
+        if self.message.content.split_whitespace().next() != Some(PREFIX) {
+            return false;
+        }
+
+        let config = self.state.config.read().await;
+
+        match &self.message.member {
+            Some(member) => config.is_teacher(&member.roles),
+            None => false,
+        }
+    }
+
+    async fn execute(&self) {
+        let args: Vec<&str> = self.message.content.split_whitespace().skip(1).collect();
+
+        match self.range(&args) {
+            // The backend answers with `ServerRequest::OfficeStats`
+            Ok((from, to)) => self.state.send(BotResponse::GetOfficeStats {
+                teacher: self.message.author.id.0,
+                from,
+                to,
+                channel: self.message.channel_id.0,
+            }),
+            Err(e) => {
+                self.discord
+                    .reply(self.message.channel_id.0, self.message.id.0, &e)
+                    .await
+                    .ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatsAction;
+    use crate::{
+        actions::action::{schedule_action, Action},
+        discord::fake::{message, FakeGuild},
+        state::fake,
+    };
+    use shared_lib::socket::message::BotResponse;
+
+    const TEACHER: u64 = 2;
+
+    #[actix_rt::test]
+    async fn students_cant_see_stats() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        let msg = message(5, &[], "!stats");
+        assert!(
+            !StatsAction::new(&guild, &fakes.state, &msg)
+                .can_execute()
+                .await
+        );
+    }
+
+    #[actix_rt::test]
+    async fn stats_cover_whole_days() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        // The fake clock is on 2020-09-13
+        for content in ["!stats", "!stats 2020-09-01 2020-09-02", "!stats yesterday"] {
+            let msg = message(5, &[TEACHER], content);
+            schedule_action(StatsAction::new(&guild, &fakes.state, &msg)).await;
+        }
+
+        let ranges: Vec<(i64, i64)> = fakes
+            .sent()
+            .await
+            .into_iter()
+            .filter_map(|msg| match msg {
+                BotResponse::GetOfficeStats {
+                    teacher: 5,
+                    from,
+                    to,
+                    channel: 50,
+                } => Some((from, to)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (1_597_363_200, 1_600_041_599),
+                (1_598_918_400, 1_599_091_199)
+            ]
+        );
+        assert_eq!(
+            guild.messages(),
+            vec![(50, "invalid date: yesterday".to_string())]
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{actions::action::Action, discord::Discord, state::BotState};
+use async_trait::async_trait;
+use serenity::model::channel::Message;
+use shared_lib::socket::health::{GetLinkStatus, LinkStatus};
+
+const PREFIX: &str = "!status";
+const LINK_DOWN: &str = "The link to the backend is down";
+
+/// Action to let teachers check the link between the bot and the backend
+pub(crate) struct StatusAction<'a> {
+    discord: &'a dyn Discord,
+    state: &'a BotState,
+    message: &'a Message,
+}
+
+/// Implement utility functions for action
+impl<'a> StatusAction<'a> {
+    pub(crate) fn new(discord: &'a dyn Discord, state: &'a BotState, message: &'a Message) -> Self {
+        StatusAction {
+            discord,
+            state,
+            message,
+        }
+    }
+}
+
+/// Message describing the link, timestamps are rendered by discord
+pub(crate) fn format_status(status: &LinkStatus) -> String {
+    let rtt = match status.rtt {
+        Some(rtt) => format!("{}ms", rtt),
+        None => "unknown".to_string(),
+    };
+
+    format!(
+        "Backend connected <t:{}:R>, last heartbeat <t:{}:R>\n\
+         Round trip {}, {} messages sent, {} received",
+        status.connected_at, status.last_heartbeat, rtt, status.sent, status.received
+    )
+}
+
+/// Implement the action trait
+#[async_trait]
+impl Action for StatusAction<'_> {
+    async fn can_execute(&self) -> bool {
+        if self.message.content.split_whitespace().next() != Some(PREFIX) {
+            return false;
+        }
+
+        let config = self.state.config.read().await;
+
+        match &self.message.member {
+            Some(member) => config.is_teacher(&member.roles),
+            None => false,
+        }
+    }
+
+    async fn execute(&self) {
+        let content = match self.state.link.send(GetLinkStatus).await {
+            Ok(status) => format_status(&status),
+            Err(_) => LINK_DOWN.to_string(),
+        };
+
+        self.discord
+            .reply(self.message.channel_id.0, self.message.id.0, &content)
+            .await
+            .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatusAction;
+    use crate::{
+        actions::action::{schedule_action, Action},
+        discord::fake::{message, FakeGuild},
+        state::fake,
+    };
+
+    const TEACHER: u64 = 2;
+
+    #[actix_rt::test]
+    async fn teachers_see_the_link() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        let msg = message(5, &[], "!status");
+        assert!(
+            !StatusAction::new(&guild, &fakes.state, &msg)
+                .can_execute()
+                .await
+        );
+
+        let msg = message(5, &[TEACHER], "!status");
+        schedule_action(StatusAction::new(&guild, &fakes.state, &msg)).await;
+
+        assert_eq!(
+            guild.messages(),
+            vec![(
+                50,
+                "Backend connected <t:1599996400:R>, last heartbeat <t:1599999999:R>\n\
+                 Round trip 42ms, 5 messages sent, 3 received"
+                    .to_string()
+            )]
+        );
+    }
+}
@@ -1,26 +1,34 @@
-use crate::{actions::action::Action, get_config_lock, models::SubjectsMessage};
+use crate::{
+    actions::action::Action, audit::log_event, discord::Discord, models::SubjectsMessage,
+    state::BotState,
+};
 use async_trait::async_trait;
-use serenity::{
-    client::Context,
-    model::{
-        channel::{PermissionOverwrite, PermissionOverwriteType, Reaction},
-        id::{ChannelId, UserId},
-        Permissions,
-    },
+use serenity::model::{
+    channel::{PermissionOverwrite, PermissionOverwriteType, Reaction},
+    id::UserId,
+    Permissions,
 };
+use shared_lib::audit::{AuditAction, AuditEvent};
 
 /// Action to open and close subject's channel
 pub(crate) struct SubjectAction<'a> {
-    context: &'a Context,
+    discord: &'a dyn Discord,
+    state: &'a BotState,
     reaction: &'a Reaction,
     open: bool,
 }
 
 /// Implement utility functions for action
 impl<'a> SubjectAction<'a> {
-    pub(crate) fn new(context: &'a Context, reaction: &'a Reaction, open: bool) -> Self {
+    pub(crate) fn new(
+        discord: &'a dyn Discord,
+        state: &'a BotState,
+        reaction: &'a Reaction,
+        open: bool,
+    ) -> Self {
         SubjectAction {
-            context,
+            discord,
+            state,
             reaction,
             open,
         }
@@ -35,8 +43,8 @@ impl<'a> SubjectAction<'a> {
     }
 
     async fn close_channel(&self, channel: u64, user: UserId) {
-        ChannelId(channel)
-            .delete_permission(self.context, PermissionOverwriteType::Member(user))
+        self.discord
+            .delete_permission(channel, PermissionOverwriteType::Member(user))
             .await
             .ok();
     }
@@ -49,8 +57,8 @@ impl<'a> SubjectAction<'a> {
             kind: PermissionOverwriteType::Member(user),
         };
 
-        ChannelId(channel)
-            .create_permission(self.context, &overwrite)
+        self.discord
+            .create_permission(channel, overwrite)
             .await
             .ok();
     }
@@ -60,8 +68,7 @@ impl<'a> SubjectAction<'a> {
 #[async_trait]
 impl Action for SubjectAction<'_> {
     async fn can_execute(&self) -> bool {
-        let config_lock = get_config_lock(self.context).await;
-        let config = config_lock.read().await;
+        let config = self.state.config.read().await;
 
         config
             .subjects
@@ -70,8 +77,7 @@ impl Action for SubjectAction<'_> {
     }
 
     async fn execute(&self) {
-        let config_lock = get_config_lock(self.context).await;
-        let config = config_lock.read().await;
+        let config = self.state.config.read().await;
 
         if let Some(s) = config
             .subjects
@@ -79,11 +85,114 @@ impl Action for SubjectAction<'_> {
             .find(|e| e.id == self.reaction.message_id.0)
         {
             if let Some((channel, user)) = self.get_channel_and_user(s).await {
-                match self.open {
-                    true => self.open_channel(channel, user).await,
-                    false => self.close_channel(channel, user).await,
+                if self.open && !s.groups.is_empty() {
+                    let groups = self.state.groups.read().await;
+                    if !groups.get(&user.0).is_some_and(|groups| s.open_to(groups)) {
+                        tracing::info!("{} isn't in the groups of subjects {}", user, s.id);
+                        return;
+                    }
                 }
+
+                let action = match self.open {
+                    true => {
+                        self.open_channel(channel, user).await;
+                        AuditAction::SubjectOpened
+                    }
+                    false => {
+                        self.close_channel(channel, user).await;
+                        AuditAction::SubjectClosed
+                    }
+                };
+
+                let emoji = self.reaction.emoji.as_data();
+                drop(config);
+                log_event(
+                    self.discord,
+                    self.state,
+                    AuditEvent::new(user.0, channel, action, emoji),
+                )
+                .await;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::SubjectAction;
+    use crate::{
+        actions::action::{schedule_action, Action},
+        discord::fake::{reaction, FakeGuild},
+        state::fake,
+    };
+    use serenity::model::{channel::PermissionOverwriteType, id::UserId, Permissions};
+    use shared_lib::socket::message::BotResponse;
+
+    #[actix_rt::test]
+    async fn reactions_toggle_subjects() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::with_channels(&[21]);
+        let added = reaction(7, 20, "💛");
+
+        schedule_action(SubjectAction::new(&guild, &fakes.state, &added, true)).await;
+        {
+            let guild = guild.guild();
+            let overwrites = &guild.channels[&21].overwrites;
+            assert_eq!(overwrites.len(), 1);
+            assert_eq!(
+                overwrites[0].kind,
+                PermissionOverwriteType::Member(UserId(7))
+            );
+            assert_eq!(overwrites[0].allow, Permissions::READ_MESSAGES);
+        }
+
+        schedule_action(SubjectAction::new(&guild, &fakes.state, &added, false)).await;
+        assert!(guild.guild().channels[&21].overwrites.is_empty());
+        assert!(matches!(
+            fakes.sent().await.as_slice(),
+            [BotResponse::Audit(_), BotResponse::Audit(_)]
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn other_reactions_are_ignored() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::with_channels(&[21]);
+
+        let elsewhere = reaction(7, 99, "💛");
+        assert!(
+            !SubjectAction::new(&guild, &fakes.state, &elsewhere, true)
+                .can_execute()
+                .await
+        );
+
+        // Unknown emojis on the subjects message don't open anything
+        let unknown = reaction(7, 20, "🍕");
+        schedule_action(SubjectAction::new(&guild, &fakes.state, &unknown, true)).await;
+        assert!(guild.calls().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn restricted_subjects_need_the_group() {
+        let mut config = fake::config();
+        config.subjects[0].groups = vec!["A1".to_string()];
+        let fakes = fake::state(config, Vec::new());
+        let guild = FakeGuild::with_channels(&[21]);
+        fakes
+            .state
+            .set_groups(8, Some(vec!["A1".to_string()]))
+            .await;
+
+        for user in [7, 8] {
+            let added = reaction(user, 20, "💛");
+            schedule_action(SubjectAction::new(&guild, &fakes.state, &added, true)).await;
+        }
+
+        let overwrites = &guild.guild().channels[&21].overwrites;
+        assert_eq!(overwrites.len(), 1);
+        assert_eq!(
+            overwrites[0].kind,
+            PermissionOverwriteType::Member(UserId(8))
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{actions::action::Action, discord::Discord, members::restrict, state::BotState};
+use async_trait::async_trait;
+use serenity::model::{guild::Member, id::GuildId};
+
+/// Action to greet new members and send them their verification link
+pub(crate) struct WelcomeAction<'a> {
+    discord: &'a dyn Discord,
+    state: &'a BotState,
+    guild_id: GuildId,
+    member: &'a Member,
+}
+
+/// Implement utility functions for action
+impl<'a> WelcomeAction<'a> {
+    pub(crate) fn new(
+        discord: &'a dyn Discord,
+        state: &'a BotState,
+        guild_id: GuildId,
+        member: &'a Member,
+    ) -> Self {
+        WelcomeAction {
+            discord,
+            state,
+            guild_id,
+            member,
+        }
+    }
+}
+
+/// Implement the action trait
+#[async_trait]
+impl Action for WelcomeAction<'_> {
+    async fn can_execute(&self) -> bool {
+        let config = self.state.config.read().await;
+
+        config.welcome.is_some() && config.guild == self.guild_id.0 && !self.member.user.bot
+    }
+
+    async fn execute(&self) {
+        let config = self.state.config.read().await;
+        let welcome = match &config.welcome {
+            Some(welcome) => welcome,
+            None => return,
+        };
+        let user = self.member.user.id.0;
+
+        // Only the members restricted here are reminded and kicked, not the ones restricted
+        // later by an unlink
+        match restrict(self.discord, &config, user).await {
+            Ok(()) => {
+                let now = self.state.clock.timestamp();
+                self.state.record_welcome(user, now).await;
+            }
+            Err(e) => tracing::error!("Couldn't restrict {}: {:?}", user, e),
+        }
+
+        let dm = format!(
+            "Welcome! Link your school account to access the server: {}\nThis link is yours and expires in {} hours.",
+            welcome.link(user, self.state.clock.timestamp()),
+            welcome.link_validity / 3600
+        );
+        // The personal link never goes to the lobby, anyone could use it
+        let greeting = match self.discord.direct_message(user, &dm).await {
+            Ok(()) => format!(
+                "Welcome <@{}>! Check your private messages to verify your account",
+                user
+            ),
+            Err(_) => format!(
+                "Welcome <@{}>! Verify your account at {}",
+                user, welcome.login_url
+            ),
+        };
+
+        self.discord.say(welcome.channel, &greeting).await.ok();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::WelcomeAction;
+    use crate::{
+        actions::action::{schedule_action, Action},
+        discord::fake::{member, Call, FakeGuild},
+        models::{Config, WelcomeConfig},
+        state::fake,
+    };
+    use serenity::model::id::GuildId;
+    use shared_lib::link::LinkSigner;
+
+    pub(crate) const UNVERIFIED: u64 = 6;
+
+    pub(crate) fn config() -> Config {
+        let mut config = fake::config();
+        config.roles.insert("unverified".to_string(), UNVERIFIED);
+        config.welcome = Some(WelcomeConfig {
+            channel: 40,
+            login_url: "https://leo/login".to_string(),
+            link_validity: 3600,
+            reminder: Some(600),
+            kick_after: Some(3600),
+            secret: "secret".to_string(),
+        });
+
+        config
+    }
+
+    #[actix_rt::test]
+    async fn new_members_get_their_link() {
+        let fakes = fake::state(config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        schedule_action(WelcomeAction::new(
+            &guild,
+            &fakes.state,
+            GuildId(1),
+            &member(7),
+        ))
+        .await;
+
+        let calls = guild.calls();
+        assert_eq!(
+            calls[0],
+            Call::AddRole {
+                user: 7,
+                role: UNVERIFIED
+            }
+        );
+        let token = match &calls[1] {
+            Call::DirectMessage { user: 7, content } => content
+                .split_whitespace()
+                .find_map(|w| w.strip_prefix("https://leo/login?token="))
+                .unwrap()
+                .to_string(),
+            call => panic!("unexpected call {:?}", call),
+        };
+        assert_eq!(
+            LinkSigner::new("secret").verify(&token, 1_600_003_600),
+            Ok(7)
+        );
+        assert_eq!(fakes.store.2.lock().unwrap().get(&7), Some(&1_600_000_000));
+        assert_eq!(
+            guild.messages(),
+            vec![(
+                40,
+                "Welcome <@7>! Check your private messages to verify your account".to_string()
+            )]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn closed_dms_get_the_public_login() {
+        let fakes = fake::state(config(), Vec::new());
+        let guild = FakeGuild::default();
+        guild.guild().closed_dms.insert(7);
+
+        schedule_action(WelcomeAction::new(
+            &guild,
+            &fakes.state,
+            GuildId(1),
+            &member(7),
+        ))
+        .await;
+
+        assert_eq!(
+            guild.messages(),
+            vec![(
+                40,
+                "Welcome <@7>! Verify your account at https://leo/login".to_string()
+            )]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn disabled_without_config() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        assert!(
+            !WelcomeAction::new(&guild, &fakes.state, GuildId(1), &member(7))
+                .can_execute()
+                .await
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{actions::action::Action, discord::Discord, state::BotState};
+use async_trait::async_trait;
+use serenity::{
+    builder::CreateApplicationCommand,
+    model::{
+        id::RoleId,
+        interactions::application_command::{
+            ApplicationCommandInteraction, ApplicationCommandOptionType,
+        },
+    },
+};
+use shared_lib::{socket::message::BotResponse, user::LinkedUser};
+
+const NAME: &str = "whois";
+const TEACHERS_ONLY: &str = "Only teachers can look up members";
+
+/// `/whois` run by a member, with what the action needs from the interaction
+pub(crate) struct WhoisCommand {
+    pub(crate) interaction: u64,
+    pub(crate) token: String,
+    pub(crate) name: String,
+    /// Roles of the member who ran the command
+    pub(crate) roles: Vec<RoleId>,
+    /// Member to look up
+    pub(crate) target: Option<u64>,
+}
+
+impl From<&ApplicationCommandInteraction> for WhoisCommand {
+    fn from(command: &ApplicationCommandInteraction) -> Self {
+        WhoisCommand {
+            interaction: command.id.0,
+            token: command.token.clone(),
+            name: command.data.name.clone(),
+            roles: command
+                .member
+                .as_ref()
+                .map(|m| m.roles.clone())
+                .unwrap_or_default(),
+            target: command
+                .data
+                .options
+                .first()
+                .and_then(|o| o.value.as_ref()?.as_str()?.parse().ok()),
+        }
+    }
+}
+
+/// Declare `/whois` to Discord
+pub(crate) fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name(NAME)
+        .description("Show the school account linked to a member")
+        .create_option(|option| {
+            option
+                .name("member")
+                .description("Member to look up")
+                .kind(ApplicationCommandOptionType::User)
+                .required(true)
+        })
+}
+
+/// Action to let teachers look up the school account of members
+///
+/// The answers are only shown to the teacher, they carry the name and email of the member
+pub(crate) struct WhoisAction<'a> {
+    discord: &'a dyn Discord,
+    state: &'a BotState,
+    command: &'a WhoisCommand,
+}
+
+/// Implement utility functions for action
+impl<'a> WhoisAction<'a> {
+    pub(crate) fn new(
+        discord: &'a dyn Discord,
+        state: &'a BotState,
+        command: &'a WhoisCommand,
+    ) -> Self {
+        WhoisAction {
+            discord,
+            state,
+            command,
+        }
+    }
+}
+
+/// Message answering the lookup of `discord_id`
+pub(crate) fn format_whois(discord_id: u64, user: Option<&LinkedUser>) -> String {
+    match user {
+        Some(user) => user.to_string(),
+        None => format!("<@{}> hasn't linked a school account", discord_id),
+    }
+}
+
+/// Implement the action trait
+#[async_trait]
+impl Action for WhoisAction<'_> {
+    async fn can_execute(&self) -> bool {
+        self.command.name == NAME
+    }
+
+    async fn execute(&self) {
+        let command = self.command;
+        if let Err(e) = self
+            .discord
+            .defer_command(command.interaction, &command.token)
+            .await
+        {
+            tracing::error!("Couldn't acknowledge /whois: {:?}", e);
+            return;
+        }
+
+        let teacher = self.state.config.read().await.is_teacher(&command.roles);
+        match command.target {
+            // The backend answers with `ServerRequest::Whois`
+            Some(discord_id) if teacher => self.state.send(BotResponse::Whois {
+                discord_id,
+                token: command.token.clone(),
+            }),
+            _ => {
+                self.discord
+                    .answer_command(&command.token, TEACHERS_ONLY)
+                    .await
+                    .ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WhoisAction, WhoisCommand, TEACHERS_ONLY};
+    use crate::{
+        actions::action::schedule_action,
+        discord::fake::{Call, FakeGuild},
+        state::fake,
+    };
+    use serenity::model::id::RoleId;
+    use shared_lib::socket::message::BotResponse;
+
+    const TEACHER: u64 = 2;
+
+    fn whois(roles: &[u64], target: u64) -> WhoisCommand {
+        WhoisCommand {
+            interaction: 70,
+            token: "t1".to_string(),
+            name: "whois".to_string(),
+            roles: roles.iter().map(|r| RoleId(*r)).collect(),
+            target: Some(target),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn teachers_look_up_members() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        let command = whois(&[TEACHER], 8);
+        schedule_action(WhoisAction::new(&guild, &fakes.state, &command)).await;
+
+        assert!(matches!(
+            fakes.sent().await.as_slice(),
+            [BotResponse::Whois { discord_id: 8, token }] if token == "t1"
+        ));
+        assert_eq!(guild.calls(), vec![Call::DeferCommand(70)]);
+    }
+
+    #[actix_rt::test]
+    async fn students_are_answered_privately() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        let command = whois(&[3], 8);
+        schedule_action(WhoisAction::new(&guild, &fakes.state, &command)).await;
+
+        assert!(fakes.sent().await.is_empty());
+        assert_eq!(
+            guild.calls(),
+            vec![
+                Call::DeferCommand(70),
+                Call::AnswerCommand {
+                    token: "t1".to_string(),
+                    content: TEACHERS_ONLY.to_string(),
+                },
+            ]
+        );
+    }
+}
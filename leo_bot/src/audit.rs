@@ -0,0 +1,14 @@
+use shared_lib::{audit::AuditEvent, socket::message::BotResponse};
+
+use crate::{discord::Discord, state::BotState};
+
+/// Ship an audit event to the backend and mirror it in the log channel
+pub(crate) async fn log_event(discord: &dyn Discord, state: &BotState, event: AuditEvent) {
+    let log_channel = state.config.read().await.log_channel;
+
+    if let Some(channel) = log_channel {
+        discord.say(channel, &event.to_string()).await.ok();
+    }
+
+    state.send(BotResponse::Audit(event));
+}
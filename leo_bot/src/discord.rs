@@ -0,0 +1,729 @@
+use async_trait::async_trait;
+use serenity::{
+    cache::Cache,
+    client::Context,
+    http::Http,
+    model::{
+        channel::{ChannelType, PermissionOverwrite, PermissionOverwriteType},
+        id::{ChannelId, GuildId, MessageId, UserId},
+        interactions::{InteractionApplicationCommandCallbackDataFlags, InteractionResponseType},
+    },
+    CacheAndHttp, Error,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::metrics;
+
+/// Member of the guild as seen by the bot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GuildMember {
+    pub(crate) id: u64,
+    pub(crate) roles: Vec<u64>,
+    /// Unix timestamp of when they joined the guild
+    pub(crate) joined_at: Option<i64>,
+    pub(crate) bot: bool,
+}
+
+/// Operations the bot performs on the guild
+///
+/// The actions go through this trait instead of calling serenity, so they can
+/// run against [`fake::FakeGuild`] in the tests
+#[async_trait]
+pub(crate) trait Discord: Send + Sync {
+    /// Create a voice channel in `category`, returns its id
+    async fn create_voice_channel(
+        &self,
+        guild: u64,
+        name: &str,
+        category: u64,
+        overwrites: Vec<PermissionOverwrite>,
+    ) -> Result<u64, Error>;
+
+    /// Replace the overwrites and the user limit (0 for none) of a voice channel
+    async fn edit_voice_channel(
+        &self,
+        channel: u64,
+        overwrites: Vec<PermissionOverwrite>,
+        user_limit: u64,
+    ) -> Result<(), Error>;
+
+    async fn delete_channel(&self, channel: u64) -> Result<(), Error>;
+
+    /// Move a member connected to a voice channel to `channel`
+    async fn move_member(&self, guild: u64, user: u64, channel: u64) -> Result<(), Error>;
+
+    async fn create_permission(
+        &self,
+        channel: u64,
+        overwrite: PermissionOverwrite,
+    ) -> Result<(), Error>;
+
+    async fn delete_permission(
+        &self,
+        channel: u64,
+        kind: PermissionOverwriteType,
+    ) -> Result<(), Error>;
+
+    async fn add_member_role(&self, guild: u64, user: u64, role: u64) -> Result<(), Error>;
+
+    async fn remove_member_role(&self, guild: u64, user: u64, role: u64) -> Result<(), Error>;
+
+    async fn say(&self, channel: u64, content: &str) -> Result<(), Error>;
+
+    /// Answer `message` with an inline reply
+    async fn reply(&self, channel: u64, message: u64, content: &str) -> Result<(), Error>;
+
+    /// Send a private message, fails when the member doesn't accept them
+    async fn direct_message(&self, user: u64, content: &str) -> Result<(), Error>;
+
+    /// Acknowledge a slash command, its answer will only be shown to the member who ran it
+    async fn defer_command(&self, interaction: u64, token: &str) -> Result<(), Error>;
+
+    /// Answer a slash command acknowledged with `defer_command`
+    async fn answer_command(&self, token: &str, content: &str) -> Result<(), Error>;
+
+    async fn kick(&self, guild: u64, user: u64, reason: &str) -> Result<(), Error>;
+
+    /// Every member of the guild, `None` when the guild isn't known yet
+    async fn members(&self, guild: u64) -> Option<Vec<GuildMember>>;
+
+    /// Voice channel of every connected member, `None` when the guild isn't known yet
+    async fn voice_states(&self, guild: u64) -> Option<HashMap<u64, u64>>;
+
+    /// Every channel of the guild, `None` when the guild isn't known yet
+    async fn channels(&self, guild: u64) -> Option<HashSet<u64>>;
+}
+
+/// Implementation talking to Discord through serenity
+pub(crate) struct SerenityDiscord {
+    http: Arc<Http>,
+    cache: Arc<Cache>,
+}
+
+impl From<&Context> for SerenityDiscord {
+    fn from(context: &Context) -> Self {
+        SerenityDiscord {
+            http: context.http.clone(),
+            cache: context.cache.clone(),
+        }
+    }
+}
+
+impl From<&CacheAndHttp> for SerenityDiscord {
+    fn from(cache_and_http: &CacheAndHttp) -> Self {
+        SerenityDiscord {
+            http: cache_and_http.http.clone(),
+            cache: cache_and_http.cache.clone(),
+        }
+    }
+}
+
+/// Count the failure of the Discord call `operation` in the metrics
+fn counted<T>(operation: &str, result: Result<T, Error>) -> Result<T, Error> {
+    if result.is_err() {
+        metrics::discord_error(operation);
+    }
+
+    result
+}
+
+#[async_trait]
+impl Discord for SerenityDiscord {
+    async fn create_voice_channel(
+        &self,
+        guild: u64,
+        name: &str,
+        category: u64,
+        overwrites: Vec<PermissionOverwrite>,
+    ) -> Result<u64, Error> {
+        let channel = GuildId(guild)
+            .create_channel(&self.http, |c| {
+                c.name(name)
+                    .category(category)
+                    .permissions(overwrites)
+                    .kind(ChannelType::Voice)
+            })
+            .await;
+
+        counted("create_voice_channel", channel.map(|c| c.id.0))
+    }
+
+    async fn edit_voice_channel(
+        &self,
+        channel: u64,
+        overwrites: Vec<PermissionOverwrite>,
+        user_limit: u64,
+    ) -> Result<(), Error> {
+        let edited = ChannelId(channel)
+            .edit(&self.http, |c| {
+                c.permissions(overwrites).user_limit(user_limit)
+            })
+            .await;
+
+        counted("edit_voice_channel", edited.map(drop))
+    }
+
+    async fn delete_channel(&self, channel: u64) -> Result<(), Error> {
+        let deleted = ChannelId(channel).delete(&self.http).await;
+
+        counted("delete_channel", deleted.map(drop))
+    }
+
+    async fn move_member(&self, guild: u64, user: u64, channel: u64) -> Result<(), Error> {
+        let moved = GuildId(guild)
+            .move_member(&self.http, UserId(user), channel)
+            .await;
+
+        counted("move_member", moved.map(drop))
+    }
+
+    async fn create_permission(
+        &self,
+        channel: u64,
+        overwrite: PermissionOverwrite,
+    ) -> Result<(), Error> {
+        let created = ChannelId(channel)
+            .create_permission(&self.http, &overwrite)
+            .await;
+
+        counted("create_permission", created)
+    }
+
+    async fn delete_permission(
+        &self,
+        channel: u64,
+        kind: PermissionOverwriteType,
+    ) -> Result<(), Error> {
+        let deleted = ChannelId(channel).delete_permission(&self.http, kind).await;
+
+        counted("delete_permission", deleted)
+    }
+
+    async fn add_member_role(&self, guild: u64, user: u64, role: u64) -> Result<(), Error> {
+        let added = self.http.add_member_role(guild, user, role).await;
+
+        counted("add_member_role", added)
+    }
+
+    async fn remove_member_role(&self, guild: u64, user: u64, role: u64) -> Result<(), Error> {
+        let removed = self.http.remove_member_role(guild, user, role).await;
+
+        counted("remove_member_role", removed)
+    }
+
+    async fn say(&self, channel: u64, content: &str) -> Result<(), Error> {
+        let said = ChannelId(channel).say(&self.http, content).await;
+
+        counted("say", said.map(drop))
+    }
+
+    async fn reply(&self, channel: u64, message: u64, content: &str) -> Result<(), Error> {
+        let replied = ChannelId(channel)
+            .send_message(&self.http, |m| {
+                m.content(content)
+                    .reference_message((ChannelId(channel), MessageId(message)))
+            })
+            .await;
+
+        counted("reply", replied.map(drop))
+    }
+
+    async fn direct_message(&self, user: u64, content: &str) -> Result<(), Error> {
+        let sent = match UserId(user).create_dm_channel(self.http.as_ref()).await {
+            Ok(channel) => channel.say(&self.http, content).await.map(drop),
+            Err(e) => Err(e),
+        };
+
+        counted("direct_message", sent)
+    }
+
+    async fn defer_command(&self, interaction: u64, token: &str) -> Result<(), Error> {
+        let response = serde_json::json!({
+            "type": InteractionResponseType::DeferredChannelMessageWithSource as u8,
+            "data": {"flags": InteractionApplicationCommandCallbackDataFlags::EPHEMERAL.bits()},
+        });
+        let deferred = self
+            .http
+            .create_interaction_response(interaction, token, &response)
+            .await;
+
+        counted("defer_command", deferred)
+    }
+
+    async fn answer_command(&self, token: &str, content: &str) -> Result<(), Error> {
+        let answered = self
+            .http
+            .edit_original_interaction_response(token, &serde_json::json!({ "content": content }))
+            .await;
+
+        counted("answer_command", answered.map(drop))
+    }
+
+    async fn kick(&self, guild: u64, user: u64, reason: &str) -> Result<(), Error> {
+        let kicked = GuildId(guild)
+            .kick_with_reason(&self.http, UserId(user), reason)
+            .await;
+
+        counted("kick", kicked)
+    }
+
+    async fn members(&self, guild: u64) -> Option<Vec<GuildMember>> {
+        self.cache
+            .guild_field(GuildId(guild), |g| {
+                g.members
+                    .values()
+                    .map(|m| GuildMember {
+                        id: m.user.id.0,
+                        roles: m.roles.iter().map(|r| r.0).collect(),
+                        joined_at: m.joined_at.map(|t| t.timestamp()),
+                        bot: m.user.bot,
+                    })
+                    .collect()
+            })
+            .await
+    }
+
+    async fn voice_states(&self, guild: u64) -> Option<HashMap<u64, u64>> {
+        self.cache
+            .guild_field(GuildId(guild), |g| {
+                g.voice_states
+                    .iter()
+                    .filter_map(|(user, v)| v.channel_id.map(|c| (user.0, c.0)))
+                    .collect()
+            })
+            .await
+    }
+
+    async fn channels(&self, guild: u64) -> Option<HashSet<u64>> {
+        self.cache
+            .guild_field(GuildId(guild), |g| g.channels.keys().map(|c| c.0).collect())
+            .await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use async_trait::async_trait;
+    use serenity::{
+        model::{
+            channel::{Message, PermissionOverwrite, PermissionOverwriteType, Reaction},
+            guild::Member,
+            prelude::VoiceState,
+        },
+        Error,
+    };
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::{Mutex, MutexGuard},
+    };
+
+    use super::{Discord, GuildMember};
+
+    /// Call made to the fake guild
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) enum Call {
+        CreateChannel { name: String, category: u64 },
+        EditChannel { channel: u64, user_limit: u64 },
+        DeleteChannel(u64),
+        MoveMember { user: u64, channel: u64 },
+        CreatePermission(u64),
+        DeletePermission(u64),
+        AddRole { user: u64, role: u64 },
+        RemoveRole { user: u64, role: u64 },
+        Say { channel: u64, content: String },
+        Reply { channel: u64, content: String },
+        DirectMessage { user: u64, content: String },
+        DeferCommand(u64),
+        AnswerCommand { token: String, content: String },
+        Kick(u64),
+    }
+
+    #[derive(Debug, Default)]
+    pub(crate) struct Channel {
+        pub(crate) name: String,
+        pub(crate) overwrites: Vec<PermissionOverwrite>,
+        pub(crate) user_limit: u64,
+    }
+
+    #[derive(Debug, Default)]
+    pub(crate) struct Guild {
+        pub(crate) channels: HashMap<u64, Channel>,
+        /// Members with the timestamp they joined at
+        pub(crate) members: HashMap<u64, i64>,
+        /// Members refusing private messages
+        pub(crate) closed_dms: HashSet<u64>,
+        /// Roles of each member
+        pub(crate) roles: HashMap<u64, HashSet<u64>>,
+        /// Voice channel of each connected member
+        pub(crate) voice: HashMap<u64, u64>,
+        pub(crate) calls: Vec<Call>,
+        next_id: u64,
+    }
+
+    /// In-memory guild recording every call
+    ///
+    /// The calls on a channel that doesn't exist fail like Discord would
+    #[derive(Default)]
+    pub(crate) struct FakeGuild(Mutex<Guild>);
+
+    impl FakeGuild {
+        /// Guild with the given channels, the ids created later start at 1000
+        pub(crate) fn with_channels(channels: &[u64]) -> Self {
+            let guild = Guild {
+                channels: channels
+                    .iter()
+                    .map(|id| (*id, Channel::default()))
+                    .collect(),
+                next_id: 1000,
+                ..Guild::default()
+            };
+
+            FakeGuild(Mutex::new(guild))
+        }
+
+        pub(crate) fn guild(&self) -> MutexGuard<'_, Guild> {
+            self.0.lock().unwrap()
+        }
+
+        pub(crate) fn calls(&self) -> Vec<Call> {
+            self.guild().calls.clone()
+        }
+
+        /// Connect `user` to `channel`, or disconnect them
+        pub(crate) fn connect(&self, user: u64, channel: Option<u64>) {
+            let mut guild = self.guild();
+
+            match channel {
+                Some(channel) => guild.voice.insert(user, channel),
+                None => guild.voice.remove(&user),
+            };
+        }
+
+        pub(crate) fn add_member(&self, user: u64, joined_at: i64) {
+            self.guild().members.insert(user, joined_at);
+        }
+
+        /// Channel and content of the messages sent
+        pub(crate) fn messages(&self) -> Vec<(u64, String)> {
+            self.calls()
+                .into_iter()
+                .filter_map(|call| match call {
+                    Call::Say { channel, content } | Call::Reply { channel, content } => {
+                        Some((channel, content))
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    impl Guild {
+        fn channel(&mut self, id: u64) -> Result<&mut Channel, Error> {
+            self.channels
+                .get_mut(&id)
+                .ok_or(Error::Other("unknown channel"))
+        }
+    }
+
+    #[async_trait]
+    impl Discord for FakeGuild {
+        async fn create_voice_channel(
+            &self,
+            _: u64,
+            name: &str,
+            category: u64,
+            overwrites: Vec<PermissionOverwrite>,
+        ) -> Result<u64, Error> {
+            let mut guild = self.guild();
+            guild.calls.push(Call::CreateChannel {
+                name: name.to_string(),
+                category,
+            });
+
+            guild.next_id += 1;
+            let id = guild.next_id;
+            guild.channels.insert(
+                id,
+                Channel {
+                    name: name.to_string(),
+                    overwrites,
+                    user_limit: 0,
+                },
+            );
+
+            Ok(id)
+        }
+
+        async fn edit_voice_channel(
+            &self,
+            channel: u64,
+            overwrites: Vec<PermissionOverwrite>,
+            user_limit: u64,
+        ) -> Result<(), Error> {
+            let mut guild = self.guild();
+            guild.calls.push(Call::EditChannel {
+                channel,
+                user_limit,
+            });
+
+            let channel = guild.channel(channel)?;
+            channel.overwrites = overwrites;
+            channel.user_limit = user_limit;
+
+            Ok(())
+        }
+
+        async fn delete_channel(&self, channel: u64) -> Result<(), Error> {
+            let mut guild = self.guild();
+            guild.calls.push(Call::DeleteChannel(channel));
+
+            guild
+                .channels
+                .remove(&channel)
+                .ok_or(Error::Other("unknown channel"))?;
+            guild.voice.retain(|_, c| *c != channel);
+
+            Ok(())
+        }
+
+        async fn move_member(&self, _: u64, user: u64, channel: u64) -> Result<(), Error> {
+            let mut guild = self.guild();
+            guild.calls.push(Call::MoveMember { user, channel });
+
+            guild.channel(channel)?;
+            match guild.voice.get_mut(&user) {
+                Some(current) => *current = channel,
+                None => return Err(Error::Other("member not connected")),
+            }
+
+            Ok(())
+        }
+
+        async fn create_permission(
+            &self,
+            channel: u64,
+            overwrite: PermissionOverwrite,
+        ) -> Result<(), Error> {
+            let mut guild = self.guild();
+            guild.calls.push(Call::CreatePermission(channel));
+
+            let channel = guild.channel(channel)?;
+            channel.overwrites.retain(|o| o.kind != overwrite.kind);
+            channel.overwrites.push(overwrite);
+
+            Ok(())
+        }
+
+        async fn delete_permission(
+            &self,
+            channel: u64,
+            kind: PermissionOverwriteType,
+        ) -> Result<(), Error> {
+            let mut guild = self.guild();
+            guild.calls.push(Call::DeletePermission(channel));
+
+            guild
+                .channel(channel)?
+                .overwrites
+                .retain(|o| o.kind != kind);
+
+            Ok(())
+        }
+
+        async fn add_member_role(&self, _: u64, user: u64, role: u64) -> Result<(), Error> {
+            let mut guild = self.guild();
+            guild.calls.push(Call::AddRole { user, role });
+            guild.roles.entry(user).or_default().insert(role);
+
+            Ok(())
+        }
+
+        async fn remove_member_role(&self, _: u64, user: u64, role: u64) -> Result<(), Error> {
+            let mut guild = self.guild();
+            guild.calls.push(Call::RemoveRole { user, role });
+            guild.roles.entry(user).or_default().remove(&role);
+
+            Ok(())
+        }
+
+        async fn say(&self, channel: u64, content: &str) -> Result<(), Error> {
+            self.guild().calls.push(Call::Say {
+                channel,
+                content: content.to_string(),
+            });
+
+            Ok(())
+        }
+
+        async fn reply(&self, channel: u64, _: u64, content: &str) -> Result<(), Error> {
+            self.guild().calls.push(Call::Reply {
+                channel,
+                content: content.to_string(),
+            });
+
+            Ok(())
+        }
+
+        async fn direct_message(&self, user: u64, content: &str) -> Result<(), Error> {
+            let mut guild = self.guild();
+            guild.calls.push(Call::DirectMessage {
+                user,
+                content: content.to_string(),
+            });
+
+            match guild.closed_dms.contains(&user) {
+                true => Err(Error::Other("cannot send messages to this user")),
+                false => Ok(()),
+            }
+        }
+
+        async fn defer_command(&self, interaction: u64, _: &str) -> Result<(), Error> {
+            self.guild().calls.push(Call::DeferCommand(interaction));
+
+            Ok(())
+        }
+
+        async fn answer_command(&self, token: &str, content: &str) -> Result<(), Error> {
+            self.guild().calls.push(Call::AnswerCommand {
+                token: token.to_string(),
+                content: content.to_string(),
+            });
+
+            Ok(())
+        }
+
+        async fn kick(&self, _: u64, user: u64, _: &str) -> Result<(), Error> {
+            let mut guild = self.guild();
+            guild.calls.push(Call::Kick(user));
+
+            guild
+                .members
+                .remove(&user)
+                .ok_or(Error::Other("unknown member"))?;
+            guild.roles.remove(&user);
+            guild.voice.remove(&user);
+
+            Ok(())
+        }
+
+        async fn members(&self, _: u64) -> Option<Vec<GuildMember>> {
+            let guild = self.guild();
+
+            let members = guild
+                .members
+                .iter()
+                .map(|(id, joined_at)| GuildMember {
+                    id: *id,
+                    roles: guild
+                        .roles
+                        .get(id)
+                        .map(|r| r.iter().copied().collect())
+                        .unwrap_or_default(),
+                    joined_at: Some(*joined_at),
+                    bot: false,
+                })
+                .collect();
+
+            Some(members)
+        }
+
+        async fn voice_states(&self, _: u64) -> Option<HashMap<u64, u64>> {
+            Some(self.guild().voice.clone())
+        }
+
+        async fn channels(&self, _: u64) -> Option<HashSet<u64>> {
+            Some(self.guild().channels.keys().copied().collect())
+        }
+    }
+
+    /// Voice state of `user` after joining `channel`, or disconnecting
+    pub(crate) fn voice(user: u64, channel: Option<u64>) -> VoiceState {
+        serde_json::from_value(serde_json::json!({
+            "channel_id": channel.map(|c| c.to_string()),
+            "deaf": false,
+            "mute": false,
+            "self_deaf": false,
+            "self_mute": false,
+            "self_video": false,
+            "session_id": "",
+            "suppress": false,
+            "user_id": user.to_string(),
+        }))
+        .unwrap()
+    }
+
+    /// Message sent in channel 50 by `author` holding `roles`
+    ///
+    /// The `<@id>` and `<@&id>` in `content` are mentions
+    pub(crate) fn message(author: u64, roles: &[u64], content: &str) -> Message {
+        let user = |id: &str| {
+            serde_json::json!({
+                "id": id,
+                "avatar": null,
+                "discriminator": "0001",
+                "username": "member",
+            })
+        };
+        let mentions: Vec<_> = content
+            .split_whitespace()
+            .filter_map(|w| w.strip_prefix("<@")?.strip_suffix('>'))
+            .collect();
+
+        serde_json::from_value(serde_json::json!({
+            "id": "60",
+            "attachments": [],
+            "author": user(&author.to_string()),
+            "channel_id": "50",
+            "content": content,
+            "edited_timestamp": null,
+            "embeds": [],
+            "guild_id": "1",
+            "type": 0,
+            "member": {"roles": roles.iter().map(|r| r.to_string()).collect::<Vec<_>>()},
+            "mention_everyone": false,
+            "mention_roles": mentions.iter().filter_map(|m| m.strip_prefix('&')).collect::<Vec<_>>(),
+            "mentions": mentions
+                .iter()
+                .filter(|m| !m.starts_with('&'))
+                .map(|m| user(m))
+                .collect::<Vec<_>>(),
+            "pinned": false,
+            "timestamp": "2020-09-13T12:26:40Z",
+            "tts": false,
+        }))
+        .unwrap()
+    }
+
+    /// Member `user` of the guild
+    pub(crate) fn member(user: u64) -> Member {
+        serde_json::from_value(serde_json::json!({
+            "deaf": false,
+            "guild_id": "1",
+            "joined_at": "2020-09-13T12:26:40Z",
+            "mute": false,
+            "roles": [],
+            "user": {
+                "id": user.to_string(),
+                "avatar": null,
+                "discriminator": "0001",
+                "username": "member",
+            },
+        }))
+        .unwrap()
+    }
+
+    /// Reaction of `user` with `emoji` on `message`
+    pub(crate) fn reaction(user: u64, message: u64, emoji: &str) -> Reaction {
+        serde_json::from_value(serde_json::json!({
+            "channel_id": "50",
+            "emoji": {"id": null, "name": emoji},
+            "message_id": message.to_string(),
+            "user_id": user.to_string(),
+            "guild_id": "1",
+        }))
+        .unwrap()
+    }
+}
@@ -1,18 +1,107 @@
-use crate::actions::{
-    action::schedule_action,
-    office::{CloseRoomAction, OpenRoomAction},
-    subject::SubjectAction,
+use crate::{
+    actions::{
+        access::AccessAction,
+        action::schedule_action,
+        hours::HoursAction,
+        office::{CloseRoomAction, OpenRoomAction, VisitAction},
+        stats::StatsAction,
+        status::StatusAction,
+        subject::SubjectAction,
+        welcome::WelcomeAction,
+        whois::{self, WhoisAction, WhoisCommand},
+    },
+    discord::{Discord, SerenityDiscord},
+    reminder, scheduler,
+    state::BotState,
+    sweeper,
 };
 use serenity::{
     async_trait,
     client::{Context, EventHandler},
-    model::{channel::Reaction, id::GuildId, prelude::VoiceState},
+    model::{
+        channel::{Message, Reaction},
+        gateway::Ready,
+        guild::Member,
+        id::GuildId,
+        interactions::Interaction,
+        prelude::VoiceState,
+    },
 };
+use shared_lib::socket::message::BotResponse;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+pub(crate) struct Handler {
+    state: BotState,
+    /// The background tasks must only be started once, `ready` is sent again on reconnection
+    tasks_started: AtomicBool,
+}
 
-pub(crate) struct Handler;
+impl Handler {
+    pub(crate) fn new(state: BotState) -> Self {
+        Handler {
+            state,
+            tasks_started: AtomicBool::new(false),
+        }
+    }
+}
 
 #[async_trait]
 impl EventHandler for Handler {
+    async fn ready(&self, context: Context, _: Ready) {
+        if !self.tasks_started.swap(true, Ordering::SeqCst) {
+            let discord: Arc<dyn Discord> = Arc::new(SerenityDiscord::from(&context));
+
+            tokio::spawn(scheduler::run(discord.clone(), self.state.clone()));
+            tokio::spawn(sweeper::run(discord.clone(), self.state.clone()));
+            tokio::spawn(reminder::run(discord, self.state.clone()));
+        }
+
+        // Declaring the command again only updates it
+        let guild = GuildId(self.state.config.read().await.guild);
+        if let Err(e) = guild
+            .create_application_command(&context.http, whois::register)
+            .await
+        {
+            tracing::error!("Couldn't declare /whois: {:?}", e);
+        }
+    }
+
+    async fn interaction_create(&self, context: Context, interaction: Interaction) {
+        let discord = SerenityDiscord::from(&context);
+
+        if let Interaction::ApplicationCommand(command) = interaction {
+            let command = WhoisCommand::from(&command);
+            schedule_action(WhoisAction::new(&discord, &self.state, &command)).await;
+        }
+    }
+
+    async fn message(&self, context: Context, message: Message) {
+        let discord = SerenityDiscord::from(&context);
+
+        let hours = schedule_action(HoursAction::new(&discord, &self.state, &message));
+        let access = schedule_action(AccessAction::new(&discord, &self.state, &message));
+        let stats = schedule_action(StatsAction::new(&discord, &self.state, &message));
+        let status = schedule_action(StatusAction::new(&discord, &self.state, &message));
+
+        futures::join!(hours, access, stats, status);
+    }
+
+    /// Greet the new member and let the backend grant their roles if they're already verified
+    async fn guild_member_addition(&self, context: Context, guild_id: GuildId, member: Member) {
+        let discord = SerenityDiscord::from(&context);
+
+        schedule_action(WelcomeAction::new(&discord, &self.state, guild_id, &member)).await;
+
+        if guild_id.0 == self.state.config.read().await.guild && !member.user.bot {
+            self.state.send(BotResponse::MemberJoined {
+                discord_id: member.user.id.0,
+            });
+        }
+    }
+
     async fn voice_state_update(
         &self,
         context: Context,
@@ -20,23 +109,29 @@ impl EventHandler for Handler {
         _: Option<VoiceState>,
         new: VoiceState,
     ) {
-        let open_room = OpenRoomAction::new(&context, &guil_id, &new);
-        let close_room = CloseRoomAction::new(&context, &new);
+        let discord: Arc<dyn Discord> = Arc::new(SerenityDiscord::from(&context));
+
+        let open_room = OpenRoomAction::new(discord.as_ref(), &self.state, &guil_id, &new);
+        let close_room = CloseRoomAction::new(&discord, &self.state, &new);
+        let visit = VisitAction::new(&self.state, &new);
 
         let a1 = schedule_action(open_room);
         let a2 = schedule_action(close_room);
+        let a3 = schedule_action(visit);
 
-        futures::join!(a1, a2);
+        futures::join!(a1, a2, a3);
     }
 
     async fn reaction_add(&self, context: Context, reaction: Reaction) {
-        let open_subject = SubjectAction::new(&context, &reaction, true);
+        let discord = SerenityDiscord::from(&context);
+        let open_subject = SubjectAction::new(&discord, &self.state, &reaction, true);
 
         schedule_action(open_subject).await;
     }
 
     async fn reaction_remove(&self, context: Context, reaction: Reaction) {
-        let close_subject = SubjectAction::new(&context, &reaction, false);
+        let discord = SerenityDiscord::from(&context);
+        let close_subject = SubjectAction::new(&discord, &self.state, &reaction, false);
 
         schedule_action(close_subject).await;
     }
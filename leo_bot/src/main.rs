@@ -1,69 +1,122 @@
 mod actions;
+mod audit;
+mod discord;
 mod events;
+mod members;
+mod metrics;
 mod models;
+mod reminder;
+mod requests;
+mod scheduler;
+mod shutdown;
+mod state;
+mod sweeper;
 
 use crate::{
+    discord::SerenityDiscord,
     events::Handler,
-    models::{Config, Room},
+    models::Config,
+    requests::RequestHandler,
+    state::{BotState, JsonStore, SystemClock},
 };
-use serenity::{
-    client::{Client, Context},
-    prelude::{RwLock, TypeMapKey},
+use actix::{AsyncContext, Context};
+use serenity::client::{bridge::gateway::GatewayIntents, Client};
+use shared_lib::{
+    shutdown::timeout_from_env,
+    socket::{
+        client::connect,
+        health::Heartbeat,
+        message::{server_request, BotResponse},
+        router::Router,
+        transport::Endpoint,
+    },
+    trace::{self, LogFormat},
 };
-use shared_lib::socket::client::tcp_client;
 use std::{env, fs::File, sync::Arc};
 
-struct ExternalConfig;
-impl TypeMapKey for ExternalConfig {
-    type Value = Arc<RwLock<Config>>;
-}
-
-pub struct RoomStorage;
-impl TypeMapKey for RoomStorage {
-    type Value = Arc<RwLock<Vec<Room>>>;
-}
-
 #[actix_web::main]
 async fn main() {
     dotenv::dotenv().ok();
+    trace::init(LogFormat::from_env());
 
-    let addr = tcp_client("127.0.0.1:1234").await;
+    let file = File::open("config.json").expect("config file");
+    let mut config: Config = serde_json::from_reader(file).unwrap();
+    if let Some(welcome) = &mut config.welcome {
+        welcome.secret = env::var("LINK_SECRET").expect("link secret");
+    }
+    let rooms_path = env::var("ROOMS_PATH").unwrap_or_else(|_| "rooms.json".to_string());
+    let applied_path = env::var("APPLIED_PATH").unwrap_or_else(|_| "applied.json".to_string());
+    let welcomed_path = env::var("WELCOMED_PATH").unwrap_or_else(|_| "welcomed.json".to_string());
+    let groups_path = env::var("GROUPS_PATH").unwrap_or_else(|_| "groups.json".to_string());
+    let metrics_address =
+        env::var("METRICS_ADDRESS").unwrap_or_else(|_| "127.0.0.1:9100".to_string());
+    // `tcp://`, `unix://` or `ws://`, a bare address is TCP
+    let backend: Endpoint = env::var("BACKEND_SOCKET")
+        .unwrap_or_else(|_| "tcp://127.0.0.1:1234".to_string())
+        .parse()
+        .expect("backend socket url");
+    let backend = backend.with_secret(env::var("SOCKET_SECRET").ok());
+    let heartbeat = Heartbeat::from_env().expect("heartbeat settings");
+
+    // The request handler and the socket client need each other's address
+    let ctx = Context::<RequestHandler>::new();
+    let handler = ctx.address();
+    let router = Router::new()
+        .route::<server_request::Verify>(handler.clone().recipient())
+        .route::<server_request::Unverify>(handler.clone().recipient())
+        .route::<server_request::Promote>(handler.clone().recipient())
+        .route::<server_request::Unlink>(handler.clone().recipient())
+        .route::<server_request::OfficeHours>(handler.clone().recipient())
+        .route::<server_request::OfficeHoursChanged>(handler.clone().recipient())
+        .route::<server_request::OfficeStats>(handler.clone().recipient())
+        .route::<server_request::Whois>(handler.clone().recipient())
+        .route::<server_request::Outbox>(handler.recipient());
+    let socket = connect(&backend, router, heartbeat)
+        .await
+        .expect("backend socket");
+    let state = BotState::new(
+        config,
+        socket.clone().recipient(),
+        socket.clone().recipient(),
+        Arc::new(JsonStore::new(
+            rooms_path,
+            applied_path,
+            welcomed_path,
+            groups_path,
+        )),
+        Arc::new(SystemClock),
+    );
+    state.send(BotResponse::GetOfficeHours);
+    let metrics = metrics::serve(&metrics_address, state.clone()).expect("metrics address");
+    actix_web::rt::spawn(metrics);
 
     // Login with a bot token from the environment
     let token = env::var("DISCORD_TOKEN").expect("token");
+    // Same application as the OAuth login of the backend, needed by the slash commands
+    let application_id = env::var("DISCORD_CLIENT_ID")
+        .expect("discord client id")
+        .parse()
+        .expect("discord client id");
     let mut client = Client::builder(token)
-        .event_handler(Handler)
+        .application_id(application_id)
+        .event_handler(Handler::new(state.clone()))
+        // Joining members are needed to grant the roles of verified users
+        .intents(GatewayIntents::non_privileged() | GatewayIntents::GUILD_MEMBERS)
         .await
         .expect("Error creating client");
 
-    {
-        let mut data = client.data.write().await;
-        let file = File::open("config.json").expect("config file");
-        let config: Config = serde_json::from_reader(file).unwrap();
-
-        data.insert::<ExternalConfig>(Arc::new(RwLock::new(config)));
-        data.insert::<RoomStorage>(Arc::new(RwLock::new(Vec::new())));
-    }
+    let discord = Arc::new(SerenityDiscord::from(client.cache_and_http.as_ref()));
+    ctx.run(RequestHandler::new(discord.clone(), state.clone()));
+    actix_web::rt::spawn(shutdown::run(
+        discord,
+        state,
+        socket.recipient(),
+        client.shard_manager.clone(),
+        timeout_from_env(),
+    ));
 
     // start listening for events by starting a single shard
     if let Err(why) = client.start().await {
-        println!("An error occurred while running the client: {:?}", why);
+        tracing::error!("An error occurred while running the client: {:?}", why);
     }
 }
-
-//TODO generic lock
-async fn get_config_lock(context: &Context) -> Arc<RwLock<Config>> {
-    let data_read = context.data.read().await;
-    data_read
-        .get::<ExternalConfig>()
-        .expect("Expected Config in TypeMap.")
-        .clone()
-}
-
-async fn get_rooms_lock(context: &Context) -> Arc<RwLock<Vec<Room>>> {
-    let data_read = context.data.read().await;
-    data_read
-        .get::<RoomStorage>()
-        .expect("Expected Room in TypeMap.")
-        .clone()
-}
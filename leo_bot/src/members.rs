@@ -0,0 +1,90 @@
+use serenity::{
+    model::{channel::PermissionOverwriteType, id::UserId},
+    Error,
+};
+use shared_lib::category::Category;
+
+use crate::{
+    discord::Discord,
+    models::{Config, SubjectsMessage},
+};
+
+/// Give the verified role and the ones matching `category`
+pub(crate) async fn grant_roles(
+    discord: &dyn Discord,
+    config: &Config,
+    discord_id: u64,
+    category: &Category,
+) -> Result<(), Error> {
+    for role in config.roles_for(category) {
+        discord
+            .add_member_role(config.guild, discord_id, role)
+            .await?;
+    }
+
+    if let Some(unverified) = config.roles.get("unverified") {
+        discord
+            .remove_member_role(config.guild, discord_id, *unverified)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Give the `unverified` role, restricting the member to the lobby
+pub(crate) async fn restrict(
+    discord: &dyn Discord,
+    config: &Config,
+    discord_id: u64,
+) -> Result<(), Error> {
+    match config.roles.get("unverified") {
+        Some(unverified) => {
+            discord
+                .add_member_role(config.guild, discord_id, *unverified)
+                .await
+        }
+        None => Ok(()),
+    }
+}
+
+/// Remove the roles granted for `previous` that `next` doesn't grant, see `Config::revoked_roles`
+pub(crate) async fn revoke_roles(
+    discord: &dyn Discord,
+    config: &Config,
+    discord_id: u64,
+    previous: &Category,
+    next: Option<&Category>,
+) -> Result<(), Error> {
+    for role in config.revoked_roles(previous, next) {
+        discord
+            .remove_member_role(config.guild, discord_id, role)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Delete the overwrites created by the reactions on the subjects matching `filter`
+pub(crate) async fn revoke_subjects<F>(
+    discord: &dyn Discord,
+    config: &Config,
+    discord_id: u64,
+    filter: F,
+) -> Result<(), Error>
+where
+    F: Fn(&SubjectsMessage) -> bool,
+{
+    let member = PermissionOverwriteType::Member(UserId(discord_id));
+
+    let channels = config
+        .subjects
+        .iter()
+        .filter(|s| filter(s))
+        .flat_map(|s| s.channels.values());
+
+    for channel in channels {
+        discord.delete_permission(*channel, member).await?;
+    }
+
+    Ok(())
+}
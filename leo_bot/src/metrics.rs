@@ -0,0 +1,122 @@
+use actix_web::{
+    dev::Server,
+    web::{self, Data},
+    App, HttpResponse, HttpServer,
+};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, IntCounterVec, IntGauge,
+    IntGaugeVec,
+};
+use shared_lib::metrics;
+use std::{cell::Cell, future::Future, io};
+
+use crate::state::BotState;
+
+lazy_static! {
+    /// Actions executed by `Action` type
+    pub(crate) static ref ACTIONS: IntCounterVec = register_int_counter_vec!(
+        "leo_bot_actions_total",
+        "Actions executed",
+        &["action"]
+    )
+    .unwrap();
+    /// Actions during which a Discord call failed, by `Action` type
+    pub(crate) static ref ACTION_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "leo_bot_action_failures_total",
+        "Actions executed with a failed Discord call",
+        &["action"]
+    )
+    .unwrap();
+    /// Failed Discord calls by operation
+    pub(crate) static ref DISCORD_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "leo_bot_discord_errors_total",
+        "Discord API calls that failed",
+        &["operation"]
+    )
+    .unwrap();
+    /// Read from the rooms when scraped
+    pub(crate) static ref OPEN_OFFICES: IntGauge =
+        register_int_gauge!("leo_bot_open_offices", "Offices open").unwrap();
+    /// Members in the waiting room of each office in queue mode, by teacher
+    pub(crate) static ref OFFICE_QUEUES: IntGaugeVec = register_int_gauge_vec!(
+        "leo_bot_office_queue_length",
+        "Members waiting to enter an office",
+        &["teacher"]
+    )
+    .unwrap();
+}
+
+tokio::task_local! {
+    /// Set when a Discord call fails during the action of the task
+    static FAILED: Cell<bool>;
+}
+
+/// Count the execution of `action`, failed if a Discord call fails meanwhile
+pub(crate) async fn count_action<F: Future<Output = ()>>(action: &str, execution: F) {
+    ACTIONS.with_label_values(&[action]).inc();
+
+    let failed = FAILED
+        .scope(Cell::new(false), async {
+            execution.await;
+            FAILED.with(Cell::get)
+        })
+        .await;
+
+    if failed {
+        ACTION_FAILURES.with_label_values(&[action]).inc();
+    }
+}
+
+/// Count a failed call to Discord, failing the current action
+pub(crate) fn discord_error(operation: &str) {
+    DISCORD_ERRORS.with_label_values(&[operation]).inc();
+    FAILED.try_with(|failed| failed.set(true)).ok();
+}
+
+/// Small HTTP listener serving the metrics on `address` under `/metrics`
+pub(crate) fn serve(address: &str, state: BotState) -> io::Result<Server> {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(Data::new(state.clone()))
+            .route("/metrics", web::get().to(scrape))
+    })
+    .workers(1)
+    // The bot stops with the gateway, see `shutdown::run`
+    .disable_signals()
+    .bind(address)?
+    .run();
+
+    Ok(server)
+}
+
+async fn scrape(state: Data<BotState>) -> HttpResponse {
+    let rooms = state.rooms.read().await;
+
+    OPEN_OFFICES.set(rooms.len() as i64);
+    // The closed offices must not be reported anymore
+    OFFICE_QUEUES.reset();
+    for room in rooms.iter().filter(|room| room.waiting_id != 0) {
+        OFFICE_QUEUES
+            .with_label_values(&[&room.discord_id.to_string()])
+            .set(room.waiting.len() as i64);
+    }
+
+    metrics::response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_action, discord_error, ACTIONS, ACTION_FAILURES};
+
+    #[actix_rt::test]
+    async fn failed_discord_calls_fail_the_action() {
+        count_action("Succeeding", async {}).await;
+        count_action("Failing", async { discord_error("say") }).await;
+        discord_error("say");
+
+        assert_eq!(ACTIONS.with_label_values(&["Succeeding"]).get(), 1);
+        assert_eq!(ACTION_FAILURES.with_label_values(&["Succeeding"]).get(), 0);
+        assert_eq!(ACTION_FAILURES.with_label_values(&["Failing"]).get(), 1);
+    }
+}
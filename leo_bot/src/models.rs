@@ -1,24 +1,328 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serenity::model::{
+    channel::{PermissionOverwrite, PermissionOverwriteType},
+    id::{RoleId, UserId},
+    Permissions,
+};
+use shared_lib::{
+    category::Category,
+    link::LinkSigner,
+    office::{OfficeSession, OfficeVisit},
+};
+use std::{collections::HashMap, fmt, str::FromStr};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Room {
     pub(crate) discord_id: u64,
     pub(crate) office_id: u64,
     pub(crate) waiting_id: u64,
     pub(crate) text_id: u64,
+    /// Office hours the room was opened for, if any
+    #[serde(default)]
+    pub(crate) schedule: Option<i64>,
+    /// Unix timestamp of when the teacher left the office, cleared when they come back
+    #[serde(default)]
+    pub(crate) teacher_left_at: Option<i64>,
+    /// Unix timestamp of when the office became empty
+    #[serde(default)]
+    pub(crate) empty_since: Option<i64>,
+    #[serde(default)]
+    pub(crate) access: OfficeAccess,
+    /// Subjects message of the office hours the room was opened for
+    #[serde(default)]
+    pub(crate) subject: Option<u64>,
+    /// Unix timestamp of the creation of the office
+    #[serde(default)]
+    pub(crate) opened_at: i64,
+    /// Members in the waiting room, with the time they arrived
+    #[serde(default)]
+    pub(crate) waiting: HashMap<u64, i64>,
+    /// Visits in progress
+    #[serde(default)]
+    pub(crate) present: HashMap<u64, OfficeVisit>,
+    /// Finished visits
+    #[serde(default)]
+    pub(crate) visits: Vec<OfficeVisit>,
+}
+
+impl Room {
+    /// Record that `user` is now in `channel`, the teacher is ignored
+    pub(crate) fn track(&mut self, user: u64, channel: Option<u64>, now: i64) {
+        if user == self.discord_id {
+            return;
+        }
+
+        let in_office = channel == Some(self.office_id);
+        let in_waiting = self.waiting_id != 0 && channel == Some(self.waiting_id);
+
+        if !in_office {
+            if let Some(mut visit) = self.present.remove(&user) {
+                visit.left_at = now;
+                self.visits.push(visit);
+            }
+        }
+
+        if in_waiting {
+            self.waiting.entry(user).or_insert(now);
+        } else if in_office && !self.present.contains_key(&user) {
+            let waited = self.waiting.remove(&user).map(|since| now - since);
+            self.present.insert(
+                user,
+                OfficeVisit {
+                    student: user,
+                    waited,
+                    joined_at: now,
+                    left_at: now,
+                },
+            );
+        } else if !in_office {
+            self.waiting.remove(&user);
+        }
+    }
+
+    /// Summary of the office closed at `now`, the visits in progress end now
+    pub(crate) fn session(&self, now: i64) -> OfficeSession {
+        let ongoing = self.present.values().map(|v| OfficeVisit {
+            left_at: now,
+            ..v.clone()
+        });
+
+        OfficeSession {
+            teacher: self.discord_id,
+            subject: self.subject,
+            opened_at: self.opened_at,
+            closed_at: now,
+            visits: self.visits.iter().cloned().chain(ongoing).collect(),
+        }
+    }
+}
+
+/// Who may join an office
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OfficeMode {
+    /// Anyone can walk in
+    #[default]
+    Open,
+    /// Students wait in the waiting room until the teacher admits them
+    Queue,
+    /// Only the invited members and roles can join
+    Invite,
+}
+
+impl FromStr for OfficeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(OfficeMode::Open),
+            "queue" => Ok(OfficeMode::Queue),
+            "invite" => Ok(OfficeMode::Invite),
+            _ => Err(format!("unknown office mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for OfficeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OfficeMode::Open => "open",
+            OfficeMode::Queue => "queue",
+            OfficeMode::Invite => "invite",
+        })
+    }
+}
+
+/// Access settings of an office, changed live by the teacher
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OfficeAccess {
+    pub(crate) mode: OfficeMode,
+    /// Members allowed to join, invited or admitted from the waiting room
+    pub(crate) users: Vec<u64>,
+    /// Roles allowed to join in invite-only mode
+    pub(crate) roles: Vec<u64>,
+    /// Maximum amount of members in the office, 0 for no limit
+    pub(crate) user_limit: u64,
+}
+
+impl OfficeAccess {
+    /// Permission overwrites of the office of `teacher` on the guild `everyone` role
+    pub(crate) fn overwrites(&self, teacher: u64, everyone: u64) -> Vec<PermissionOverwrite> {
+        let allow = |kind| PermissionOverwrite {
+            allow: Permissions::CONNECT,
+            deny: Permissions::empty(),
+            kind,
+        };
+
+        if self.mode == OfficeMode::Open {
+            return Vec::new();
+        }
+
+        let mut overwrites = vec![
+            PermissionOverwrite {
+                allow: Permissions::empty(),
+                deny: Permissions::CONNECT,
+                kind: PermissionOverwriteType::Role(RoleId(everyone)),
+            },
+            allow(PermissionOverwriteType::Member(UserId(teacher))),
+        ];
+        overwrites.extend(
+            self.users
+                .iter()
+                .map(|u| allow(PermissionOverwriteType::Member(UserId(*u)))),
+        );
+        if self.mode == OfficeMode::Invite {
+            overwrites.extend(
+                self.roles
+                    .iter()
+                    .map(|r| allow(PermissionOverwriteType::Role(RoleId(*r)))),
+            );
+        }
+
+        overwrites
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
+    pub(crate) guild: u64,
     pub(crate) roles: HashMap<String, u64>,
     pub(crate) room: u64,
     pub(crate) teacher_category: u64,
     pub(crate) subjects: Vec<SubjectsMessage>,
+    /// Channel mirroring the audit events, if any
+    #[serde(default)]
+    pub(crate) log_channel: Option<u64>,
+    #[serde(default)]
+    pub(crate) office: OfficeTimeouts,
+    /// Greeting of the new members, disabled when missing
+    #[serde(default)]
+    pub(crate) welcome: Option<WelcomeConfig>,
+    /// What becomes of the open offices when the bot shuts down
+    #[serde(default)]
+    pub(crate) on_shutdown: ShutdownOffices,
+}
+
+/// Fate of the open offices on shutdown
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownOffices {
+    /// Saved with the rooms, the bot picks them up on restart
+    #[default]
+    Keep,
+    /// Deleted like abandoned offices, their sessions are sent to the backend
+    Delete,
+}
+
+/// How new members are led to the verification
+///
+/// Members are restricted to the lobby by the `unverified` role until they're verified
+#[derive(Serialize, Deserialize)]
+pub struct WelcomeConfig {
+    /// Lobby channel greeting the new members
+    pub(crate) channel: u64,
+    /// Login route of the backend, the verification links point to it
+    pub(crate) login_url: String,
+    /// Validity of the verification links in seconds
+    #[serde(default = "WelcomeConfig::default_link_validity")]
+    pub(crate) link_validity: u64,
+    /// Delay in seconds after joining before reminding the members with the `unverified` role,
+    /// never when missing
+    #[serde(default)]
+    pub(crate) reminder: Option<u64>,
+    /// Delay in seconds after joining before kicking the members with the `unverified` role,
+    /// never when missing
+    #[serde(default)]
+    pub(crate) kick_after: Option<u64>,
+    /// Secret shared with the backend to sign the links, read from `LINK_SECRET`
+    #[serde(skip)]
+    pub(crate) secret: String,
+}
+
+impl WelcomeConfig {
+    fn default_link_validity() -> u64 {
+        7 * 24 * 60 * 60
+    }
+
+    /// Verification link of `discord_id`, valid from `now`
+    pub(crate) fn link(&self, discord_id: u64, now: i64) -> String {
+        let expires_at = now + self.link_validity as i64;
+        let token = LinkSigner::new(&self.secret).sign(discord_id, expires_at);
+
+        format!("{}?token={}", self.login_url, token)
+    }
+}
+
+/// Delays in seconds before an office is deleted
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct OfficeTimeouts {
+    /// After the teacher disconnected from the voice channels
+    pub(crate) grace_period: u64,
+    /// After the teacher moved to another channel
+    pub(crate) away: u64,
+    /// After the last member left the office
+    pub(crate) idle: u64,
+    /// Time between two sweeps of the offices
+    pub(crate) sweep_interval: u64,
+}
+
+impl Default for OfficeTimeouts {
+    fn default() -> Self {
+        OfficeTimeouts {
+            grace_period: 60,
+            away: 10 * 60,
+            idle: 15 * 60,
+            sweep_interval: 60,
+        }
+    }
+}
+
+impl Config {
+    /// Roles of a verified member of `category`
+    pub(crate) fn roles_for(&self, category: &Category) -> Vec<u64> {
+        std::iter::once("verified".to_string())
+            .chain(category.role_keys())
+            .filter_map(|key| self.roles.get(&key).copied())
+            .collect()
+    }
+
+    /// Whether a member with `roles` holds the teacher role
+    pub(crate) fn is_teacher(&self, roles: &[RoleId]) -> bool {
+        self.roles
+            .get("teacher")
+            .is_some_and(|teacher| roles.contains(&RoleId(*teacher)))
+    }
+
+    /// Roles granted for `previous` that `next` doesn't grant, every one of them without `next`
+    ///
+    /// The roles given by hand, a teacher role on staff the rules don't classify for instance,
+    /// are kept
+    pub(crate) fn revoked_roles(&self, previous: &Category, next: Option<&Category>) -> Vec<u64> {
+        let kept = next.map(|next| self.roles_for(next)).unwrap_or_default();
+
+        self.roles_for(previous)
+            .into_iter()
+            .filter(|role| !kept.contains(role))
+            .collect()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct SubjectsMessage {
     pub(crate) id: u64,
     pub(crate) channels: HashMap<String, u64>,
+    /// Year the subjects belong to, the access is revoked on promotion
+    #[serde(default)]
+    pub(crate) year: Option<u8>,
+    /// ADFS groups the subjects are open to, every member when empty
+    #[serde(default)]
+    pub(crate) groups: Vec<String>,
+}
+
+impl SubjectsMessage {
+    /// Whether a member of `groups` may open the subjects
+    pub(crate) fn open_to(&self, groups: &[String]) -> bool {
+        self.groups.is_empty() || self.groups.iter().any(|group| groups.contains(group))
+    }
 }
@@ -0,0 +1,124 @@
+use shared_lib::audit::{AuditAction, AuditEvent};
+use std::{sync::Arc, time::Duration};
+
+use crate::{audit::log_event, discord::Discord, state::BotState};
+
+/// Time between two checks of the unverified members
+const TICK: Duration = Duration::from_secs(60);
+
+/// Remind and kick the unverified members, never returns
+pub(crate) async fn run(discord: Arc<dyn Discord>, state: BotState) {
+    let mut interval = tokio::time::interval(TICK);
+    let mut since = state.clock.timestamp();
+
+    loop {
+        interval.tick().await;
+
+        let now = state.clock.timestamp();
+        remind(discord.as_ref(), &state, since, now).await;
+        since = now;
+    }
+}
+
+/// Remind the unverified members whose reminder is due in `(since, now]`
+/// and kick the ones past the deadline
+///
+/// Only the members given the `unverified` role by the welcome are concerned, the delays
+/// count from their welcome. The members restricted later by an unlink or an unverify are
+/// never kicked
+pub(crate) async fn remind(discord: &dyn Discord, state: &BotState, since: i64, now: i64) {
+    let welcomed = state.welcomed.read().await.clone();
+    if welcomed.is_empty() {
+        return;
+    }
+    let config = state.config.read().await;
+    let (welcome, unverified) = match (&config.welcome, config.roles.get("unverified")) {
+        (Some(welcome), Some(unverified)) => (welcome, *unverified),
+        _ => return,
+    };
+    let members = match discord.members(config.guild).await {
+        Some(members) => members,
+        None => return,
+    };
+
+    let mut kicked = Vec::new();
+    let mut forgotten = Vec::new();
+    for (user, welcomed_at) in welcomed {
+        // Verified or gone
+        if !members
+            .iter()
+            .any(|m| m.id == user && m.roles.contains(&unverified))
+        {
+            forgotten.push(user);
+            continue;
+        }
+        let due = |delay: Option<u64>| delay.map(|d| welcomed_at + d as i64);
+
+        if due(welcome.kick_after).is_some_and(|deadline| deadline <= now) {
+            if discord
+                .kick(config.guild, user, "not verified in time")
+                .await
+                .is_ok()
+            {
+                kicked.push(user);
+            }
+            continue;
+        }
+
+        if due(welcome.reminder).is_some_and(|reminder| since < reminder && reminder <= now) {
+            let dm = format!(
+                "You still haven't linked your school account, here is a new link: {}",
+                welcome.link(user, now)
+            );
+            discord.direct_message(user, &dm).await.ok();
+        }
+    }
+    drop(config);
+
+    forgotten.extend_from_slice(&kicked);
+    state.forget_welcomes(&forgotten).await;
+    for user in kicked {
+        let event = AuditEvent::new(user, user, AuditAction::MemberKicked, "not verified");
+        log_event(discord, state, event).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::remind;
+    use crate::{
+        actions::welcome::tests::{config, UNVERIFIED},
+        discord::fake::{Call, FakeGuild},
+        state::fake,
+    };
+
+    #[actix_rt::test]
+    async fn unverified_members_are_reminded_then_kicked() {
+        let fakes = fake::state(config(), Vec::new());
+        let guild = FakeGuild::default();
+        guild.add_member(7, 1_000);
+        guild.add_member(8, 1_000);
+        guild
+            .guild()
+            .roles
+            .insert(7, std::iter::once(UNVERIFIED).collect());
+        fakes.state.record_welcome(7, 1_000).await;
+        // Verified since their welcome, they are forgotten
+        fakes.state.record_welcome(8, 1_000).await;
+
+        remind(&guild, &fakes.state, 1_000, 1_599).await;
+        assert!(guild.calls().is_empty());
+
+        remind(&guild, &fakes.state, 1_599, 1_660).await;
+        remind(&guild, &fakes.state, 1_660, 1_720).await;
+        assert!(matches!(
+            guild.calls().as_slice(),
+            [Call::DirectMessage { user: 7, .. }]
+        ));
+
+        remind(&guild, &fakes.state, 4_590, 4_650).await;
+        assert_eq!(guild.calls()[1], Call::Kick(7));
+        assert!(guild.guild().members.contains_key(&8));
+        assert!(fakes.store.2.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,375 @@
+use actix::{Actor, AtomicResponse, Context, Handler, WrapFuture};
+use serenity::Error;
+use shared_lib::{
+    socket::{
+        message::{BotResponse, ServerRequest, Traced},
+        router::Variant,
+    },
+    trace,
+};
+use std::sync::Arc;
+use tracing::Instrument;
+
+use crate::{
+    actions::{hours::format_change, stats::format_stats, whois::format_whois},
+    discord::Discord,
+    members::{grant_roles, restrict, revoke_roles, revoke_subjects},
+    state::BotState,
+};
+
+/// Actor applying the requests sent by the backend
+pub(crate) struct RequestHandler {
+    discord: Arc<dyn Discord>,
+    state: BotState,
+}
+
+impl RequestHandler {
+    pub(crate) fn new(discord: Arc<dyn Discord>, state: BotState) -> Self {
+        RequestHandler { discord, state }
+    }
+}
+
+impl Actor for RequestHandler {
+    type Context = Context<Self>;
+}
+
+/// Requests are applied as part of the backend request that sent them, so their logs and
+/// answers carry its ID
+///
+/// They are applied one at a time, in the order the backend sent them
+impl<V: Variant<ServerRequest>> Handler<Traced<V>> for RequestHandler {
+    type Result = AtomicResponse<Self, ()>;
+
+    fn handle(&mut self, msg: Traced<V>, _: &mut Context<Self>) -> Self::Result {
+        let discord = self.discord.clone();
+        let state = self.state.clone();
+        let span = msg.span();
+        let Traced { request_id, msg } = msg;
+        let kind = msg.kind();
+        let msg = msg.into();
+
+        let applied = async move {
+            let applied = match msg {
+                // The backend retries until acknowledged, a replay is only acknowledged again
+                ServerRequest::Outbox { key, request } => {
+                    let applied = match state.was_applied(&key).await {
+                        true => Ok(()),
+                        false => apply(discord.as_ref(), &state, *request).await,
+                    };
+                    if applied.is_ok() {
+                        state.record_applied(&key).await;
+                        state.send(BotResponse::Ack { key });
+                    }
+                    applied
+                }
+                msg => apply(discord.as_ref(), &state, msg).await,
+            };
+
+            if let Err(e) = applied {
+                tracing::error!("Couldn't apply {}: {:?}", kind, e);
+            }
+        };
+        let applied = trace::scope(request_id, applied).instrument(span);
+
+        AtomicResponse::new(Box::pin(applied.into_actor(self)))
+    }
+}
+
+/// Apply `msg` on the guild, stops at the first Discord call that fails
+async fn apply(discord: &dyn Discord, state: &BotState, msg: ServerRequest) -> Result<(), Error> {
+    let config = state.config.read().await;
+
+    match msg {
+        ServerRequest::Verify {
+            discord_id,
+            category,
+            previous,
+            groups,
+        } => {
+            if let Some(previous) = previous {
+                revoke_roles(discord, &config, discord_id, &previous, Some(&category)).await?;
+            }
+            grant_roles(discord, &config, discord_id, &category).await?;
+            // The member may have left the groups of some subjects
+            revoke_subjects(discord, &config, discord_id, |s| !s.open_to(&groups)).await?;
+            state.set_groups(discord_id, Some(groups)).await;
+        }
+        ServerRequest::Unverify {
+            discord_id,
+            category,
+        } => {
+            revoke_roles(discord, &config, discord_id, &category, None).await?;
+            restrict(discord, &config, discord_id).await?;
+            state.set_groups(discord_id, None).await;
+        }
+        ServerRequest::Promote {
+            discord_id,
+            from,
+            to,
+        } => {
+            revoke_roles(discord, &config, discord_id, &from, Some(&to)).await?;
+            grant_roles(discord, &config, discord_id, &to).await?;
+            revoke_subjects(discord, &config, discord_id, |s| {
+                s.year.is_some() && s.year == from.year
+            })
+            .await?;
+        }
+        ServerRequest::Unlink {
+            discord_id,
+            category,
+        } => {
+            revoke_roles(discord, &config, discord_id, &category, None).await?;
+            revoke_subjects(discord, &config, discord_id, |_| true).await?;
+            restrict(discord, &config, discord_id).await?;
+            state.set_groups(discord_id, None).await;
+        }
+        ServerRequest::OfficeHours(hours) => *state.schedule.write().await = hours,
+        ServerRequest::OfficeHoursChanged {
+            channel,
+            message,
+            change,
+            result,
+        } => {
+            discord
+                .reply(channel, message, &format_change(&change, &result))
+                .await?;
+        }
+        ServerRequest::OfficeStats {
+            channel,
+            teacher,
+            stats,
+        } => {
+            discord.say(channel, &format_stats(teacher, &stats)).await?;
+        }
+        ServerRequest::Whois {
+            token,
+            discord_id,
+            user,
+        } => {
+            discord
+                .answer_command(&token, &format_whois(discord_id, user.as_ref()))
+                .await?;
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, RequestHandler};
+    use crate::{
+        actions::welcome::tests::{config, UNVERIFIED},
+        discord::fake::{Call, Channel, FakeGuild},
+        reminder::remind,
+        state::fake,
+    };
+    use actix::Actor;
+    use shared_lib::{
+        category::Category,
+        office::HoursChange,
+        socket::message::{server_request, BotResponse, ServerRequest, Traced},
+    };
+    use std::sync::Arc;
+
+    #[actix_rt::test]
+    async fn outbox_commands_apply_once() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = Arc::new(FakeGuild::default());
+        let handler = RequestHandler::new(guild.clone(), fakes.state.clone()).start();
+
+        let command = server_request::Outbox {
+            key: "k1".to_string(),
+            request: Box::new(ServerRequest::Unverify {
+                discord_id: 8,
+                category: Category::default(),
+            }),
+        };
+        handler
+            .send(Traced::untraced(command.clone()))
+            .await
+            .unwrap();
+        let sent = fakes.sent().await;
+        let calls = guild.calls().len();
+        assert!(calls > 0);
+
+        handler.send(Traced::untraced(command)).await.unwrap();
+        let acks: Vec<_> = fakes
+            .sent()
+            .await
+            .into_iter()
+            .filter(|msg| matches!(msg, BotResponse::Ack { key } if key == "k1"))
+            .collect();
+
+        assert_eq!(sent.len(), 1);
+        assert_eq!(acks.len(), 2);
+        assert_eq!(guild.calls().len(), calls);
+    }
+
+    #[actix_rt::test]
+    async fn failed_commands_are_retried() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        // The channel of the subject is missing, revoking its overwrites fails
+        let guild = Arc::new(FakeGuild::default());
+        let handler = RequestHandler::new(guild.clone(), fakes.state.clone()).start();
+
+        let command = Traced::untraced(server_request::Outbox {
+            key: "k1".to_string(),
+            request: Box::new(ServerRequest::Unlink {
+                discord_id: 8,
+                category: Category::default(),
+            }),
+        });
+        handler.send(command.clone()).await.unwrap();
+        assert!(fakes.sent().await.is_empty());
+        assert!(!fakes.state.was_applied("k1").await);
+
+        guild.guild().channels.insert(21, Channel::default());
+        handler.send(command).await.unwrap();
+        assert!(matches!(
+            fakes.sent().await.as_slice(),
+            [BotResponse::Ack { key }] if key == "k1"
+        ));
+        assert!(fakes.state.was_applied("k1").await);
+    }
+
+    #[actix_rt::test]
+    async fn hours_changes_are_answered() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        let changed = |change, result| ServerRequest::OfficeHoursChanged {
+            channel: 7,
+            message: 9,
+            change,
+            result,
+        };
+        apply(&guild, &fakes.state, changed(HoursChange::Added, Ok(())))
+            .await
+            .unwrap();
+        let failed = Err("unknown office hours: 4".to_string());
+        apply(
+            &guild,
+            &fakes.state,
+            changed(HoursChange::Removed(4), failed),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            guild.messages(),
+            vec![
+                (7, "Office hours added".to_string()),
+                (
+                    7,
+                    "Couldn't remove office hours `4`: unknown office hours: 4".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn lookups_answer_the_command() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+
+        let whois = ServerRequest::Whois {
+            token: "t1".to_string(),
+            discord_id: 8,
+            user: None,
+        };
+        apply(&guild, &fakes.state, whois).await.unwrap();
+
+        // Never posted in the channel, only the teacher sees the answer
+        assert!(guild.messages().is_empty());
+        assert_eq!(
+            guild.calls(),
+            vec![Call::AnswerCommand {
+                token: "t1".to_string(),
+                content: "<@8> hasn't linked a school account".to_string(),
+            }]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn only_granted_roles_are_revoked() {
+        let fakes = fake::state(fake::config(), Vec::new());
+        let guild = FakeGuild::default();
+        let year = |year| Category {
+            year: Some(year),
+            ..Category::default()
+        };
+
+        let verify = ServerRequest::Verify {
+            discord_id: 8,
+            category: year(2),
+            previous: Some(year(1)),
+            groups: Vec::new(),
+        };
+        apply(&guild, &fakes.state, verify).await.unwrap();
+        let removed: Vec<_> = guild
+            .calls()
+            .into_iter()
+            .filter(|call| matches!(call, Call::RemoveRole { .. }))
+            .collect();
+
+        // The teacher role 2, given by hand, and the verified role 3 stay
+        assert_eq!(removed, vec![Call::RemoveRole { user: 8, role: 4 }]);
+    }
+
+    #[actix_rt::test]
+    async fn unlinked_members_are_not_kicked() {
+        let fakes = fake::state(config(), Vec::new());
+        let guild = FakeGuild::default();
+        guild.guild().channels.insert(21, Channel::default());
+        // Member since long before the welcome flow
+        guild.add_member(8, 1_000);
+
+        let unlink = ServerRequest::Unlink {
+            discord_id: 8,
+            category: Category::default(),
+        };
+        apply(&guild, &fakes.state, unlink).await.unwrap();
+        assert!(guild.calls().contains(&Call::AddRole {
+            user: 8,
+            role: UNVERIFIED
+        }));
+
+        let now = fakes.state.clock.timestamp();
+        remind(&guild, &fakes.state, now - 60, now).await;
+        assert!(!guild.calls().contains(&Call::Kick(8)));
+        assert!(guild.guild().members.contains_key(&8));
+    }
+
+    #[actix_rt::test]
+    async fn groups_follow_the_verification() {
+        let mut config = fake::config();
+        config.subjects[0].groups = vec!["A1".to_string()];
+        let fakes = fake::state(config, Vec::new());
+        let guild = FakeGuild::with_channels(&[21]);
+        let verify = |groups: &[&str]| ServerRequest::Verify {
+            discord_id: 8,
+            category: Category::default(),
+            previous: None,
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        };
+
+        apply(&guild, &fakes.state, verify(&["A1", "TD2"]))
+            .await
+            .unwrap();
+        assert_eq!(fakes.store.3.lock().unwrap()[&8], vec!["A1", "TD2"]);
+        assert!(!guild.calls().contains(&Call::DeletePermission(21)));
+
+        // Out of the group, the subject is closed
+        apply(&guild, &fakes.state, verify(&["A2"])).await.unwrap();
+        assert!(guild.calls().contains(&Call::DeletePermission(21)));
+
+        let unverify = ServerRequest::Unverify {
+            discord_id: 8,
+            category: Category::default(),
+        };
+        apply(&guild, &fakes.state, unverify).await.unwrap();
+        assert!(fakes.store.3.lock().unwrap().is_empty());
+    }
+}
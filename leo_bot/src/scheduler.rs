@@ -0,0 +1,157 @@
+use serenity::model::id::GuildId;
+use shared_lib::{
+    audit::{AuditAction, AuditEvent},
+    office::{format_time, OfficeHours},
+};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use crate::{
+    actions::office::{create_office, delete_office},
+    audit::log_event,
+    discord::Discord,
+    state::BotState,
+};
+
+/// Time between two checks of the schedule
+const TICK: Duration = Duration::from_secs(60);
+
+/// Open and close the offices following the office hours, never returns
+pub(crate) async fn run(discord: Arc<dyn Discord>, state: BotState) {
+    let mut interval = tokio::time::interval(TICK);
+    // Office hours already opened during their current occurrence
+    let mut opened = HashSet::new();
+
+    loop {
+        interval.tick().await;
+
+        let active: Vec<OfficeHours> = {
+            let schedule = state.schedule.read().await;
+            let now = state.clock.now();
+
+            schedule
+                .iter()
+                .filter(|h| h.is_active(now))
+                .cloned()
+                .collect()
+        };
+
+        opened.retain(|id| active.iter().any(|h| h.id == *id));
+        for hours in &active {
+            if opened.insert(hours.id) {
+                open(discord.as_ref(), &state, hours).await;
+            }
+        }
+
+        close_finished(discord.as_ref(), &state, &active).await;
+    }
+}
+
+/// Create the office of the teacher and announce it in the subject channels
+async fn open(discord: &dyn Discord, state: &BotState, hours: &OfficeHours) {
+    let (guild, channels) = {
+        let config = state.config.read().await;
+        let channels: Vec<u64> = config
+            .subjects
+            .iter()
+            .filter(|s| s.id == hours.subject)
+            .flat_map(|s| s.channels.values().copied())
+            .collect();
+
+        (GuildId(config.guild), channels)
+    };
+
+    let existing = state
+        .rooms
+        .read()
+        .await
+        .iter()
+        .find(|r| r.discord_id == hours.teacher)
+        .map(|r| r.office_id);
+
+    let office_id = match existing {
+        Some(office_id) => office_id,
+        None => match create_office(discord, state, &guild, hours.teacher).await {
+            Ok(mut room) => {
+                let office_id = room.office_id;
+                room.schedule = Some(hours.id);
+                room.subject = Some(hours.subject);
+                let mut rooms = state.rooms.write().await;
+                rooms.push(room);
+                state.store.save_rooms(&rooms);
+                drop(rooms);
+
+                let event = AuditEvent::new(
+                    hours.teacher,
+                    office_id,
+                    AuditAction::OfficeOpened,
+                    format!("office hours {}", hours.id),
+                );
+                log_event(discord, state, event).await;
+
+                office_id
+            }
+            Err(e) => {
+                tracing::error!("Couldn't open office hours {}: {:?}", hours.id, e);
+                return;
+            }
+        },
+    };
+
+    let announcement = format!(
+        "<@{}> holds office hours in <#{}> until {}",
+        hours.teacher,
+        office_id,
+        format_time(hours.end)
+    );
+    for channel in channels {
+        discord.say(channel, &announcement).await.ok();
+    }
+}
+
+/// Delete the scheduled offices whose office hours are over once they are empty
+async fn close_finished(discord: &dyn Discord, state: &BotState, active: &[OfficeHours]) {
+    let guild = state.config.read().await.guild;
+
+    let occupied: HashSet<u64> = discord
+        .voice_states(guild)
+        .await
+        .unwrap_or_default()
+        .into_values()
+        .collect();
+
+    let mut rooms = state.rooms.write().await;
+    let mut closed = Vec::new();
+
+    for room in rooms.iter() {
+        let finished = match room.schedule {
+            Some(id) => !active.iter().any(|h| h.id == id),
+            None => false,
+        };
+
+        if finished
+            && !occupied.contains(&room.office_id)
+            && delete_office(discord, state, room).await.is_ok()
+        {
+            closed.push((room.discord_id, room.office_id));
+        }
+    }
+    rooms.retain(|r| {
+        !closed
+            .iter()
+            .any(|(_, office_id)| *office_id == r.office_id)
+    });
+    if !closed.is_empty() {
+        state.store.save_rooms(&rooms);
+    }
+    drop(rooms);
+
+    for (teacher, office_id) in closed {
+        let event = AuditEvent::new(
+            teacher,
+            office_id,
+            AuditAction::OfficeClosed,
+            "office hours over",
+        );
+        log_event(discord, state, event).await;
+    }
+}
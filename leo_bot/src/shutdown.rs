@@ -0,0 +1,108 @@
+use actix::Recipient;
+use serenity::{client::bridge::gateway::ShardManager, prelude::Mutex};
+use shared_lib::{
+    audit::{AuditAction, AuditEvent},
+    shutdown,
+    socket::message::Close,
+};
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    actions::office::delete_office, audit::log_event, discord::Discord, models::ShutdownOffices,
+    state::BotState,
+};
+
+/// Delete or keep the open offices, as configured
+pub(crate) async fn close_offices(discord: &dyn Discord, state: &BotState) {
+    let on_shutdown = state.config.read().await.on_shutdown;
+    let mut rooms = state.rooms.write().await;
+
+    if on_shutdown == ShutdownOffices::Keep {
+        state.store.save_rooms(&rooms);
+        return;
+    }
+
+    let mut closed = Vec::new();
+    for room in rooms.iter() {
+        if delete_office(discord, state, room).await.is_ok() {
+            closed.push((room.discord_id, room.office_id));
+        }
+    }
+
+    rooms.retain(|r| !closed.iter().any(|(_, office)| *office == r.office_id));
+    state.store.save_rooms(&rooms);
+    drop(rooms);
+
+    for (teacher, office) in closed {
+        let event = AuditEvent::new(teacher, office, AuditAction::OfficeClosed, "shutdown");
+        log_event(discord, state, event).await;
+    }
+}
+
+/// Stop the bot once the process is asked to, within `timeout`
+///
+/// The offices are handled first since their sessions go to the backend, then the socket
+/// says goodbye and the gateway is closed
+pub(crate) async fn run(
+    discord: Arc<dyn Discord>,
+    state: BotState,
+    client: Recipient<Close>,
+    shards: Arc<Mutex<ShardManager>>,
+    timeout: Duration,
+) {
+    shutdown::signal().await;
+    tracing::info!("Shutting down within {:?}", timeout);
+
+    let graceful = async {
+        close_offices(discord.as_ref(), &state).await;
+        client.send(Close).await.ok();
+    };
+
+    if actix::clock::timeout(timeout, graceful).await.is_err() {
+        tracing::warn!("Shutdown deadline reached, closing the gateway now");
+    }
+
+    shards.lock().await.shutdown_all().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::close_offices;
+    use crate::{
+        discord::fake::{Call, FakeGuild},
+        models::ShutdownOffices,
+        state::fake,
+    };
+    use shared_lib::socket::message::BotResponse;
+
+    #[actix_rt::test]
+    async fn offices_are_kept_by_default() {
+        let fakes = fake::state(fake::config(), vec![fake::room(5, 30)]);
+        let guild = FakeGuild::with_channels(&[30]);
+        fakes.store.0.lock().unwrap().clear();
+
+        close_offices(&guild, &fakes.state).await;
+
+        assert!(guild.calls().is_empty());
+        assert_eq!(fakes.store.0.lock().unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn offices_can_be_deleted() {
+        let mut config = fake::config();
+        config.on_shutdown = ShutdownOffices::Delete;
+        let fakes = fake::state(config, vec![fake::room(5, 30)]);
+        let guild = FakeGuild::with_channels(&[30]);
+
+        close_offices(&guild, &fakes.state).await;
+
+        assert_eq!(guild.calls(), vec![Call::DeleteChannel(30)]);
+        assert!(fakes.state.rooms.read().await.is_empty());
+        assert!(fakes.store.0.lock().unwrap().is_empty());
+        assert!(fakes
+            .sent()
+            .await
+            .iter()
+            .any(|msg| matches!(msg, BotResponse::OfficeSession(_))));
+    }
+}
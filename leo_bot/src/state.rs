@@ -0,0 +1,465 @@
+use actix::Recipient;
+use chrono::{DateTime, Utc};
+use serenity::prelude::RwLock;
+use shared_lib::{
+    office::OfficeHours,
+    socket::{
+        health::GetLinkStatus,
+        message::{BotResponse, Traced},
+    },
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use crate::models::{Config, Room};
+
+/// Source of the current time
+pub(crate) trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Current unix timestamp in seconds
+    fn timestamp(&self) -> i64 {
+        self.now().timestamp()
+    }
+}
+
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Outbox keys remembered to skip the replays of the backend
+const MAX_APPLIED: usize = 1000;
+
+/// Persistence of the offices, applied commands, welcomed members and member groups, so a
+/// restart doesn't forget them
+pub(crate) trait Store: Send + Sync {
+    fn load_rooms(&self) -> Vec<Room>;
+    fn save_rooms(&self, rooms: &[Room]);
+    fn load_applied(&self) -> Vec<String>;
+    fn save_applied(&self, keys: &[String]);
+    fn load_welcomed(&self) -> HashMap<u64, i64>;
+    fn save_welcomed(&self, welcomed: &HashMap<u64, i64>);
+    fn load_groups(&self) -> HashMap<u64, Vec<String>>;
+    fn save_groups(&self, groups: &HashMap<u64, Vec<String>>);
+}
+
+/// Store keeping the offices, applied commands, welcomed members and member groups in JSON
+/// files
+pub(crate) struct JsonStore {
+    path: PathBuf,
+    applied_path: PathBuf,
+    welcomed_path: PathBuf,
+    groups_path: PathBuf,
+}
+
+impl JsonStore {
+    pub(crate) fn new(
+        path: impl Into<PathBuf>,
+        applied_path: impl Into<PathBuf>,
+        welcomed_path: impl Into<PathBuf>,
+        groups_path: impl Into<PathBuf>,
+    ) -> Self {
+        JsonStore {
+            path: path.into(),
+            applied_path: applied_path.into(),
+            welcomed_path: welcomed_path.into(),
+            groups_path: groups_path.into(),
+        }
+    }
+}
+
+fn load_json<T: serde::de::DeserializeOwned + Default>(path: &PathBuf) -> T {
+    File::open(path)
+        .ok()
+        .and_then(|file| serde_json::from_reader(file).ok())
+        .unwrap_or_default()
+}
+
+fn save_json<T: serde::Serialize + ?Sized>(path: &PathBuf, value: &T) {
+    let result = File::create(path)
+        .map_err(serde_json::Error::io)
+        .and_then(|file| serde_json::to_writer(file, value));
+
+    if let Err(e) = result {
+        tracing::error!("Couldn't save {:?}: {:?}", path, e);
+    }
+}
+
+impl Store for JsonStore {
+    fn load_rooms(&self) -> Vec<Room> {
+        load_json(&self.path)
+    }
+
+    fn save_rooms(&self, rooms: &[Room]) {
+        save_json(&self.path, rooms);
+    }
+
+    fn load_applied(&self) -> Vec<String> {
+        load_json(&self.applied_path)
+    }
+
+    fn save_applied(&self, keys: &[String]) {
+        save_json(&self.applied_path, keys);
+    }
+
+    fn load_welcomed(&self) -> HashMap<u64, i64> {
+        load_json(&self.welcomed_path)
+    }
+
+    fn save_welcomed(&self, welcomed: &HashMap<u64, i64>) {
+        save_json(&self.welcomed_path, welcomed);
+    }
+
+    fn load_groups(&self) -> HashMap<u64, Vec<String>> {
+        load_json(&self.groups_path)
+    }
+
+    fn save_groups(&self, groups: &HashMap<u64, Vec<String>>) {
+        save_json(&self.groups_path, groups);
+    }
+}
+
+/// Everything the actions share, injected instead of read from the serenity `TypeMap`
+#[derive(Clone)]
+pub(crate) struct BotState {
+    pub(crate) config: Arc<RwLock<Config>>,
+    pub(crate) rooms: Arc<RwLock<Vec<Room>>>,
+    pub(crate) schedule: Arc<RwLock<Vec<OfficeHours>>>,
+    /// Keys of the last outbox commands applied, oldest first
+    pub(crate) applied: Arc<RwLock<VecDeque<String>>>,
+    /// Unix timestamp of the welcome of the members restricted on arrival, until they verify
+    pub(crate) welcomed: Arc<RwLock<HashMap<u64, i64>>>,
+    /// ADFS groups of the verified members, for the subjects open to some groups only
+    pub(crate) groups: Arc<RwLock<HashMap<u64, Vec<String>>>>,
+    /// Socket client connected to the backend
+    pub(crate) client: Recipient<Traced<BotResponse>>,
+    /// Health of the socket, answered by the same client
+    pub(crate) link: Recipient<GetLinkStatus>,
+    pub(crate) store: Arc<dyn Store>,
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl BotState {
+    pub(crate) fn new(
+        config: Config,
+        client: Recipient<Traced<BotResponse>>,
+        link: Recipient<GetLinkStatus>,
+        store: Arc<dyn Store>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        BotState {
+            config: Arc::new(RwLock::new(config)),
+            rooms: Arc::new(RwLock::new(store.load_rooms())),
+            schedule: Arc::new(RwLock::new(Vec::new())),
+            applied: Arc::new(RwLock::new(store.load_applied().into())),
+            welcomed: Arc::new(RwLock::new(store.load_welcomed())),
+            groups: Arc::new(RwLock::new(store.load_groups())),
+            client,
+            link,
+            store,
+            clock,
+        }
+    }
+
+    /// Send a message to the backend as part of the current request, dropped when the socket
+    /// is down
+    pub(crate) fn send(&self, msg: BotResponse) {
+        if self.client.do_send(Traced::new(msg)).is_err() {
+            tracing::warn!("Socket client is gone, dropping message");
+        }
+    }
+
+    /// Whether the outbox command of `key` was already applied
+    pub(crate) async fn was_applied(&self, key: &str) -> bool {
+        self.applied
+            .read()
+            .await
+            .iter()
+            .any(|applied| applied == key)
+    }
+
+    /// Remember that the outbox command of `key` was applied
+    pub(crate) async fn record_applied(&self, key: &str) {
+        let mut applied = self.applied.write().await;
+
+        if applied.len() >= MAX_APPLIED {
+            applied.pop_front();
+        }
+        applied.push_back(key.to_string());
+        self.store.save_applied(applied.make_contiguous());
+    }
+
+    /// Remember that `user` was welcomed at `at`, the reminder and the kick are due from there
+    pub(crate) async fn record_welcome(&self, user: u64, at: i64) {
+        let mut welcomed = self.welcomed.write().await;
+
+        welcomed.insert(user, at);
+        self.store.save_welcomed(&welcomed);
+    }
+
+    /// Set the groups of `user`, or forget them when they lose their verification
+    pub(crate) async fn set_groups(&self, user: u64, groups: Option<Vec<String>>) {
+        let mut known = self.groups.write().await;
+
+        match groups {
+            Some(groups) => known.insert(user, groups),
+            None => known.remove(&user),
+        };
+        self.store.save_groups(&known);
+    }
+
+    /// Forget the welcome of members who verified, left or were kicked
+    pub(crate) async fn forget_welcomes(&self, users: &[u64]) {
+        let mut welcomed = self.welcomed.write().await;
+
+        let before = welcomed.len();
+        welcomed.retain(|user, _| !users.contains(user));
+        if welcomed.len() != before {
+            self.store.save_welcomed(&welcomed);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use actix::{Actor, Addr, Context, Handler, Message, MessageResult};
+    use chrono::{DateTime, TimeZone, Utc};
+    use shared_lib::socket::{
+        health::{GetLinkStatus, LinkStatus},
+        message::{BotResponse, Traced},
+    };
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{BotState, Clock, Store};
+    use crate::models::{Config, Room};
+
+    /// Clock stuck at a given time
+    pub(crate) struct FixedClock(pub(crate) Mutex<DateTime<Utc>>);
+
+    impl FixedClock {
+        pub(crate) fn at(timestamp: i64) -> Self {
+            FixedClock(Mutex::new(Utc.timestamp_opt(timestamp, 0).unwrap()))
+        }
+
+        pub(crate) fn advance(&self, seconds: i64) {
+            *self.0.lock().unwrap() += chrono::Duration::seconds(seconds);
+        }
+    }
+
+    impl Clock for FixedClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// Store keeping the rooms, applied commands, welcomed members and member groups in memory
+    pub(crate) struct MemoryStore(
+        pub(crate) Mutex<Vec<Room>>,
+        pub(crate) Mutex<Vec<String>>,
+        pub(crate) Mutex<HashMap<u64, i64>>,
+        pub(crate) Mutex<HashMap<u64, Vec<String>>>,
+    );
+
+    impl Store for MemoryStore {
+        fn load_rooms(&self) -> Vec<Room> {
+            self.0.lock().unwrap().clone()
+        }
+
+        fn save_rooms(&self, rooms: &[Room]) {
+            *self.0.lock().unwrap() = rooms.to_vec();
+        }
+
+        fn load_applied(&self) -> Vec<String> {
+            self.1.lock().unwrap().clone()
+        }
+
+        fn save_applied(&self, keys: &[String]) {
+            *self.1.lock().unwrap() = keys.to_vec();
+        }
+
+        fn load_welcomed(&self) -> HashMap<u64, i64> {
+            self.2.lock().unwrap().clone()
+        }
+
+        fn save_welcomed(&self, welcomed: &HashMap<u64, i64>) {
+            *self.2.lock().unwrap() = welcomed.clone();
+        }
+
+        fn load_groups(&self) -> HashMap<u64, Vec<String>> {
+            self.3.lock().unwrap().clone()
+        }
+
+        fn save_groups(&self, groups: &HashMap<u64, Vec<String>>) {
+            *self.3.lock().unwrap() = groups.clone();
+        }
+    }
+
+    /// Actor recording the messages sent to the backend
+    #[derive(Default)]
+    pub(crate) struct Backend(Vec<BotResponse>);
+
+    /// Ask the fake backend for the messages it received
+    ///
+    /// The mailbox is ordered, so the answer includes every message sent before
+    #[derive(Message)]
+    #[rtype(result = "Vec<BotResponse>")]
+    struct Received;
+
+    impl Actor for Backend {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Traced<BotResponse>> for Backend {
+        type Result = ();
+
+        fn handle(&mut self, msg: Traced<BotResponse>, _: &mut Context<Self>) {
+            self.0.push(msg.msg);
+        }
+    }
+
+    impl Handler<Received> for Backend {
+        type Result = MessageResult<Received>;
+
+        fn handle(&mut self, _: Received, _: &mut Context<Self>) -> Self::Result {
+            MessageResult(self.0.clone())
+        }
+    }
+
+    /// Link connected an hour before the fake clock
+    impl Handler<GetLinkStatus> for Backend {
+        type Result = MessageResult<GetLinkStatus>;
+
+        fn handle(&mut self, _: GetLinkStatus, _: &mut Context<Self>) -> Self::Result {
+            MessageResult(LinkStatus {
+                connected_at: 1_599_996_400,
+                last_heartbeat: 1_599_999_999,
+                rtt: Some(42),
+                sent: 5,
+                received: 3,
+            })
+        }
+    }
+
+    /// State made of in-memory fakes, with handles to inspect them
+    pub(crate) struct Fakes {
+        pub(crate) state: BotState,
+        backend: Addr<Backend>,
+        pub(crate) clock: Arc<FixedClock>,
+        pub(crate) store: Arc<MemoryStore>,
+    }
+
+    impl Fakes {
+        /// Messages received by the fake backend so far
+        pub(crate) async fn sent(&self) -> Vec<BotResponse> {
+            self.backend.send(Received).await.unwrap()
+        }
+    }
+
+    /// Must be called inside an actix system
+    pub(crate) fn state(config: Config, rooms: Vec<Room>) -> Fakes {
+        let clock = Arc::new(FixedClock::at(1_600_000_000));
+        let store = Arc::new(MemoryStore(
+            Mutex::new(rooms),
+            Mutex::default(),
+            Mutex::default(),
+            Mutex::default(),
+        ));
+
+        let backend = Backend::default().start();
+        let state = BotState::new(
+            config,
+            backend.clone().recipient(),
+            backend.clone().recipient(),
+            store.clone(),
+            clock.clone(),
+        );
+
+        Fakes {
+            state,
+            backend,
+            clock,
+            store,
+        }
+    }
+
+    /// Office `office` of `teacher`, opened at the start of the fake clock
+    pub(crate) fn room(teacher: u64, office: u64) -> Room {
+        serde_json::from_value(serde_json::json!({
+            "discord_id": teacher,
+            "office_id": office,
+            "waiting_id": 0,
+            "text_id": 0,
+            "opened_at": 1_600_000_000,
+        }))
+        .unwrap()
+    }
+
+    /// Minimal configuration with a teacher role and one subject
+    pub(crate) fn config() -> Config {
+        serde_json::from_str(
+            r#"{
+                "guild": 1,
+                "roles": {"teacher": 2, "verified": 3, "a1": 4},
+                "room": 10,
+                "teacher_category": 11,
+                "subjects": [{"id": 20, "channels": {"💛": 21}, "year": 1}]
+            }"#,
+        )
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake;
+    use shared_lib::socket::message::BotResponse;
+
+    #[actix_rt::test]
+    async fn rooms_survive_restarts() {
+        let fakes = fake::state(fake::config(), vec![fake::room(1, 2)]);
+
+        let rooms = fakes.state.rooms.read().await;
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].office_id, 2);
+
+        fakes.state.store.save_rooms(&[]);
+        assert!(fakes.store.0.lock().unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn replays_are_skipped() {
+        let fakes = fake::state(fake::config(), Vec::new());
+
+        fakes.state.record_applied("a").await;
+        fakes.state.record_applied("b").await;
+
+        assert!(fakes.state.was_applied("a").await);
+        assert!(!fakes.state.was_applied("c").await);
+        assert_eq!(*fakes.store.1.lock().unwrap(), vec!["a", "b"]);
+    }
+
+    #[actix_rt::test]
+    async fn clock_and_backend() {
+        let fakes = fake::state(fake::config(), Vec::new());
+
+        fakes.clock.advance(60);
+        assert_eq!(fakes.state.clock.timestamp(), 1_600_000_060);
+
+        fakes.state.send(BotResponse::GetOfficeHours);
+        assert!(matches!(
+            fakes.sent().await.as_slice(),
+            [BotResponse::GetOfficeHours]
+        ));
+    }
+}
@@ -0,0 +1,195 @@
+use shared_lib::audit::{AuditAction, AuditEvent};
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    actions::office::delete_office,
+    audit::log_event,
+    discord::Discord,
+    models::{OfficeTimeouts, Room},
+    state::BotState,
+};
+
+/// Sweep the offices periodically, never returns
+pub(crate) async fn run(discord: Arc<dyn Discord>, state: BotState) {
+    let sweep_interval = state.config.read().await.office.sweep_interval;
+    let mut interval = tokio::time::interval(Duration::from_secs(sweep_interval.max(1)));
+
+    loop {
+        interval.tick().await;
+        sweep(discord.as_ref(), &state).await;
+    }
+}
+
+/// Why `room` must be deleted, if it must
+///
+/// `teacher_channel` is the voice channel the teacher is in
+fn close_reason(
+    room: &Room,
+    teacher_channel: Option<u64>,
+    now: i64,
+    timeouts: &OfficeTimeouts,
+) -> Option<&'static str> {
+    let elapsed = |since: Option<i64>| since.map(|t| (now - t).max(0) as u64);
+
+    match (teacher_channel, elapsed(room.teacher_left_at)) {
+        (None, Some(gone)) if gone >= timeouts.grace_period => return Some("teacher left"),
+        (Some(_), Some(gone)) if gone >= timeouts.away => return Some("teacher away"),
+        _ => (),
+    }
+
+    match elapsed(room.empty_since) {
+        Some(empty) if empty >= timeouts.idle => Some("idle"),
+        _ => None,
+    }
+}
+
+/// Delete the offices abandoned by their teacher or left empty for too long
+///
+/// The state of the rooms is refreshed from the cache first, so the events
+/// missed while the bot was disconnected are caught up
+pub(crate) async fn sweep(discord: &dyn Discord, state: &BotState) {
+    let config = state.config.read().await;
+
+    let guild = (
+        discord.voice_states(config.guild).await,
+        discord.channels(config.guild).await,
+    );
+    let (voice, channels) = match guild {
+        (Some(voice), Some(channels)) => (voice, channels),
+        _ => return,
+    };
+
+    let now = state.clock.timestamp();
+    let mut rooms = state.rooms.write().await;
+    let mut closed = Vec::new();
+
+    // Scheduled offices are handled by the scheduler
+    for room in rooms.iter_mut().filter(|r| r.schedule.is_none()) {
+        if !channels.contains(&room.office_id) {
+            closed.push((room.discord_id, room.office_id, "deleted"));
+            continue;
+        }
+
+        let teacher_channel = voice.get(&room.discord_id).copied();
+        match teacher_channel {
+            Some(channel) if channel == room.office_id => room.teacher_left_at = None,
+            _ => {
+                room.teacher_left_at.get_or_insert(now);
+            }
+        }
+        match voice.values().any(|c| *c == room.office_id) {
+            true => room.empty_since = None,
+            false => {
+                room.empty_since.get_or_insert(now);
+            }
+        }
+
+        if let Some(reason) = close_reason(room, teacher_channel, now, &config.office) {
+            if delete_office(discord, state, room).await.is_ok() {
+                closed.push((room.discord_id, room.office_id, reason));
+            }
+        }
+    }
+
+    rooms.retain(|r| !closed.iter().any(|(_, office, _)| *office == r.office_id));
+    if !closed.is_empty() {
+        state.store.save_rooms(&rooms);
+    }
+    drop(rooms);
+    drop(config);
+
+    for (teacher, office, reason) in closed {
+        let event = AuditEvent::new(teacher, office, AuditAction::OfficeClosed, reason);
+        log_event(discord, state, event).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{close_reason, sweep};
+    use crate::{discord::fake::FakeGuild, models::OfficeTimeouts, state::fake};
+
+    const NOW: i64 = 1_600_001_000;
+
+    #[test]
+    fn teachers_get_a_grace_period() {
+        let timeouts = OfficeTimeouts::default();
+        let mut room = fake::room(5, 30);
+
+        room.teacher_left_at = Some(NOW - 59);
+        assert_eq!(close_reason(&room, None, NOW, &timeouts), None);
+
+        room.teacher_left_at = Some(NOW - 60);
+        assert_eq!(
+            close_reason(&room, None, NOW, &timeouts),
+            Some("teacher left")
+        );
+    }
+
+    #[test]
+    fn teachers_away_keep_their_office_longer() {
+        let timeouts = OfficeTimeouts::default();
+        let mut room = fake::room(5, 30);
+
+        // Still connected in another channel, the grace period doesn't apply
+        room.teacher_left_at = Some(NOW - 60);
+        assert_eq!(close_reason(&room, Some(40), NOW, &timeouts), None);
+
+        room.teacher_left_at = Some(NOW - 600);
+        assert_eq!(
+            close_reason(&room, Some(40), NOW, &timeouts),
+            Some("teacher away")
+        );
+    }
+
+    #[test]
+    fn empty_offices_close_when_idle() {
+        let timeouts = OfficeTimeouts::default();
+        let mut room = fake::room(5, 30);
+
+        room.empty_since = Some(NOW - 899);
+        assert_eq!(close_reason(&room, Some(30), NOW, &timeouts), None);
+
+        room.empty_since = Some(NOW - 900);
+        assert_eq!(close_reason(&room, Some(30), NOW, &timeouts), Some("idle"));
+
+        // A clock going backwards never closes an office
+        room.empty_since = Some(NOW + 900);
+        assert_eq!(close_reason(&room, Some(30), NOW, &timeouts), None);
+    }
+
+    #[actix_rt::test]
+    async fn abandoned_offices_are_deleted() {
+        let fakes = fake::state(fake::config(), vec![fake::room(5, 30), fake::room(6, 31)]);
+        let guild = FakeGuild::with_channels(&[30, 31]);
+        guild.connect(6, Some(31));
+
+        // The first sweep notices the teacher is gone, the next one after the grace period closes
+        sweep(&guild, &fakes.state).await;
+        assert_eq!(fakes.state.rooms.read().await.len(), 2);
+
+        fakes.clock.advance(60);
+        sweep(&guild, &fakes.state).await;
+
+        let rooms = fakes.state.rooms.read().await;
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].discord_id, 6);
+        assert!(!guild.guild().channels.contains_key(&30));
+        assert_eq!(fakes.store.0.lock().unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn scheduled_offices_are_left_to_the_scheduler() {
+        let mut room = fake::room(5, 30);
+        room.schedule = Some(4);
+        let fakes = fake::state(fake::config(), vec![room]);
+        let guild = FakeGuild::with_channels(&[30]);
+
+        sweep(&guild, &fakes.state).await;
+        fakes.clock.advance(3600);
+        sweep(&guild, &fakes.state).await;
+
+        assert_eq!(fakes.state.rooms.read().await.len(), 1);
+        assert!(guild.guild().channels.contains_key(&30));
+    }
+}
@@ -1,25 +1,140 @@
-use std::{path::Path, process::Command};
-
-const FRONTEND_DIR: &str = "../frontend";
-
 fn main() {
-    println!("cargo:rerun-if-changed={}/src", FRONTEND_DIR);
+    println!("cargo:rerun-if-changed=build.rs");
 
-    build_frontend(FRONTEND_DIR);
+    // Without the feature the frontend is served from `FRONT_PATH`, built on its own
+    #[cfg(feature = "embed-frontend")]
+    embed::run();
 }
 
-fn build_frontend<P: AsRef<Path>>(source: P) {
-    if cfg!(target_os = "windows") {
-        Command::new("cmd")
-            .args(["/C", "npm", "run", "build"])
-            .current_dir(source.as_ref())
-            .status()
-            .expect("Failed to build frontend");
-    } else {
-        Command::new("sh")
-            .args(["-c", "npm", "run", "build"])
+#[cfg(feature = "embed-frontend")]
+mod embed {
+    use std::{
+        env,
+        fmt::Write as _,
+        fs,
+        io::Write,
+        path::{Path, PathBuf},
+        process::Command,
+    };
+
+    const FRONTEND_DIR: &str = "../frontend";
+
+    pub fn run() {
+        println!("cargo:rerun-if-env-changed=FRONTEND_ASSETS");
+
+        // Assets built beforehand, by a CI step for instance, don't need Node here
+        let assets = match env::var_os("FRONTEND_ASSETS") {
+            Some(assets) => PathBuf::from(assets),
+            None => {
+                println!("cargo:rerun-if-changed={}/src", FRONTEND_DIR);
+                build_frontend(FRONTEND_DIR);
+                Path::new(FRONTEND_DIR).join("build")
+            }
+        };
+
+        write_assets(&assets);
+    }
+
+    fn build_frontend<P: AsRef<Path>>(source: P) {
+        // npm is a batch script on windows, `Command` needs its full name
+        let npm = if cfg!(target_os = "windows") {
+            "npm.cmd"
+        } else {
+            "npm"
+        };
+
+        match Command::new(npm)
+            .args(["run", "build"])
             .current_dir(source.as_ref())
             .status()
-            .expect("Failed to build frontend");
-    };
+        {
+            Ok(status) if status.success() => (),
+            Ok(status) => panic!("Failed to build frontend: npm exited with {}", status),
+            Err(e) => panic!(
+                "Failed to build frontend, install Node or set FRONTEND_ASSETS to prebuilt assets: {}",
+                e
+            ),
+        }
+    }
+
+    /// Write the assets and their compressed variants to `OUT_DIR`, with the table the
+    /// backend includes
+    fn write_assets(assets: &Path) {
+        println!("cargo:rerun-if-changed={}", assets.display());
+
+        let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+        let mut files = Vec::new();
+        list_files(assets, &mut files);
+        files.sort();
+        assert!(
+            files.iter().any(|file| file.ends_with("index.html")),
+            "No index.html in the frontend assets {}",
+            assets.display()
+        );
+
+        let mut table = String::from("static ASSETS: &[Asset] = &[\n");
+        for file in files {
+            let path = file.strip_prefix(assets).unwrap();
+            let url = path.to_string_lossy().replace('\\', "/");
+            let raw = fs::read(&file).expect("frontend asset");
+            let compressed = out.join("frontend").join(path);
+            fs::create_dir_all(compressed.parent().unwrap()).unwrap();
+
+            let gzip = variant(&compressed, "gz", &raw, gzip(&raw));
+            let brotli = variant(&compressed, "br", &raw, brotli(&raw));
+            writeln!(
+                table,
+                "    Asset {{ path: {:?}, raw: include_bytes!({:?}), gzip: {}, brotli: {} }},",
+                url,
+                fs::canonicalize(&file).unwrap(),
+                gzip,
+                brotli
+            )
+            .unwrap();
+        }
+        table.push_str("];\n");
+
+        fs::write(out.join("frontend_assets.rs"), table).unwrap();
+    }
+
+    fn list_files(dir: &Path, files: &mut Vec<PathBuf>) {
+        for entry in fs::read_dir(dir).expect("frontend assets directory") {
+            let path = entry.unwrap().path();
+
+            if path.is_dir() {
+                list_files(&path, files);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    /// Keep a compressed variant only when it's smaller, images are already compressed
+    fn variant(path: &Path, extension: &str, raw: &[u8], compressed: Vec<u8>) -> String {
+        if compressed.len() >= raw.len() {
+            return "None".to_string();
+        }
+
+        let mut path = path.as_os_str().to_owned();
+        path.push(".");
+        path.push(extension);
+        fs::write(&path, compressed).unwrap();
+
+        format!("Some(include_bytes!({:?}))", PathBuf::from(path))
+    }
+
+    fn gzip(raw: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(raw).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn brotli(raw: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        {
+            let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+            encoder.write_all(raw).unwrap();
+        }
+        compressed
+    }
 }
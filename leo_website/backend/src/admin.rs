@@ -0,0 +1,463 @@
+use actix::Addr;
+use actix_session::Session;
+use actix_web::{
+    delete,
+    error::{
+        ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized,
+    },
+    get, post, put,
+    web::{self, Data},
+    HttpResponse,
+};
+use rbatis::{crud::CRUD, plugin::page::PageRequest, rbatis::Rbatis};
+use serde::Deserialize;
+use shared_lib::{
+    audit::{timestamp, AuditAction, AuditEvent},
+    category::Category,
+    socket::{health::GetStatus, message::ServerRequest, server::Server},
+};
+use std::sync::Arc;
+
+use crate::{
+    audit,
+    models::{DevinciType, DevinciUser},
+    onboarding::{fetch_user, unlink, DISCORD_ID},
+    outbox, rollover,
+};
+
+/// Biggest page an admin can request
+pub(crate) const MAX_PAGE_SIZE: u64 = 100;
+
+/// Discord ids holding the admin role on the guild
+pub struct Admins(Vec<u64>);
+
+impl Admins {
+    pub fn new(ids: Vec<u64>) -> Self {
+        Admins(ids)
+    }
+
+    pub(crate) fn contains(&self, discord_id: u64) -> bool {
+        self.0.contains(&discord_id)
+    }
+}
+
+#[derive(Deserialize)]
+struct UserQuery {
+    search: Option<String>,
+    /// Only keep the members of this ADFS group
+    group: Option<String>,
+    page: Option<u64>,
+    size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct FuncUpdate {
+    func: u8,
+}
+
+#[derive(Deserialize)]
+struct CategoryUpdate {
+    category: Category,
+}
+
+/// The `groups` column with a comma on both ends, so that every group is surrounded by them
+const GROUPS_LIST: &str = "concat(',', groups, ',')";
+
+/// Match `group` alone in `GROUPS_LIST`, `like` adds the `%` around it
+fn group_pattern(group: &str) -> String {
+    let escaped = group
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!(",{},", escaped)
+}
+
+/// Return the discord id of the logged admin, one of `ADMIN_IDS` or a verified teacher
+pub(crate) async fn require_admin(
+    session: &Session,
+    rb: &Rbatis,
+    admins: &Admins,
+) -> actix_web::Result<u64> {
+    let discord_id = session
+        .get::<u64>(DISCORD_ID)?
+        .ok_or_else(|| ErrorUnauthorized("not logged in"))?;
+
+    if admins.contains(discord_id) {
+        return Ok(discord_id);
+    }
+
+    match is_teacher(rb, discord_id).await? {
+        true => Ok(discord_id),
+        false => Err(ErrorForbidden("admin only")),
+    }
+}
+
+/// Whether the user is a verified teacher
+pub(crate) async fn is_teacher(rb: &Rbatis, discord_id: u64) -> actix_web::Result<bool> {
+    let user = fetch_user(rb, discord_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(matches!(
+        user,
+        Some(user) if user.verified && matches!(DevinciType::from(user.func), DevinciType::Professor)
+    ))
+}
+
+async fn require_user(rb: &Rbatis, discord_id: u64) -> actix_web::Result<DevinciUser> {
+    fetch_user(rb, discord_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("unknown user"))
+}
+
+async fn update_user(rb: &Rbatis, user: &DevinciUser) -> actix_web::Result<()> {
+    rb.update_by_column("discord_id", user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(())
+}
+
+/// Ask the bot to align the member's roles with the stored user
+///
+/// `previous` is the category the current roles were granted for
+pub(crate) async fn sync_roles(
+    rb: &Rbatis,
+    server: &Addr<Server>,
+    user: &DevinciUser,
+    previous: Category,
+) {
+    let request = match user.verified {
+        true => ServerRequest::Verify {
+            discord_id: user.discord_id,
+            category: user.category(),
+            previous: Some(previous),
+            groups: user.group_list(),
+        },
+        false => ServerRequest::Unverify {
+            discord_id: user.discord_id,
+            category: previous,
+        },
+    };
+
+    outbox::enqueue(rb, server, request).await;
+}
+
+#[get("/users")]
+async fn list_users(
+    query: web::Query<UserQuery>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+) -> actix_web::Result<HttpResponse> {
+    require_admin(&session, &rb, &admins).await?;
+
+    let mut wrapper = rb.new_wrapper();
+    if let Some(search) = &query.search {
+        wrapper = wrapper
+            .push_sql("(")
+            .like("first_name", search)
+            .or()
+            .like("last_name", search)
+            .or()
+            .like("mail", search)
+            .push_sql(")");
+    }
+    if let Some(group) = &query.group {
+        wrapper = wrapper.like(GROUPS_LIST, group_pattern(group));
+    }
+    let wrapper = wrapper.order_by(true, &["last_name", "first_name"]);
+
+    let page = PageRequest::new(
+        query.page.unwrap_or(1).max(1),
+        query.size.unwrap_or(20).clamp(1, MAX_PAGE_SIZE),
+    );
+    let users = rb
+        .fetch_page_by_wrapper::<DevinciUser>(wrapper, &page)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[get("/users/{id}")]
+async fn get_user(
+    path: web::Path<u64>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+) -> actix_web::Result<HttpResponse> {
+    require_admin(&session, &rb, &admins).await?;
+
+    let user = require_user(&rb, path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[post("/users/{id}/verify")]
+async fn verify_user(
+    path: web::Path<u64>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let actor = require_admin(&session, &rb, &admins).await?;
+
+    let mut user = require_user(&rb, path.into_inner()).await?;
+    user.verified = true;
+    update_user(&rb, &user).await?;
+    sync_roles(&rb, &server, &user, user.category()).await;
+
+    audit::record(
+        &rb,
+        AuditEvent::new(actor, user.discord_id, AuditAction::UserVerified, ""),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[post("/users/{id}/unverify")]
+async fn unverify_user(
+    path: web::Path<u64>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let actor = require_admin(&session, &rb, &admins).await?;
+
+    let mut user = require_user(&rb, path.into_inner()).await?;
+    user.verified = false;
+    update_user(&rb, &user).await?;
+    sync_roles(&rb, &server, &user, user.category()).await;
+
+    audit::record(
+        &rb,
+        AuditEvent::new(actor, user.discord_id, AuditAction::UserUnverified, ""),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[put("/users/{id}/func")]
+async fn update_func(
+    path: web::Path<u64>,
+    body: web::Json<FuncUpdate>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let actor = require_admin(&session, &rb, &admins).await?;
+    let func = DevinciType::parse(body.func).ok_or_else(|| ErrorBadRequest("unknown func"))?;
+
+    let mut user = require_user(&rb, path.into_inner()).await?;
+    let details = format!("{} -> {}", user.func, body.func);
+    let previous = user.category();
+    let mut category = previous.clone();
+    func.apply_to(&mut category);
+    user.set_category(category);
+    user.func_updated_at = timestamp();
+    update_user(&rb, &user).await?;
+    sync_roles(&rb, &server, &user, previous).await;
+
+    audit::record(
+        &rb,
+        AuditEvent::new(actor, user.discord_id, AuditAction::FuncUpdated, details),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[put("/users/{id}/category")]
+async fn update_category(
+    path: web::Path<u64>,
+    body: web::Json<CategoryUpdate>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let actor = require_admin(&session, &rb, &admins).await?;
+
+    let mut user = require_user(&rb, path.into_inner()).await?;
+    let previous = user.category();
+    let details = format!("{:?} -> {:?}", previous, body.category);
+    user.set_category(body.into_inner().category);
+    user.func_updated_at = timestamp();
+    update_user(&rb, &user).await?;
+    sync_roles(&rb, &server, &user, previous).await;
+
+    audit::record(
+        &rb,
+        AuditEvent::new(actor, user.discord_id, AuditAction::FuncUpdated, details),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[post("/users/{id}/resync")]
+async fn resync_user(
+    path: web::Path<u64>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let actor = require_admin(&session, &rb, &admins).await?;
+
+    let user = require_user(&rb, path.into_inner()).await?;
+    sync_roles(&rb, &server, &user, user.category()).await;
+
+    audit::record(
+        &rb,
+        AuditEvent::new(actor, user.discord_id, AuditAction::RolesResynced, ""),
+    )
+    .await;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Unlink the accounts and erase the stored personal data
+#[delete("/users/{id}")]
+async fn delete_user(
+    path: web::Path<u64>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let actor = require_admin(&session, &rb, &admins).await?;
+
+    match unlink(&rb, &server, actor, path.into_inner()).await? {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(ErrorNotFound("unknown user")),
+    }
+}
+
+/// State of the socket of every connected bot
+#[get("/status")]
+async fn link_status(
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    require_admin(&session, &rb, &admins).await?;
+
+    let sessions = server
+        .send(GetStatus)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// Register every admin route under `/api/admin`
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api/admin")
+            .service(list_users)
+            .service(get_user)
+            .service(verify_user)
+            .service(unverify_user)
+            .service(update_func)
+            .service(update_category)
+            .service(resync_user)
+            .service(delete_user)
+            .service(link_status)
+            .configure(rollover::configure)
+            .configure(outbox::configure)
+            .configure(audit::configure),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{configure, group_pattern, Admins};
+    use crate::onboarding::DISCORD_ID;
+    use actix::Actor;
+    use actix_session::{CookieSession, Session};
+    use actix_web::{
+        http::{header, StatusCode},
+        test::{call_service, init_service, TestRequest},
+        web::{self, Data},
+        App, HttpResponse,
+    };
+    use rbatis::rbatis::Rbatis;
+    use serde_json::json;
+    use shared_lib::socket::server::Server;
+    use std::sync::Arc;
+
+    const ADMIN: u64 = 1;
+    const TEACHER: u64 = 2;
+
+    #[test]
+    fn groups_match_whole() {
+        assert_eq!(group_pattern("A1"), ",A1,");
+        assert_eq!(group_pattern("etu_a1%"), r",etu\_a1\%,");
+        assert!(!",A10,TDA1,".contains(&group_pattern("A1")));
+        assert!(",A10,A1,".contains(&group_pattern("A1")));
+    }
+
+    async fn log_in(session: Session, id: web::Path<u64>) -> actix_web::Result<HttpResponse> {
+        session.insert(DISCORD_ID, id.into_inner())?;
+
+        Ok(HttpResponse::Ok().finish())
+    }
+
+    #[actix_web::test]
+    async fn admins_are_listed_or_teachers() {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(Admins::new(vec![ADMIN])))
+                .app_data(Data::new(Arc::new(Rbatis::new())))
+                .app_data(Data::new(Server::default().start()))
+                .wrap(CookieSession::signed(&[0; 32]).secure(false))
+                .route("/test/login/{id}", web::get().to(log_in))
+                .configure(configure),
+        )
+        .await;
+        let mut cookies = Vec::new();
+        for user in [ADMIN, TEACHER] {
+            let login = TestRequest::get().uri(&format!("/test/login/{}", user));
+            let logged = call_service(&app, login.to_request()).await;
+            cookies.push(logged.headers().get(header::SET_COOKIE).unwrap().clone());
+        }
+        let (admin, teacher) = (Some(&cookies[0]), Some(&cookies[1]));
+        let delete = || TestRequest::delete().uri("/api/admin/users/3");
+        let func = |func: u8| {
+            TestRequest::put()
+                .uri("/api/admin/users/3/func")
+                .set_json(json!({ "func": func }))
+        };
+
+        // The database isn't reachable, it was reached when it's a 500: the listed admins
+        // pass without it, the other members are looked up as teachers
+        let cases = vec![
+            (None, delete(), StatusCode::UNAUTHORIZED),
+            (teacher, delete(), StatusCode::INTERNAL_SERVER_ERROR),
+            (teacher, func(42), StatusCode::INTERNAL_SERVER_ERROR),
+            (admin, delete(), StatusCode::INTERNAL_SERVER_ERROR),
+            (admin, func(42), StatusCode::BAD_REQUEST),
+            (admin, func(1), StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+
+        for (i, (cookie, request, expected)) in cases.into_iter().enumerate() {
+            let request = match cookie {
+                Some(cookie) => request.insert_header((header::COOKIE, cookie.clone())),
+                None => request,
+            };
+            let response = call_service(&app, request.to_request()).await;
+
+            assert_eq!(response.status(), expected, "case {}", i);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use actix_session::Session;
+use actix_web::{
+    error::ErrorInternalServerError,
+    get,
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    web::{self, Data},
+    HttpResponse,
+};
+use rbatis::{
+    crud::{Skip, CRUD},
+    plugin::page::PageRequest,
+    rbatis::Rbatis,
+    wrapper::Wrapper,
+};
+use serde::Deserialize;
+use shared_lib::audit::{AuditAction, AuditEvent};
+use std::sync::Arc;
+
+use crate::{
+    admin::{require_admin, Admins, MAX_PAGE_SIZE},
+    models::AuditEntry,
+};
+
+/// Biggest amount of rows put in a CSV export
+const MAX_EXPORT_ROWS: u64 = 10_000;
+
+impl From<AuditEvent> for AuditEntry {
+    fn from(event: AuditEvent) -> Self {
+        AuditEntry {
+            id: None,
+            actor: event.actor,
+            target: event.target,
+            action: event.action.as_str().to_string(),
+            details: event.details,
+            created_at: event.timestamp,
+        }
+    }
+}
+
+/// Persist an event in the audit table
+///
+/// A failure is only logged, it must never cancel the action itself
+pub async fn record(rb: &Rbatis, event: AuditEvent) {
+    let entry = AuditEntry::from(event);
+
+    if let Err(e) = rb.save(&entry, &[Skip::Column("id")]).await {
+        tracing::error!("Couldn't record audit entry {:?}: {}", entry, e);
+    }
+}
+
+/// Stands for an erased user in the audit log and the office sessions
+pub(crate) const ERASED: u64 = 0;
+
+impl AuditEntry {
+    /// Drop what the entry says about `discord_id`, keeping that something happened
+    fn pseudonymize(&mut self, discord_id: u64) {
+        if self.actor == discord_id {
+            self.actor = ERASED;
+        }
+        if self.target == discord_id {
+            self.target = ERASED;
+        }
+        self.details.clear();
+    }
+}
+
+/// Pseudonymize every entry about `discord_id`, part of the erasure of a user
+pub async fn erase(rb: &Rbatis, discord_id: u64) -> rbatis::Result<()> {
+    let wrapper = rb
+        .new_wrapper()
+        .eq("actor", discord_id)
+        .or()
+        .eq("target", discord_id);
+
+    for mut entry in rb.fetch_list_by_wrapper::<AuditEntry>(wrapper).await? {
+        entry.pseudonymize(discord_id);
+        rb.update_by_column("id", &entry).await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    actor: Option<u64>,
+    target: Option<u64>,
+    action: Option<AuditAction>,
+    /// Unix timestamp in seconds, inclusive
+    from: Option<i64>,
+    /// Unix timestamp in seconds, inclusive
+    to: Option<i64>,
+    page: Option<u64>,
+    size: Option<u64>,
+}
+
+impl AuditQuery {
+    fn wrapper(&self, rb: &Rbatis) -> Wrapper {
+        let mut wrapper = rb.new_wrapper();
+
+        if let Some(actor) = self.actor {
+            wrapper = wrapper.eq("actor", actor);
+        }
+        if let Some(target) = self.target {
+            wrapper = wrapper.eq("target", target);
+        }
+        if let Some(action) = self.action {
+            wrapper = wrapper.eq("action", action.as_str());
+        }
+        if let Some(from) = self.from {
+            wrapper = wrapper.ge("created_at", from);
+        }
+        if let Some(to) = self.to {
+            wrapper = wrapper.le("created_at", to);
+        }
+
+        wrapper.order_by(false, &["created_at"])
+    }
+}
+
+/// Quote a CSV field when needed
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_csv(entries: &[AuditEntry]) -> String {
+    let mut csv = String::from("created_at,actor,target,action,details\n");
+
+    for entry in entries {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            entry.created_at,
+            entry.actor,
+            entry.target,
+            csv_field(&entry.action),
+            csv_field(&entry.details)
+        ));
+    }
+
+    csv
+}
+
+#[get("/audit")]
+async fn list_events(
+    query: web::Query<AuditQuery>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+) -> actix_web::Result<HttpResponse> {
+    require_admin(&session, &rb, &admins).await?;
+
+    let page = PageRequest::new(
+        query.page.unwrap_or(1).max(1),
+        query.size.unwrap_or(20).clamp(1, MAX_PAGE_SIZE),
+    );
+    let events = rb
+        .fetch_page_by_wrapper::<AuditEntry>(query.wrapper(&rb), &page)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(events))
+}
+
+#[get("/audit/export")]
+async fn export_events(
+    query: web::Query<AuditQuery>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+) -> actix_web::Result<HttpResponse> {
+    require_admin(&session, &rb, &admins).await?;
+
+    let entries = rb
+        .fetch_list_by_wrapper::<AuditEntry>(query.wrapper(&rb).limit(MAX_EXPORT_ROWS))
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok()
+        .append_header((CONTENT_TYPE, "text/csv; charset=utf-8"))
+        .append_header((CONTENT_DISPOSITION, "attachment; filename=\"audit.csv\""))
+        .body(to_csv(&entries)))
+}
+
+/// Register the audit routes, they are mounted inside the admin scope
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_events).service(export_events);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ERASED;
+    use crate::models::AuditEntry;
+
+    fn entry(actor: u64, target: u64) -> AuditEntry {
+        AuditEntry {
+            id: Some(1),
+            actor,
+            target,
+            action: "user_verified".to_string(),
+            details: "jean.marchand@edu.devinci.fr".to_string(),
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn erased_users_leave_no_trace() {
+        let mut own = entry(7, 7);
+        own.pseudonymize(7);
+        let mut by_admin = entry(1, 7);
+        by_admin.pseudonymize(7);
+
+        assert_eq!((own.actor, own.target), (ERASED, ERASED));
+        assert_eq!((by_admin.actor, by_admin.target), (1, ERASED));
+        assert!(own.details.is_empty() && by_admin.details.is_empty());
+        assert_eq!(own.action, "user_verified");
+    }
+}
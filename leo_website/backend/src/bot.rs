@@ -0,0 +1,222 @@
+use actix::{
+    fut::WrapFuture, Actor, ActorFutureExt, Addr, Context, Handler, Message, ResponseActFuture,
+};
+use futures::future::LocalBoxFuture;
+use rbatis::rbatis::Rbatis;
+use shared_lib::{
+    office::{HoursChange, OfficeStats},
+    socket::{
+        message::{bot_response, BotResponse, ServerRequest, Traced},
+        router::Variant,
+        server::Server,
+    },
+    trace,
+};
+use std::{fmt::Debug, sync::Arc};
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
+use tracing::Instrument;
+
+use crate::{
+    admin::sync_roles,
+    audit, office_hours,
+    office_stats::{self, StatsQuery},
+    onboarding::fetch_user,
+    outbox,
+};
+
+/// Actor handling the messages sent by the bot over the socket
+pub struct BotHandler {
+    rb: Arc<Rbatis>,
+    server: Addr<Server>,
+    /// Read by every message in flight, written to wait for them on shutdown
+    in_flight: Arc<RwLock<()>>,
+    drained: Option<OwnedRwLockWriteGuard<()>>,
+}
+
+impl BotHandler {
+    pub fn new(rb: Arc<Rbatis>, server: Addr<Server>) -> Self {
+        BotHandler {
+            rb,
+            server,
+            in_flight: Arc::new(RwLock::new(())),
+            drained: None,
+        }
+    }
+}
+
+/// Wait for the messages in flight, the ones received afterwards are dropped
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Drain;
+
+impl Actor for BotHandler {
+    type Context = Context<Self>;
+}
+
+/// Message of the bot handled by the backend, each is routed to `BotHandler` by its variant
+pub trait BotMessage: Variant<BotResponse> + Debug {
+    /// Apply the message, the answers are sent to `server`
+    fn handle(self, rb: Arc<Rbatis>, server: Addr<Server>) -> LocalBoxFuture<'static, ()>;
+}
+
+impl<M: BotMessage> Handler<Traced<M>> for BotHandler {
+    type Result = ();
+
+    fn handle(&mut self, msg: Traced<M>, _: &mut Context<Self>) {
+        let rb = self.rb.clone();
+        let server = self.server.clone();
+        let guard = match self.in_flight.clone().try_read_owned() {
+            Ok(guard) if self.drained.is_none() => guard,
+            _ => {
+                tracing::warn!("Shutting down, dropping {:?}", msg.msg);
+                return;
+            }
+        };
+        let span = msg.span();
+        let Traced { request_id, msg } = msg;
+
+        // The answers are sent as part of the request of the message
+        let handled = async move {
+            let _guard = guard;
+
+            msg.handle(rb, server).await
+        };
+        actix::spawn(trace::scope(request_id, handled).instrument(span));
+    }
+}
+
+impl BotMessage for bot_response::Audit {
+    fn handle(self, rb: Arc<Rbatis>, _: Addr<Server>) -> LocalBoxFuture<'static, ()> {
+        Box::pin(async move { audit::record(&rb, self.0).await })
+    }
+}
+
+impl BotMessage for bot_response::GetOfficeHours {
+    fn handle(self, rb: Arc<Rbatis>, server: Addr<Server>) -> LocalBoxFuture<'static, ()> {
+        Box::pin(async move { office_hours::push_all(&rb, &server).await })
+    }
+}
+
+impl BotMessage for bot_response::AddOfficeHours {
+    fn handle(self, rb: Arc<Rbatis>, server: Addr<Server>) -> LocalBoxFuture<'static, ()> {
+        Box::pin(async move {
+            let result = office_hours::add(&rb, &server, self.hours).await;
+            if let Err(e) = &result {
+                tracing::error!("Couldn't add office hours: {}", e);
+            }
+            server.do_send(Traced::new(ServerRequest::OfficeHoursChanged {
+                channel: self.channel,
+                message: self.message,
+                change: HoursChange::Added,
+                result,
+            }));
+        })
+    }
+}
+
+impl BotMessage for bot_response::RemoveOfficeHours {
+    fn handle(self, rb: Arc<Rbatis>, server: Addr<Server>) -> LocalBoxFuture<'static, ()> {
+        let id = self.id;
+
+        Box::pin(async move {
+            let result = match office_hours::remove(&rb, &server, id, self.teacher).await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(format!("unknown office hours: {}", id)),
+                Err(e) => {
+                    tracing::error!("Couldn't remove office hours {}: {}", id, e);
+                    Err(e.to_string())
+                }
+            };
+            server.do_send(Traced::new(ServerRequest::OfficeHoursChanged {
+                channel: self.channel,
+                message: self.message,
+                change: HoursChange::Removed(id),
+                result,
+            }));
+        })
+    }
+}
+
+impl BotMessage for bot_response::OfficeSession {
+    fn handle(self, rb: Arc<Rbatis>, _: Addr<Server>) -> LocalBoxFuture<'static, ()> {
+        Box::pin(async move { office_stats::record(&rb, self.0).await })
+    }
+}
+
+impl BotMessage for bot_response::GetOfficeStats {
+    fn handle(self, rb: Arc<Rbatis>, server: Addr<Server>) -> LocalBoxFuture<'static, ()> {
+        let teacher = self.teacher;
+        let query = StatsQuery {
+            teacher: Some(teacher),
+            from: Some(self.from),
+            to: Some(self.to),
+            ..StatsQuery::default()
+        };
+
+        Box::pin(async move {
+            match office_stats::load(&rb, &query).await {
+                Ok(sessions) => server.do_send(Traced::new(ServerRequest::OfficeStats {
+                    channel: self.channel,
+                    teacher,
+                    stats: OfficeStats::from_sessions(&sessions),
+                })),
+                Err(e) => tracing::error!("Couldn't load office stats of {}: {}", teacher, e),
+            }
+        })
+    }
+}
+
+impl BotMessage for bot_response::Whois {
+    fn handle(self, rb: Arc<Rbatis>, server: Addr<Server>) -> LocalBoxFuture<'static, ()> {
+        let discord_id = self.discord_id;
+
+        Box::pin(async move {
+            match fetch_user(&rb, discord_id).await {
+                Ok(user) => server.do_send(Traced::new(ServerRequest::Whois {
+                    token: self.token,
+                    discord_id,
+                    user: user.as_ref().map(Into::into),
+                })),
+                Err(e) => tracing::error!("Couldn't fetch {}: {}", discord_id, e),
+            }
+        })
+    }
+}
+
+impl BotMessage for bot_response::MemberJoined {
+    fn handle(self, rb: Arc<Rbatis>, server: Addr<Server>) -> LocalBoxFuture<'static, ()> {
+        let discord_id = self.discord_id;
+
+        // Members who left and came back get their roles again
+        Box::pin(async move {
+            match fetch_user(&rb, discord_id).await {
+                Ok(Some(user)) if user.verified => {
+                    sync_roles(&rb, &server, &user, user.category()).await
+                }
+                Ok(_) => (),
+                Err(e) => tracing::error!("Couldn't fetch {}: {}", discord_id, e),
+            }
+        })
+    }
+}
+
+impl BotMessage for bot_response::Ack {
+    fn handle(self, rb: Arc<Rbatis>, server: Addr<Server>) -> LocalBoxFuture<'static, ()> {
+        Box::pin(async move {
+            outbox::ack(&rb, &self.key).await;
+            // The next command about the same member was waiting for this one
+            outbox::flush(&rb, &server).await;
+        })
+    }
+}
+
+impl Handler<Drain> for BotHandler {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, _: Drain, _: &mut Context<Self>) -> Self::Result {
+        let drained = self.in_flight.clone().write_owned().into_actor(self);
+
+        // The write lock is kept, so no message starts afterwards
+        Box::pin(drained.map(|guard, act, _| act.drained = Some(guard)))
+    }
+}
@@ -0,0 +1,324 @@
+use serde::Deserialize;
+use shared_lib::category::{Category, StaffKind};
+use std::{fs::File, path::Path};
+
+/// Map the ADFS groups matching `pattern` to a category
+///
+/// `*` in the pattern matches any sequence of characters
+#[derive(Deserialize, Debug, Clone)]
+pub struct GroupRule {
+    pattern: String,
+    #[serde(default)]
+    school: Option<String>,
+    #[serde(default)]
+    program: Option<String>,
+    /// Read the year from the last character of the group
+    #[serde(default)]
+    year_suffix: bool,
+    #[serde(default)]
+    apprentice: bool,
+    #[serde(default)]
+    staff: Option<StaffKind>,
+}
+
+impl GroupRule {
+    fn new(pattern: &str) -> Self {
+        GroupRule {
+            pattern: pattern.to_string(),
+            school: None,
+            program: None,
+            year_suffix: false,
+            apprentice: false,
+            staff: None,
+        }
+    }
+
+    fn school(mut self, school: &str) -> Self {
+        self.school = Some(school.to_string());
+        self
+    }
+
+    fn program(mut self, program: &str) -> Self {
+        self.program = Some(program.to_string());
+        self
+    }
+
+    fn year_suffix(mut self) -> Self {
+        self.year_suffix = true;
+        self
+    }
+
+    fn apprentice(mut self) -> Self {
+        self.apprentice = true;
+        self
+    }
+
+    fn staff(mut self, staff: StaffKind) -> Self {
+        self.staff = Some(staff);
+        self
+    }
+
+    fn matches(&self, group: &str) -> bool {
+        glob_match(
+            self.pattern.to_lowercase().as_bytes(),
+            group.to_lowercase().as_bytes(),
+        )
+    }
+
+    fn category(&self, group: &str) -> Category {
+        let year = match self.year_suffix {
+            true => group
+                .chars()
+                .last()
+                .and_then(|c| c.to_digit(10))
+                .map(|y| y as u8)
+                .filter(|y| (1..=5).contains(y)),
+            false => None,
+        };
+
+        Category {
+            school: self.school.clone(),
+            program: self.program.clone(),
+            year,
+            apprentice: self.apprentice,
+            staff: self.staff,
+        }
+    }
+}
+
+/// Match `text` against a pattern where `*` matches any sequence
+///
+/// Only the last `*` is backtracked to, a later star can match whatever an earlier one would, so
+/// the work is bounded by the product of both lengths instead of growing with each star
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position of the last star in the pattern, and of the text it was matched from
+    let mut star = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            // The last star swallows one more character
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Ordered table of rules, the first matching rule wins
+#[derive(Debug, Clone)]
+pub struct GroupRules(Vec<GroupRule>);
+
+impl Default for GroupRules {
+    fn default() -> Self {
+        GroupRules(vec![
+            GroupRule::new("staff").staff(StaffKind::Staff),
+            GroupRule::new("intervenant").staff(StaffKind::Intervenant),
+            GroupRule::new("*echange*").program("exchange"),
+            GroupRule::new("etu-esilv-app*")
+                .school("esilv")
+                .program("engineering")
+                .apprentice()
+                .year_suffix(),
+            GroupRule::new("etu-esilv*")
+                .school("esilv")
+                .program("engineering")
+                .year_suffix(),
+            GroupRule::new("etu-emlv*").school("emlv").year_suffix(),
+            GroupRule::new("etu-iim*").school("iim").year_suffix(),
+        ])
+    }
+}
+
+impl GroupRules {
+    /// Load the rules from the JSON file at `path`
+    ///
+    /// The built-in rules are used without a file
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        match path {
+            Some(path) => {
+                let file = File::open(path).map_err(|e| e.to_string())?;
+                let rules = serde_json::from_reader(file).map_err(|e| e.to_string())?;
+
+                Ok(GroupRules(rules))
+            }
+            None => Ok(GroupRules::default()),
+        }
+    }
+
+    /// Category of a member of `group`, empty when no rule matches
+    pub fn classify(&self, group: &str) -> Category {
+        self.0
+            .iter()
+            .find(|r| r.matches(group))
+            .map(|r| r.category(group))
+            .unwrap_or_default()
+    }
+
+    /// Most specific category among the ones of every group
+    ///
+    /// On a tie the first group wins
+    pub fn classify_all<S: AsRef<str>>(&self, groups: &[S]) -> Category {
+        groups
+            .iter()
+            .map(|g| self.classify(g.as_ref()))
+            .rev()
+            .max_by_key(specificity)
+            .unwrap_or_default()
+    }
+}
+
+/// Amount of information carried by a category
+fn specificity(category: &Category) -> usize {
+    [
+        category.staff.is_some(),
+        category.school.is_some(),
+        category.program.is_some(),
+        category.year.is_some(),
+        category.apprentice,
+    ]
+    .iter()
+    .filter(|set| **set)
+    .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_match, GroupRules};
+    use shared_lib::category::{Category, StaffKind};
+
+    fn student(school: &str, year: Option<u8>, apprentice: bool) -> Category {
+        Category {
+            school: Some(school.to_string()),
+            program: None,
+            year,
+            apprentice,
+            staff: None,
+        }
+    }
+
+    fn engineer(year: Option<u8>, apprentice: bool) -> Category {
+        Category {
+            program: Some("engineering".to_string()),
+            ..student("esilv", year, apprentice)
+        }
+    }
+
+    #[test]
+    fn glob() {
+        let cases = [
+            ("staff", "staff", true),
+            ("staff", "staffs", false),
+            ("etu-*", "etu-esilv-a1", true),
+            ("*-a?", "etu-a1", false),
+            ("*echange*", "etu-echange-2021", true),
+            ("a*b*c", "abc", true),
+            ("a*b*c", "acb", false),
+            ("", "", true),
+            ("*", "", true),
+            ("a**", "a", true),
+            ("*a", "ba", true),
+            ("*a", "ab", false),
+        ];
+
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                glob_match(pattern.as_bytes(), text.as_bytes()),
+                expected,
+                "{} ~ {}",
+                pattern,
+                text
+            );
+        }
+
+        // Each star used to retry every split of the text after it
+        let pattern = format!("{}b", "*a".repeat(20));
+        assert!(!glob_match(pattern.as_bytes(), "a".repeat(5000).as_bytes()));
+    }
+
+    #[test]
+    fn default_rules() {
+        let staff = |kind| Category {
+            staff: Some(kind),
+            ..Category::default()
+        };
+        let exchange = Category {
+            program: Some("exchange".to_string()),
+            ..Category::default()
+        };
+
+        let cases = [
+            ("staff", staff(StaffKind::Staff)),
+            ("STAFF", staff(StaffKind::Staff)),
+            ("intervenant", staff(StaffKind::Intervenant)),
+            ("etu-esilv-a1", engineer(Some(1), false)),
+            ("etu-esilv-a5", engineer(Some(5), false)),
+            ("etu-esilv", engineer(None, false)),
+            ("etu-esilv-a9", engineer(None, false)),
+            ("etu-esilv-app-a3", engineer(Some(3), true)),
+            ("etu-emlv-a2", student("emlv", Some(2), false)),
+            ("etu-iim-a4", student("iim", Some(4), false)),
+            ("etu-esilv-echange", exchange),
+            ("alumni", Category::default()),
+            ("", Category::default()),
+        ];
+
+        let rules = GroupRules::default();
+        for (group, expected) in cases {
+            assert_eq!(rules.classify(group), expected, "group {}", group);
+        }
+    }
+
+    #[test]
+    fn custom_rules() {
+        let rules: Vec<super::GroupRule> = serde_json::from_str(
+            r#"[
+                {"pattern": "etu-dvf*", "school": "dvf", "year_suffix": true},
+                {"pattern": "prof-*", "staff": "staff"}
+            ]"#,
+        )
+        .unwrap();
+        let rules = GroupRules(rules);
+
+        assert_eq!(rules.classify("etu-dvf-a2"), student("dvf", Some(2), false));
+        assert_eq!(rules.classify("prof-maths").staff, Some(StaffKind::Staff));
+        assert_eq!(rules.classify("etu-esilv-a1"), Category::default());
+    }
+
+    #[test]
+    fn most_specific_group() {
+        let cases: [(&[&str], Category); 5] = [
+            (&[], Category::default()),
+            (&["alumni", "etu-esilv-a2"], engineer(Some(2), false)),
+            (
+                &["etu-esilv-a2", "etu-esilv-app-a2"],
+                engineer(Some(2), true),
+            ),
+            (&["etu-esilv", "etu-esilv-a4"], engineer(Some(4), false)),
+            (
+                &["etu-emlv-a1", "etu-iim-a1"],
+                student("emlv", Some(1), false),
+            ),
+        ];
+
+        let rules = GroupRules::default();
+        for (groups, expected) in cases {
+            assert_eq!(rules.classify_all(groups), expected, "groups {:?}", groups);
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use serde::Deserialize;
+use shared_lib::socket::{health::Heartbeat, transport::Endpoint};
+use std::{
+    env, fmt,
+    fs::File,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use thiserror::Error;
+
+use crate::classification::GroupRules;
+
+/// Shortest session key accepted to encrypt the cookies
+const MIN_SESSION_KEY_LEN: usize = 32;
+
+/// Setting of the backend, named by its path in the config file and its environment var
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key(&'static str, &'static str);
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.0, self.1)
+    }
+}
+
+const HTTP_ADDRESS: Key = Key("http.address", "HTTP_ADDRESS");
+const PORT: Key = Key("http.address", "PORT");
+const HOST_URL: Key = Key("http.host_url", "HOST_URL");
+const FRONT_PATH: Key = Key("http.front_path", "FRONT_PATH");
+const BOT_SOCKET: Key = Key("socket.url", "BOT_SOCKET");
+const SOCKET_SECRET: Key = Key("socket.secret", "SOCKET_SECRET");
+const HEARTBEAT_INTERVAL: Key = Key("socket.heartbeat_interval", "HEARTBEAT_INTERVAL");
+const HEARTBEAT_TIMEOUT: Key = Key("socket.heartbeat_timeout", "HEARTBEAT_TIMEOUT");
+const DATABASE_URL: Key = Key("database.url", "DATABASE_URL");
+const DISCORD_CLIENT_ID: Key = Key("discord.client_id", "DISCORD_CLIENT_ID");
+const DISCORD_CLIENT_SECRET: Key = Key("discord.client_secret", "DISCORD_CLIENT_SECRET");
+const ADFS_URL: Key = Key("adfs.url", "ADFS_DEVINCI_URL");
+const ADFS_CLIENT_ID: Key = Key("adfs.client_id", "ADFS_DEVINCI_CLIENT_ID");
+const SESSION_KEY: Key = Key("session.key", "SESSION_KEY");
+const LINK_SECRET: Key = Key("session.link_secret", "LINK_SECRET");
+const ADMIN_IDS: Key = Key("admins", "ADMIN_IDS");
+const GROUP_RULES: Key = Key("group_rules", "GROUP_RULES");
+const SHUTDOWN_TIMEOUT: Key = Key("shutdown_timeout", "SHUTDOWN_TIMEOUT");
+
+/// Reason the configuration can't be used
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ConfigError {
+    #[error("couldn't read {0}: {1}")]
+    File(PathBuf, String),
+    #[error("{0} is missing")]
+    Missing(Key),
+    #[error("{0} is invalid: {1}")]
+    Invalid(Key, String),
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Address the HTTP server binds
+    pub address: String,
+    /// Public url of the website, the OAuth providers redirect to it
+    pub host_url: String,
+    /// Directory of the built frontend, the embedded one is served when empty
+    pub front_path: PathBuf,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            address: "0.0.0.0:8080".to_string(),
+            host_url: String::new(),
+            front_path: PathBuf::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketConfig {
+    /// `tcp://`, `unix://` or `ws://` url the bot connects to
+    pub url: String,
+    /// Shared with the bot, required by WebSocket urls
+    pub secret: Option<String>,
+    /// In seconds
+    pub heartbeat_interval: u64,
+    /// In seconds
+    pub heartbeat_timeout: u64,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        let heartbeat = Heartbeat::default();
+
+        SocketConfig {
+            url: "tcp://0.0.0.0:1234".to_string(),
+            secret: None,
+            heartbeat_interval: heartbeat.interval.as_secs(),
+            heartbeat_timeout: heartbeat.timeout.as_secs(),
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn front_path(&self) -> Option<&Path> {
+        Some(self.front_path.as_path()).filter(|path| !path.as_os_str().is_empty())
+    }
+}
+
+impl SocketConfig {
+    /// Only valid once the config is validated
+    pub fn endpoint(&self) -> Endpoint {
+        let endpoint: Endpoint = self.url.parse().expect("validated socket url");
+
+        endpoint.with_secret(self.secret.clone())
+    }
+
+    pub fn heartbeat(&self) -> Heartbeat {
+        Heartbeat {
+            interval: Duration::from_secs(self.heartbeat_interval),
+            timeout: Duration::from_secs(self.heartbeat_timeout),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+}
+
+/// Application registered on an OAuth provider
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdfsConfig {
+    /// Url of the ADFS OAuth endpoints, without `/authorize` or `/token`
+    pub url: String,
+    pub client_id: String,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Encrypts the session cookies, at least 32 bytes
+    pub key: String,
+    /// Shared with the bot to sign the verification links
+    pub link_secret: String,
+}
+
+/// Everything the backend needs to start, from a JSON file and the environment
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub http: HttpConfig,
+    pub socket: SocketConfig,
+    pub database: DatabaseConfig,
+    pub discord: DiscordConfig,
+    pub adfs: AdfsConfig,
+    pub session: SessionConfig,
+    /// Discord ids of the admins
+    pub admins: Vec<u64>,
+    /// JSON file of the ADFS group rules, the built-in ones are used without it
+    pub group_rules: Option<PathBuf>,
+    /// In seconds
+    pub shutdown_timeout: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            http: HttpConfig::default(),
+            socket: SocketConfig::default(),
+            database: DatabaseConfig::default(),
+            discord: DiscordConfig::default(),
+            adfs: AdfsConfig::default(),
+            session: SessionConfig::default(),
+            admins: Vec::new(),
+            group_rules: None,
+            shutdown_timeout: shared_lib::shutdown::DEFAULT_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+impl Config {
+    /// Read the file `path` if any, override it with the environment and validate the result
+    ///
+    /// Every error is reported, not only the first one
+    pub fn load(path: Option<&Path>) -> Result<Self, Vec<ConfigError>> {
+        Self::load_with(path, |key| env::var(key).ok())
+    }
+
+    fn load_with(
+        path: Option<&Path>,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, Vec<ConfigError>> {
+        let mut config = match path.map(Self::from_file).transpose() {
+            Ok(config) => config.unwrap_or_default(),
+            Err(e) => return Err(vec![e]),
+        };
+
+        let mut errors = config.override_with(var);
+        errors.extend(config.validate());
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let error = |e: &dyn fmt::Display| ConfigError::File(path.to_path_buf(), e.to_string());
+        let file = File::open(path).map_err(|e| error(&e))?;
+
+        serde_json::from_reader(file).map_err(|e| error(&e))
+    }
+
+    /// Replace the settings whose environment var is set and not empty
+    fn override_with(&mut self, var: impl Fn(&str) -> Option<String>) -> Vec<ConfigError> {
+        let var = |key: Key| var(key.1).filter(|value| !value.trim().is_empty());
+        let mut errors = Vec::new();
+
+        // Older setups only give the port, like most hosts do
+        if let Some(port) = parse_var::<u16>(PORT, var(PORT), &mut errors) {
+            self.http.address = format!("0.0.0.0:{}", port);
+        }
+        let strings = [
+            (HTTP_ADDRESS, &mut self.http.address),
+            (HOST_URL, &mut self.http.host_url),
+            (BOT_SOCKET, &mut self.socket.url),
+            (DATABASE_URL, &mut self.database.url),
+            (DISCORD_CLIENT_ID, &mut self.discord.client_id),
+            (DISCORD_CLIENT_SECRET, &mut self.discord.client_secret),
+            (ADFS_URL, &mut self.adfs.url),
+            (ADFS_CLIENT_ID, &mut self.adfs.client_id),
+            (SESSION_KEY, &mut self.session.key),
+            (LINK_SECRET, &mut self.session.link_secret),
+        ];
+        for (key, field) in strings {
+            if let Some(value) = var(key) {
+                *field = value;
+            }
+        }
+        let seconds = [
+            (HEARTBEAT_INTERVAL, &mut self.socket.heartbeat_interval),
+            (HEARTBEAT_TIMEOUT, &mut self.socket.heartbeat_timeout),
+            (SHUTDOWN_TIMEOUT, &mut self.shutdown_timeout),
+        ];
+        for (key, field) in seconds {
+            if let Some(value) = parse_var(key, var(key), &mut errors) {
+                *field = value;
+            }
+        }
+
+        if let Some(path) = var(FRONT_PATH) {
+            self.http.front_path = path.into();
+        }
+        if let Some(secret) = var(SOCKET_SECRET) {
+            self.socket.secret = Some(secret);
+        }
+        if let Some(path) = var(GROUP_RULES) {
+            self.group_rules = Some(path.into());
+        }
+        if let Some(ids) = var(ADMIN_IDS) {
+            let ids: Result<Vec<u64>, _> = ids.split(',').map(|id| parse(ADMIN_IDS, id)).collect();
+            match ids {
+                Ok(ids) => self.admins = ids,
+                Err(e) => errors.push(e),
+            }
+        }
+
+        errors
+    }
+
+    /// Every setting that can't be used as is
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        let required = [
+            (HTTP_ADDRESS, &self.http.address),
+            (HOST_URL, &self.http.host_url),
+            (BOT_SOCKET, &self.socket.url),
+            (DATABASE_URL, &self.database.url),
+            (DISCORD_CLIENT_ID, &self.discord.client_id),
+            (DISCORD_CLIENT_SECRET, &self.discord.client_secret),
+            (ADFS_URL, &self.adfs.url),
+            (ADFS_CLIENT_ID, &self.adfs.client_id),
+            (SESSION_KEY, &self.session.key),
+            (LINK_SECRET, &self.session.link_secret),
+        ];
+        errors.extend(
+            required
+                .iter()
+                .filter(|(_, value)| value.trim().is_empty())
+                .map(|(key, _)| ConfigError::Missing(*key)),
+        );
+        // Built without the frontend, it must be served from disk
+        if self.http.front_path().is_none() && !cfg!(feature = "embed-frontend") {
+            errors.push(ConfigError::Missing(FRONT_PATH));
+        }
+        let mut invalid =
+            |key: Key, reason: &str| errors.push(ConfigError::Invalid(key, reason.to_string()));
+
+        for (key, url) in [(HOST_URL, &self.http.host_url), (ADFS_URL, &self.adfs.url)] {
+            if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+                invalid(key, "not an http(s) url");
+            }
+        }
+        if self.http.host_url.ends_with('/') {
+            invalid(HOST_URL, "must not end with /");
+        }
+        if self.http.front_path().is_some_and(|path| !path.is_dir()) {
+            invalid(FRONT_PATH, "not a directory");
+        }
+
+        match self.socket.url.parse::<Endpoint>() {
+            Ok(Endpoint::WebSocket { .. }) if self.socket.secret.is_none() => {
+                invalid(SOCKET_SECRET, "required by WebSocket urls")
+            }
+            Ok(_) => (),
+            Err(e) => invalid(BOT_SOCKET, &e),
+        }
+        if self.socket.heartbeat_interval == 0 {
+            invalid(HEARTBEAT_INTERVAL, "must be at least a second");
+        }
+        if self.socket.heartbeat_timeout <= self.socket.heartbeat_interval {
+            invalid(HEARTBEAT_TIMEOUT, "must be longer than the interval");
+        }
+
+        if !self.session.key.is_empty() && self.session.key.len() < MIN_SESSION_KEY_LEN {
+            invalid(SESSION_KEY, "shorter than 32 bytes");
+        }
+        if let Err(e) = GroupRules::load(self.group_rules.as_deref()) {
+            invalid(GROUP_RULES, &e);
+        }
+
+        errors
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+}
+
+fn parse<T: FromStr>(key: Key, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e: T::Err| ConfigError::Invalid(key, e.to_string()))
+}
+
+/// Parse the environment var `key` if set, the error is added to `errors`
+fn parse_var<T: FromStr>(
+    key: Key,
+    value: Option<String>,
+    errors: &mut Vec<ConfigError>,
+) -> Option<T>
+where
+    T::Err: fmt::Display,
+{
+    parse(key, &value?).map_err(|e| errors.push(e)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, ConfigError, DATABASE_URL, HEARTBEAT_INTERVAL, SESSION_KEY};
+    use std::{collections::HashMap, env, fs};
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        let front = env::temp_dir();
+        let mut vars: HashMap<_, _> = [
+            ("HOST_URL", "https://leo.example"),
+            ("FRONT_PATH", front.to_str().unwrap()),
+            ("DATABASE_URL", "postgres://leo@localhost/leo"),
+            ("DISCORD_CLIENT_ID", "client"),
+            ("DISCORD_CLIENT_SECRET", "secret"),
+            ("ADFS_DEVINCI_URL", "https://adfs.example/adfs/oauth2"),
+            ("ADFS_DEVINCI_CLIENT_ID", "client"),
+            ("SESSION_KEY", "0123456789abcdef0123456789abcdef"),
+            ("LINK_SECRET", "link"),
+        ]
+        .iter()
+        .chain(pairs)
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        vars.retain(|_, value| !value.is_empty());
+
+        vars
+    }
+
+    fn load(vars: &HashMap<String, String>) -> Result<Config, Vec<ConfigError>> {
+        Config::load_with(None, |key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn environment_alone() {
+        let config = load(&vars(&[("PORT", "9000"), ("ADMIN_IDS", "1, 2")])).unwrap();
+
+        assert_eq!(config.http.address, "0.0.0.0:9000");
+        assert_eq!(config.socket.url, "tcp://0.0.0.0:1234");
+        assert_eq!(config.admins, vec![1, 2]);
+        assert_eq!(config.shutdown_timeout().as_secs(), 10);
+    }
+
+    #[test]
+    fn every_error_at_once() {
+        let vars = vars(&[
+            ("DATABASE_URL", ""),
+            ("SESSION_KEY", "short"),
+            ("BOT_SOCKET", "ws://0.0.0.0:1234/bot"),
+            ("HEARTBEAT_INTERVAL", "often"),
+        ]);
+        let errors = load(&vars).err().unwrap();
+
+        assert!(errors.contains(&ConfigError::Missing(DATABASE_URL)));
+        assert!(errors.contains(&ConfigError::Invalid(
+            SESSION_KEY,
+            "shorter than 32 bytes".to_string()
+        )));
+        assert!(errors
+            .iter()
+            .any(|e| matches!(e, ConfigError::Invalid(key, _) if key.1 == "SOCKET_SECRET")));
+        assert!(errors
+            .iter()
+            .any(|e| matches!(e, ConfigError::Invalid(key, _) if *key == HEARTBEAT_INTERVAL)));
+        assert_eq!(errors.len(), 4);
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let path = env::temp_dir().join(format!("leo-backend-{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"{"socket": {"url": "unix:///run/leo.sock"}, "database": {"url": "postgres://file"}}"#,
+        )
+        .unwrap();
+        let vars = vars(&[("DATABASE_URL", "")]);
+        let mut env_vars = vars.clone();
+        env_vars.insert("DATABASE_URL".to_string(), "postgres://env".to_string());
+
+        let from_file = Config::load_with(Some(&path), |key| vars.get(key).cloned()).unwrap();
+        let from_env = Config::load_with(Some(&path), |key| env_vars.get(key).cloned()).unwrap();
+        fs::write(&path, r#"{"socket": {"address": "0.0.0.0:1234"}}"#).unwrap();
+        let typo = Config::load_with(Some(&path), |key| vars.get(key).cloned());
+        fs::remove_file(&path).ok();
+
+        assert_eq!(from_file.socket.url, "unix:///run/leo.sock");
+        assert_eq!(from_file.database.url, "postgres://file");
+        assert_eq!(from_env.database.url, "postgres://env");
+        assert!(matches!(
+            typo.err().unwrap().as_slice(),
+            [ConfigError::File(..)]
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+use actix_files::{Files, NamedFile};
+use actix_web::{
+    dev::{fn_service, ServiceRequest, ServiceResponse},
+    web, HttpResponse,
+};
+use std::path::{Path, PathBuf};
+
+const INDEX: &str = "index.html";
+
+/// Paths of the backend, answered with a 404 rather than the frontend when they don't exist
+fn is_api(path: &str) -> bool {
+    path == "/api" || path.starts_with("/api/")
+}
+
+/// Serve the frontend from `front_path`, or the one embedded in the binary without it
+///
+/// The paths matching no asset get `index.html`, the client-side router handles them
+pub fn configure(cfg: &mut web::ServiceConfig, front_path: Option<&Path>) {
+    match front_path {
+        Some(path) => {
+            cfg.service(files(path.to_path_buf()));
+        }
+        #[cfg(feature = "embed-frontend")]
+        None => {
+            cfg.service(
+                web::resource("/{path:.*}")
+                    .route(web::get().to(embedded::serve))
+                    .route(web::head().to(embedded::serve)),
+            );
+        }
+        #[cfg(not(feature = "embed-frontend"))]
+        None => tracing::warn!("No FRONT_PATH and no embedded frontend, only the API is served"),
+    }
+}
+
+fn files(front_path: PathBuf) -> Files {
+    let index = front_path.join(INDEX);
+
+    Files::new("/", front_path)
+        .index_file(INDEX)
+        .default_handler(fn_service(move |req: ServiceRequest| {
+            let index = index.clone();
+
+            async move {
+                let (req, _) = req.into_parts();
+                if is_api(req.path()) {
+                    return Ok(ServiceResponse::new(req, HttpResponse::NotFound().finish()));
+                }
+
+                let response = NamedFile::open_async(index).await?.into_response(&req);
+                Ok(ServiceResponse::new(req, response))
+            }
+        }))
+}
+
+#[cfg(feature = "embed-frontend")]
+mod embedded {
+    use actix_web::{
+        http::header::{ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, VARY},
+        HttpRequest, HttpResponse,
+    };
+
+    use super::{is_api, INDEX};
+
+    /// Assets named after their content by the React build, they never change
+    const HASHED_ASSETS: &str = "static/";
+    const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+    /// The other files must be revalidated, a new build changes `index.html` under the same name
+    const REVALIDATE: &str = "no-cache";
+
+    fn cache_control(path: &str) -> &'static str {
+        if path.trim_start_matches('/').starts_with(HASHED_ASSETS) {
+            IMMUTABLE
+        } else {
+            REVALIDATE
+        }
+    }
+
+    /// Whether the `Accept-Encoding` header accepts `encoding`, a `q=0` refuses it
+    fn accepts(header: &str, encoding: &str) -> bool {
+        header.split(',').any(|accepted| {
+            let mut parts = accepted.split(';').map(str::trim);
+
+            parts.next() == Some(encoding)
+                && !parts.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q == 0.0)
+                })
+        })
+    }
+
+    /// File of the frontend build, with its compressed variants when they're smaller
+    struct Asset {
+        path: &'static str,
+        raw: &'static [u8],
+        gzip: Option<&'static [u8]>,
+        brotli: Option<&'static [u8]>,
+    }
+
+    // `ASSETS`, written by the build script
+    include!(concat!(env!("OUT_DIR"), "/frontend_assets.rs"));
+
+    fn find(path: &str) -> Option<&'static Asset> {
+        ASSETS.iter().find(|asset| asset.path == path)
+    }
+
+    pub(super) async fn serve(req: HttpRequest) -> HttpResponse {
+        if is_api(req.path()) {
+            return HttpResponse::NotFound().finish();
+        }
+
+        let path = req.match_info().query("path");
+        let asset = find(path)
+            .or_else(|| find(INDEX))
+            .expect("index.html is embedded");
+        let accepted = req
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+
+        let (body, encoding) = match (asset.brotli, asset.gzip) {
+            (Some(brotli), _) if accepts(accepted, "br") => (brotli, Some("br")),
+            (_, Some(gzip)) if accepts(accepted, "gzip") => (gzip, Some("gzip")),
+            _ => (asset.raw, None),
+        };
+        let extension = asset.path.rsplit('.').next().unwrap_or("");
+
+        let mut response = HttpResponse::Ok();
+        response
+            .content_type(actix_files::file_extension_to_mime(extension).to_string())
+            .insert_header((CACHE_CONTROL, cache_control(asset.path)))
+            .insert_header((VARY, "Accept-Encoding"));
+        if let Some(encoding) = encoding {
+            response.insert_header((CONTENT_ENCODING, encoding));
+        }
+
+        response.body(body)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{accepts, cache_control, IMMUTABLE, REVALIDATE};
+        use crate::frontend::is_api;
+
+        #[test]
+        fn encodings() {
+            assert!(accepts("gzip, deflate, br", "br"));
+            assert!(accepts("gzip;q=0.8, br;q=1.0", "gzip"));
+            assert!(!accepts("gzip;q=0, br", "gzip"));
+            assert!(!accepts("", "gzip"));
+            assert!(!accepts("x-gzip", "gzip"));
+        }
+
+        #[test]
+        fn cached_assets() {
+            assert_eq!(cache_control("static/js/main.3f2a.js"), IMMUTABLE);
+            assert_eq!(cache_control("index.html"), REVALIDATE);
+            assert!(is_api("/api/users"));
+            assert!(!is_api("/apiary"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::configure;
+    use actix_web::{http::StatusCode, test, App};
+    use std::{env, fs};
+
+    #[actix_web::test]
+    async fn client_routes_get_the_index() {
+        let front = env::temp_dir().join(format!("leo-front-{}", std::process::id()));
+        fs::create_dir_all(&front).unwrap();
+        fs::write(front.join("index.html"), "<html>leo</html>").unwrap();
+        fs::write(front.join("app.js"), "leo()").unwrap();
+
+        let app =
+            test::init_service(App::new().configure(|cfg| configure(cfg, Some(&front)))).await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        let route = test::call_service(&app, get("/verify/done")).await;
+        let script = test::call_service(&app, get("/app.js")).await;
+        let api = test::call_service(&app, get("/api/nothing")).await;
+        let index = test::read_body(route).await;
+        let script = test::read_body(script).await;
+        fs::remove_dir_all(&front).ok();
+
+        assert_eq!(index, "<html>leo</html>");
+        assert_eq!(script, "leo()");
+        assert_eq!(api.status(), StatusCode::NOT_FOUND);
+    }
+}
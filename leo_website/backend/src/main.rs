@@ -1,96 +1,89 @@
 #[macro_use]
 extern crate rbatis;
 
+mod admin;
+mod audit;
+mod bot;
+mod classification;
+mod config;
+mod frontend;
+mod metrics;
 mod models;
 mod oauth;
-
-use actix::Actor;
-use actix_files::Files;
-use actix_session::{CookieSession, Session};
+mod office_hours;
+mod office_stats;
+mod onboarding;
+mod outbox;
+mod rollover;
+mod shutdown;
+mod telemetry;
+
+use actix::{Actor, AsyncContext, Context};
+use actix_session::CookieSession;
 use actix_web::{
-    get,
-    http::header::LOCATION,
-    middleware::Logger,
     web::{self, Data},
     App, HttpResponse, HttpServer,
 };
 use rbatis::rbatis::Rbatis;
-use serde::Deserialize;
-use shared_lib::socket::{server::Server, session::tcp_server};
-use std::{env, sync::Arc};
-
-use crate::oauth::{adfs::ADFSAuth, discord::DiscordAuth};
-
-#[derive(Deserialize)]
-struct Info {
-    code: String,
-}
-
-#[get("/adfs")]
-async fn adfs_devinci(
-    info: web::Query<Info>,
-    session: Session,
-    auth_devinci: Data<ADFSAuth>,
-    rb: Data<Arc<Rbatis>>,
-) -> actix_web::Result<HttpResponse> {
-    let token = auth_devinci.get_token(&info.code).await?;
-    session.insert("devinci_token", token)?;
-
-    Ok(HttpResponse::Ok().body("TODO"))
-}
-
-#[get("/discord")]
-async fn auth_discord(
-    info: web::Query<Info>,
-    session: Session,
-    oauth_discord: Data<DiscordAuth>,
-    auth_devinci: Data<ADFSAuth>,
-) -> actix_web::Result<HttpResponse> {
-    let token = oauth_discord.get_token(&info.code).await?;
-    session.insert("discord_token", token)?;
-
-    Ok(HttpResponse::Found()
-        .append_header((LOCATION, auth_devinci.generate_authorize_url()))
-        .finish())
-}
-
-#[get("/userinfo")]
-async fn user_info(
-    session: Session,
-    oauth_discord: Data<DiscordAuth>,
-    auth_devinci: Data<ADFSAuth>,
-) -> actix_web::Result<HttpResponse> {
-    if let Some(discord_token) = session.get::<String>("discord_token")? {
-        if let Some(devinci_token) = session.get::<String>("devinci_token")? {
-            let id = oauth_discord.get_id(&discord_token).await?;
-            let mut user = auth_devinci.get_devinci_user(&devinci_token).await?;
-            user.discord_id = id.parse::<u64>().unwrap_or_default();
-
-            return Ok(HttpResponse::Ok().json(user));
-        }
-    }
-    Ok(HttpResponse::Ok().finish())
-}
-
-#[get("/login")]
-async fn login(auth: Data<DiscordAuth>) -> actix_web::Result<HttpResponse> {
-    Ok(HttpResponse::Found()
-        .append_header((LOCATION, auth.generate_authorize_url()))
-        .finish())
-}
+use shared_lib::{
+    link::LinkSigner,
+    socket::{
+        message::bot_response,
+        router::Router,
+        server::Server,
+        session::{listen, websocket_route},
+    },
+    trace::{self, LogFormat},
+};
+use std::{env, path::PathBuf, process, sync::Arc};
+
+use crate::{
+    admin::Admins,
+    bot::BotHandler,
+    classification::GroupRules,
+    config::Config,
+    oauth::{adfs::ADFSAuth, discord::DiscordAuth},
+};
 
 #[actix_web::main]
 async fn main() -> Result<(), std::io::Error> {
     dotenv::dotenv().ok();
-    env_logger::init();
+    trace::init(LogFormat::from_env());
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    // `--config FILE` or `BACKEND_CONFIG`, the environment alone is enough without it
+    let config_path = args
+        .iter()
+        .position(|arg| arg == "--config")
+        .and_then(|i| args.get(i + 1).cloned())
+        .or_else(|| env::var("BACKEND_CONFIG").ok())
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+    let config = match Config::load(config_path.as_deref()) {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for error in errors {
+                eprintln!("  {}", error);
+            }
+            process::exit(1);
+        }
+    };
+    if args.iter().any(|arg| arg == "--check-config") {
+        println!("Configuration is valid");
+        return Ok(());
+    }
 
-    let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
-    let db_url = env::var("DATABASE_URL").expect("database url");
-    let host_url = env::var("HOST_URL").expect("You must set the HOST_URL environment var!");
+    let bot_socket = config.socket.endpoint();
+    let heartbeat = config.socket.heartbeat();
+    let shutdown_timeout = config.shutdown_timeout();
+    let host_url = config.http.host_url.clone();
     let redirect_discord = format!("{}/discord", host_url);
 
     let rb = Rbatis::new();
-    rb.link(&db_url).await.expect("rbatis link database fail");
+    rb.link(&config.database.url)
+        .await
+        .expect("rbatis link database fail");
 
     // let user_test = DevinciUser {
     //     discord_id: 0,
@@ -108,24 +101,79 @@ async fn main() -> Result<(), std::io::Error> {
     // }
 
     let rb = Arc::new(rb);
-
-    let server = Server::default().start();
-    tcp_server("0.0.0.0:1234", server.clone());
-
-    HttpServer::new(move || {
+    let admins = Data::new(Admins::new(config.admins.clone()));
+    let signer = Data::new(LinkSigner::new(&config.session.link_secret));
+    let rules = GroupRules::load(config.group_rules.as_deref()).expect("validated group rules");
+    let http_address = config.http.address.clone();
+
+    // The server and the bot handler need each other's address
+    let ctx = Context::<Server>::new();
+    let server = ctx.address();
+    let bot_handler = BotHandler::new(rb.clone(), server.clone()).start();
+    let router = Router::new()
+        .route::<bot_response::Audit>(bot_handler.clone().recipient())
+        .route::<bot_response::GetOfficeHours>(bot_handler.clone().recipient())
+        .route::<bot_response::AddOfficeHours>(bot_handler.clone().recipient())
+        .route::<bot_response::RemoveOfficeHours>(bot_handler.clone().recipient())
+        .route::<bot_response::OfficeSession>(bot_handler.clone().recipient())
+        .route::<bot_response::GetOfficeStats>(bot_handler.clone().recipient())
+        .route::<bot_response::Whois>(bot_handler.clone().recipient())
+        .route::<bot_response::MemberJoined>(bot_handler.clone().recipient())
+        .route::<bot_response::Ack>(bot_handler.clone().recipient());
+    ctx.run(Server::default().with_router(router));
+    let listener = listen(&bot_socket, server.clone(), heartbeat).await?;
+    actix_web::rt::spawn(outbox::run(rb.clone(), server.clone()));
+    let (shutdown_rb, shutdown_server) = (rb.clone(), server.clone());
+
+    let http = HttpServer::new(move || {
         App::new()
             .app_data(Data::new(server.to_owned()))
             .app_data(Data::new(rb.to_owned()))
-            .app_data(Data::new(DiscordAuth::new(&redirect_discord)))
-            .app_data(Data::new(ADFSAuth::new(&host_url)))
-            .wrap(Logger::default())
-            .wrap(CookieSession::private(&[0; 32]))
-            .service(auth_discord)
-            .service(login)
-            .service(Files::new("/", env::var("FRONT_PATH").unwrap()).index_file("index.html"))
+            .app_data(Data::new(DiscordAuth::new(
+                &config.discord,
+                &redirect_discord,
+            )))
+            .app_data(Data::new(ADFSAuth::new(
+                &config.adfs,
+                &host_url,
+                rules.clone(),
+            )))
+            .app_data(admins.clone())
+            .app_data(signer.clone())
+            .wrap_fn(telemetry::traced)
+            .wrap(CookieSession::private(config.session.key.as_bytes()))
+            .configure(onboarding::configure)
+            .configure(office_hours::configure)
+            .configure(office_stats::configure)
+            .configure(admin::configure)
+            .configure(metrics::configure)
+            .configure(|cfg| {
+                // `listen` already refused a WebSocket without a secret
+                let route = websocket_route(&bot_socket, server.clone(), heartbeat)
+                    .expect("bot socket route");
+                if let Some(route) = route {
+                    cfg.service(route);
+                }
+            })
+            .configure(|cfg| frontend::configure(cfg, config.http.front_path()))
             .default_service(web::route().to(HttpResponse::NotFound))
     })
-    .bind(format!("0.0.0.0:{}", port))?
-    .run()
-    .await
+    // Signals are handled by `shutdown::run`, the bot socket must be closed too
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs())
+    .bind(http_address)?
+    .run();
+
+    let stopping = actix_web::rt::spawn(shutdown::run(
+        http.handle(),
+        listener,
+        shutdown_rb,
+        shutdown_server,
+        bot_handler,
+        shutdown_timeout,
+    ));
+    http.await?;
+    stopping.await.ok();
+
+    Ok(())
 }
@@ -0,0 +1,74 @@
+use actix_web::{
+    get,
+    web::{self, Data},
+    HttpResponse,
+};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, HistogramVec,
+    IntCounterVec, IntGauge,
+};
+use rbatis::{crud::CRUD, rbatis::Rbatis};
+use shared_lib::metrics;
+use std::{sync::Arc, time::Duration};
+
+use crate::models::DevinciUser;
+
+lazy_static! {
+    /// HTTP requests by method, route pattern and status
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "leo_backend_http_requests_total",
+        "HTTP requests handled",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_DURATION: HistogramVec = register_histogram_vec!(
+        "leo_backend_http_request_duration_seconds",
+        "Time spent handling HTTP requests",
+        &["method", "route"]
+    )
+    .unwrap();
+    /// OAuth callbacks by provider and result, `success` or `failure`
+    pub static ref OAUTH_LOGINS: IntCounterVec = register_int_counter_vec!(
+        "leo_backend_oauth_logins_total",
+        "OAuth logins completed or failed",
+        &["provider", "result"]
+    )
+    .unwrap();
+    /// Read from the database when scraped
+    pub static ref VERIFIED_USERS: IntGauge =
+        register_int_gauge!("leo_backend_verified_users", "Users linked and verified").unwrap();
+}
+
+/// Count a handled request, `route` is the pattern it matched to keep the labels bounded
+pub fn observe_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    HTTP_REQUESTS
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    HTTP_DURATION
+        .with_label_values(&[method, route])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Count the end of an OAuth flow with `provider`
+pub fn oauth_login(provider: &str, success: bool) {
+    let result = if success { "success" } else { "failure" };
+
+    OAUTH_LOGINS.with_label_values(&[provider, result]).inc();
+}
+
+#[get("/metrics")]
+async fn scrape(rb: Data<Arc<Rbatis>>) -> HttpResponse {
+    let verified = rb.new_wrapper().eq("verified", true);
+
+    match rb.fetch_count_by_wrapper::<DevinciUser>(verified).await {
+        Ok(count) => VERIFIED_USERS.set(count as i64),
+        Err(e) => tracing::error!("Couldn't count the verified users: {}", e),
+    }
+
+    metrics::response()
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(scrape);
+}
@@ -1,4 +1,8 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use shared_lib::{
+    category::{Category, StaffKind},
+    user::LinkedUser,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -10,13 +14,33 @@ pub struct Claims {
     pub(crate) family_name: String,
     pub(crate) given_name: String,
     pub(crate) sub: String,
-    pub(crate) group: Option<String>,
+    /// ADFS sends a single string or an array depending on the membership
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub(crate) group: Vec<String>,
     pub(crate) auth_time: String,
     pub(crate) authmethod: String,
     pub(crate) ver: String,
     pub(crate) appid: String,
 }
 
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(group)) => vec![group],
+        Some(OneOrMany::Many(groups)) => groups,
+        None => Vec::new(),
+    })
+}
+
 #[crud_table(table_name:"users")]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DevinciUser {
@@ -24,23 +48,181 @@ pub struct DevinciUser {
     pub(crate) first_name: String,
     pub(crate) last_name: String,
     pub(crate) mail: String,
+    /// Coarse `DevinciType`, kept in sync with the category
     pub(crate) func: u8,
+    pub(crate) school: Option<String>,
+    pub(crate) program: Option<String>,
+    pub(crate) year: Option<u8>,
+    pub(crate) apprentice: bool,
+    pub(crate) staff: Option<StaffKind>,
+    /// Every ADFS group of the user, comma separated
+    pub(crate) groups: String,
+    pub(crate) verified: bool,
+    /// Unix timestamp of the last time the category was set from fresh data
+    pub(crate) func_updated_at: i64,
+}
+
+impl DevinciUser {
+    pub fn category(&self) -> Category {
+        Category {
+            school: self.school.clone(),
+            program: self.program.clone(),
+            year: self.year,
+            apprentice: self.apprentice,
+            staff: self.staff,
+        }
+    }
+
+    pub fn set_groups(&mut self, groups: &[String]) {
+        self.groups = groups.join(",");
+    }
+
+    pub fn group_list(&self) -> Vec<String> {
+        self.groups
+            .split(',')
+            .filter(|group| !group.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn set_category(&mut self, category: Category) {
+        self.func = DevinciType::from(&category).into();
+        self.school = category.school;
+        self.program = category.program;
+        self.year = category.year;
+        self.apprentice = category.apprentice;
+        self.staff = category.staff;
+    }
+}
+
+impl From<&DevinciUser> for LinkedUser {
+    fn from(user: &DevinciUser) -> Self {
+        LinkedUser {
+            discord_id: user.discord_id,
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            mail: user.mail.clone(),
+            category: user.category(),
+            verified: user.verified,
+        }
+    }
+}
+
+#[crud_table(table_name:"audit_log")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub(crate) id: Option<i64>,
+    pub(crate) actor: u64,
+    pub(crate) target: u64,
+    pub(crate) action: String,
+    pub(crate) details: String,
+    pub(crate) created_at: i64,
+}
+
+/// Weekly office hours of a teacher, see `shared_lib::office::OfficeHours`
+#[crud_table(table_name:"office_hours")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    pub(crate) id: Option<i64>,
+    pub(crate) teacher: u64,
+    pub(crate) subject: u64,
+    pub(crate) weekday: u8,
+    pub(crate) start_minute: u16,
+    pub(crate) end_minute: u16,
+    pub(crate) timezone: String,
+}
+
+/// A closed office, see `shared_lib::office::OfficeSession`
+#[crud_table(table_name:"office_sessions")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionEntry {
+    pub(crate) id: Option<i64>,
+    pub(crate) teacher: u64,
+    pub(crate) subject: Option<u64>,
+    pub(crate) opened_at: i64,
+    pub(crate) closed_at: i64,
+    /// JSON array of `OfficeVisit`
+    pub(crate) visits: String,
+}
+
+/// Delivery state of an outbox entry
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxStatus {
+    Pending,
+    Delivered,
+    /// Out of attempts, waiting for an admin
+    Failed,
 }
 
+/// Command for the bot kept until it is acknowledged, see `crate::outbox`
+#[crud_table(table_name:"outbox")]
 #[derive(Debug, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub(crate) id: Option<i64>,
+    /// Sent with every delivery so the bot can skip the replays
+    pub(crate) idempotency_key: String,
+    /// JSON of the `ServerRequest`
+    pub(crate) request: String,
+    pub(crate) status: OutboxStatus,
+    pub(crate) attempts: u32,
+    pub(crate) next_attempt_at: i64,
+    pub(crate) created_at: i64,
+    pub(crate) delivered_at: Option<i64>,
+    /// HTTP request which queued the command, sent along to follow it in the bot
+    pub(crate) request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum DevinciType {
     Student(u8),
     Professor,
     Other,
 }
 
+impl DevinciType {
+    /// `None` for the values no type is stored as
+    pub fn parse(func: u8) -> Option<Self> {
+        match func {
+            0 => Some(DevinciType::Professor),
+            x if (1..=5).contains(&x) => Some(DevinciType::Student(x)),
+            6 => Some(DevinciType::Other),
+            _ => None,
+        }
+    }
+
+    /// Change the year and staff kind of `category` to match this type
+    pub fn apply_to(self, category: &mut Category) {
+        match self {
+            DevinciType::Professor => {
+                category.staff.get_or_insert(StaffKind::Staff);
+                category.year = None;
+            }
+            DevinciType::Student(year) => {
+                category.staff = None;
+                category.year = Some(year);
+            }
+            DevinciType::Other => {
+                category.staff = None;
+                category.year = None;
+            }
+        }
+    }
+}
+
+impl From<&Category> for DevinciType {
+    fn from(category: &Category) -> Self {
+        match (category.staff, category.year) {
+            (Some(_), _) => DevinciType::Professor,
+            (None, Some(year)) => DevinciType::Student(year),
+            (None, None) => DevinciType::Other,
+        }
+    }
+}
+
 impl From<u8> for DevinciType {
     fn from(value: u8) -> Self {
-        match value {
-            0 => DevinciType::Professor,
-            x if (1..=5).contains(&x) => DevinciType::Student(x),
-            _ => DevinciType::Other,
-        }
+        DevinciType::parse(value).unwrap_or(DevinciType::Other)
     }
 }
 
@@ -53,3 +235,49 @@ impl From<DevinciType> for u8 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Claims, DevinciType};
+    use serde_json::json;
+
+    fn claims(group: serde_json::Value) -> Claims {
+        let mut claims = json!({
+            "aud": "", "iss": "", "iat": 0, "exp": 0,
+            "email": "jean.marchand@edu.devinci.fr",
+            "family_name": "MARCHAND", "given_name": "Jean", "sub": "",
+            "auth_time": "", "authmethod": "", "ver": "", "appid": ""
+        });
+        if !group.is_null() {
+            claims["group"] = group;
+        }
+
+        serde_json::from_value(claims).unwrap()
+    }
+
+    #[test]
+    fn claims_group_shapes() {
+        let cases = [
+            (json!(null), vec![]),
+            (json!("etu-esilv-a1"), vec!["etu-esilv-a1"]),
+            (
+                json!(["etu-esilv-a1", "staff"]),
+                vec!["etu-esilv-a1", "staff"],
+            ),
+            (json!([]), vec![]),
+        ];
+
+        for (group, expected) in cases {
+            assert_eq!(claims(group.clone()).group, expected, "group {}", group);
+        }
+    }
+
+    #[test]
+    fn stored_funcs() {
+        assert_eq!(DevinciType::parse(0), Some(DevinciType::Professor));
+        assert_eq!(DevinciType::parse(3), Some(DevinciType::Student(3)));
+        assert_eq!(DevinciType::parse(6), Some(DevinciType::Other));
+        assert_eq!(DevinciType::parse(7), None);
+        assert_eq!(DevinciType::from(42), DevinciType::Other);
+    }
+}
@@ -1,27 +1,41 @@
 use awc::Client;
 use form_urlencoded::byte_serialize;
+use shared_lib::audit::timestamp;
 use std::collections::HashMap;
 use voca_rs::Voca;
 
-use crate::models::{Claims, DevinciType, DevinciUser};
+use crate::{
+    classification::GroupRules,
+    config::AdfsConfig,
+    models::{Claims, DevinciType, DevinciUser},
+};
+
+/// Claims in the payload of the JWT `token`, encoded in base64url without padding
+fn decode_claims(token: &str) -> actix_web::Result<Claims> {
+    let payload = token
+        .split('.')
+        .nth(1)
+        .ok_or(actix_web::error::ContentTypeError::ParseError)?;
+    let decoded = base64::decode_config(payload.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    Ok(serde_json::from_slice(&decoded)?)
+}
 
 pub struct ADFSAuth {
     client_id: String,
     host_url: String,
     target_url: String,
+    rules: GroupRules,
 }
 
 impl ADFSAuth {
-    pub fn new(url: &str) -> Self {
-        let client_id = std::env::var("ADFS_DEVINCI_CLIENT_ID")
-            .expect("You must set the ADFS_DEVINCI_CLIENT_ID environment var!");
-        let target_url = std::env::var("ADFS_DEVINCI_URL")
-            .expect("You must set the ADFS_DEVINCI_URL environment var!");
-
+    pub fn new(config: &AdfsConfig, url: &str, rules: GroupRules) -> Self {
         Self {
-            client_id,
+            client_id: config.client_id.clone(),
             host_url: url.to_string(),
-            target_url,
+            target_url: config.url.clone(),
+            rules,
         }
     }
 
@@ -63,30 +77,51 @@ impl ADFSAuth {
     }
 
     pub async fn get_devinci_user(&self, token: &str) -> actix_web::Result<DevinciUser> {
-        let infos = token.split('.').collect::<Vec<_>>();
-
-        if infos.len() < 2 {
-            return Err(actix_web::error::ContentTypeError::ParseError.into());
-        }
-
-        let decoded = base64::decode(infos[1]).unwrap();
-        let user: Claims = serde_json::from_slice(&decoded)?;
-
-        let devinci_type = match user.group {
-            Some(p) if p == "staff" || p == "intervenant" => DevinciType::Professor,
-            Some(e) if e.starts_with("etu-esilv") => {
-                let year = e.chars().last().unwrap_or('1').to_digit(10).unwrap_or(1);
-                DevinciType::Student(year as u8)
-            }
-            _ => DevinciType::Other,
-        };
+        let user = decode_claims(token)?;
+        let category = self.rules.classify_all(&user.group);
 
-        Ok(DevinciUser {
+        let mut devinci_user = DevinciUser {
             discord_id: 0,
             first_name: user.given_name,
             last_name: user.family_name._capitalize(true),
             mail: user.email,
-            func: devinci_type.into(),
-        })
+            func: DevinciType::Other.into(),
+            school: None,
+            program: None,
+            year: None,
+            apprentice: false,
+            staff: None,
+            groups: String::new(),
+            verified: false,
+            func_updated_at: timestamp(),
+        };
+        devinci_user.set_category(category);
+        devinci_user.set_groups(&user.group);
+
+        Ok(devinci_user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_claims;
+    use serde_json::json;
+
+    #[test]
+    fn payloads_are_base64url() {
+        let claims = json!({
+            "aud": "leo", "iss": "adfs", "iat": 1, "exp": 2,
+            "email": "jean.marchand@edu.devinci.fr",
+            "family_name": "marchand", "given_name": "Jean ~?>",
+            "sub": "s", "group": ["A1", "TD2"],
+            "auth_time": "t", "authmethod": "m", "ver": "1.0", "appid": "leo",
+        });
+        let payload = base64::encode_config(claims.to_string(), base64::URL_SAFE_NO_PAD);
+        assert!(payload.contains(['-', '_']));
+
+        let claims = decode_claims(&format!("header.{}.signature", payload)).unwrap();
+        assert_eq!(claims.group, vec!["A1", "TD2"]);
+        assert!(decode_claims("header.not base64!.signature").is_err());
+        assert!(decode_claims("header").is_err());
     }
 }
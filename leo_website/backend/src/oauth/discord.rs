@@ -2,6 +2,8 @@ use awc::Client;
 use form_urlencoded::byte_serialize;
 use std::collections::HashMap;
 
+use crate::config::DiscordConfig;
+
 pub struct DiscordAuth {
     client_id: String,
     client_secret: String,
@@ -9,15 +11,10 @@ pub struct DiscordAuth {
 }
 
 impl DiscordAuth {
-    pub fn new(redirect: &str) -> Self {
-        let client_id = std::env::var("DISCORD_CLIENT_ID")
-            .expect("You must set the DISCORD_CLIENT_ID environment var!");
-        let client_secret = std::env::var("DISCORD_CLIENT_SECRET")
-            .expect("You must set the DISCORD_CLIENT_SECRET environment var!");
-
+    pub fn new(config: &DiscordConfig, redirect: &str) -> Self {
         Self {
-            client_id,
-            client_secret,
+            client_id: config.client_id.clone(),
+            client_secret: config.client_secret.clone(),
             redirect: String::from(redirect),
         }
     }
@@ -0,0 +1,206 @@
+use actix::Addr;
+use actix_session::Session;
+use actix_web::{
+    delete,
+    error::{
+        ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized,
+    },
+    get, post,
+    web::{self, Data},
+    HttpResponse,
+};
+use rbatis::{
+    crud::{Skip, CRUD},
+    rbatis::Rbatis,
+};
+use serde::Deserialize;
+use shared_lib::{
+    office::OfficeHours,
+    socket::{
+        message::{ServerRequest, Traced},
+        server::Server,
+    },
+};
+use std::sync::Arc;
+
+use crate::{admin::is_teacher, models::ScheduleEntry, onboarding::DISCORD_ID};
+
+impl From<ScheduleEntry> for OfficeHours {
+    fn from(entry: ScheduleEntry) -> Self {
+        OfficeHours {
+            id: entry.id.unwrap_or_default(),
+            teacher: entry.teacher,
+            subject: entry.subject,
+            weekday: entry.weekday,
+            start: entry.start_minute,
+            end: entry.end_minute,
+            timezone: entry.timezone,
+        }
+    }
+}
+
+impl From<OfficeHours> for ScheduleEntry {
+    fn from(hours: OfficeHours) -> Self {
+        ScheduleEntry {
+            id: None,
+            teacher: hours.teacher,
+            subject: hours.subject,
+            weekday: hours.weekday,
+            start_minute: hours.start,
+            end_minute: hours.end,
+            timezone: hours.timezone,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NewOfficeHours {
+    subject: u64,
+    weekday: u8,
+    start: u16,
+    end: u16,
+    timezone: String,
+}
+
+/// Every scheduled office hours
+pub async fn load_all(rb: &Rbatis) -> rbatis::Result<Vec<OfficeHours>> {
+    let entries = rb.fetch_list::<ScheduleEntry>().await?;
+
+    Ok(entries.into_iter().map(OfficeHours::from).collect())
+}
+
+/// Send the whole schedule to the bot
+pub async fn push_all(rb: &Rbatis, server: &Addr<Server>) {
+    match load_all(rb).await {
+        Ok(hours) => server.do_send(Traced::new(ServerRequest::OfficeHours(hours))),
+        Err(e) => tracing::error!("Couldn't load office hours: {}", e),
+    }
+}
+
+/// Store new office hours and send the updated schedule to the bot
+pub async fn add(rb: &Rbatis, server: &Addr<Server>, hours: OfficeHours) -> Result<(), String> {
+    hours.validate()?;
+
+    rb.save(&ScheduleEntry::from(hours), &[Skip::Column("id")])
+        .await
+        .map_err(|e| e.to_string())?;
+    push_all(rb, server).await;
+
+    Ok(())
+}
+
+/// Delete office hours of `teacher`, returns false when they don't exist
+pub async fn remove(
+    rb: &Rbatis,
+    server: &Addr<Server>,
+    id: i64,
+    teacher: u64,
+) -> rbatis::Result<bool> {
+    let wrapper = rb.new_wrapper().eq("id", id).eq("teacher", teacher);
+    let removed = rb.remove_by_wrapper::<ScheduleEntry>(wrapper).await? > 0;
+
+    if removed {
+        push_all(rb, server).await;
+    }
+
+    Ok(removed)
+}
+
+/// Return the discord id of the logged teacher
+async fn require_teacher(session: &Session, rb: &Rbatis) -> actix_web::Result<u64> {
+    let discord_id = session
+        .get::<u64>(DISCORD_ID)?
+        .ok_or_else(|| ErrorUnauthorized("not logged in"))?;
+
+    match is_teacher(rb, discord_id).await? {
+        true => Ok(discord_id),
+        false => Err(ErrorForbidden("teacher only")),
+    }
+}
+
+#[get("/api/office-hours")]
+async fn list_hours(session: Session, rb: Data<Arc<Rbatis>>) -> actix_web::Result<HttpResponse> {
+    let teacher = require_teacher(&session, &rb).await?;
+
+    let entries = rb
+        .fetch_list_by_wrapper::<ScheduleEntry>(rb.new_wrapper().eq("teacher", teacher))
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let hours: Vec<OfficeHours> = entries.into_iter().map(OfficeHours::from).collect();
+
+    Ok(HttpResponse::Ok().json(hours))
+}
+
+#[post("/api/office-hours")]
+async fn add_hours(
+    body: web::Json<NewOfficeHours>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let teacher = require_teacher(&session, &rb).await?;
+    let body = body.into_inner();
+
+    let hours = OfficeHours {
+        id: 0,
+        teacher,
+        subject: body.subject,
+        weekday: body.weekday,
+        start: body.start,
+        end: body.end,
+        timezone: body.timezone,
+    };
+    hours.validate().map_err(ErrorBadRequest)?;
+    add(&rb, &server, hours)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Created().finish())
+}
+
+#[delete("/api/office-hours/{id}")]
+async fn remove_hours(
+    path: web::Path<i64>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let teacher = require_teacher(&session, &rb).await?;
+
+    match remove(&rb, &server, path.into_inner(), teacher)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(ErrorNotFound("unknown office hours")),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_hours)
+        .service(add_hours)
+        .service(remove_hours);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OfficeHours;
+    use crate::models::ScheduleEntry;
+
+    #[test]
+    fn entry_round_trip() {
+        let hours = OfficeHours {
+            id: 0,
+            teacher: 1,
+            subject: 2,
+            weekday: 4,
+            start: 600,
+            end: 720,
+            timezone: "Europe/Paris".to_string(),
+        };
+
+        let entry = ScheduleEntry::from(hours.clone());
+        assert_eq!(entry.id, None);
+        assert_eq!(OfficeHours::from(entry), hours);
+    }
+}
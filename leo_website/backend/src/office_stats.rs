@@ -0,0 +1,262 @@
+use actix_session::Session;
+use actix_web::{
+    error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized},
+    get,
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    web::{self, Data},
+    HttpResponse,
+};
+use rbatis::{
+    crud::{Skip, CRUD},
+    rbatis::Rbatis,
+};
+use serde::{Deserialize, Serialize};
+use shared_lib::office::{OfficeSession, OfficeStats};
+use std::sync::Arc;
+
+use crate::{
+    admin::{is_teacher, Admins},
+    audit::ERASED,
+    models::SessionEntry,
+    onboarding::DISCORD_ID,
+};
+
+impl From<OfficeSession> for SessionEntry {
+    fn from(session: OfficeSession) -> Self {
+        SessionEntry {
+            id: None,
+            teacher: session.teacher,
+            subject: session.subject,
+            opened_at: session.opened_at,
+            closed_at: session.closed_at,
+            visits: serde_json::to_string(&session.visits).unwrap_or_else(|_| "[]".to_string()),
+        }
+    }
+}
+
+impl From<SessionEntry> for OfficeSession {
+    fn from(entry: SessionEntry) -> Self {
+        OfficeSession {
+            teacher: entry.teacher,
+            subject: entry.subject,
+            opened_at: entry.opened_at,
+            closed_at: entry.closed_at,
+            visits: serde_json::from_str(&entry.visits).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct StatsQuery {
+    pub(crate) teacher: Option<u64>,
+    pub(crate) subject: Option<u64>,
+    /// Unix timestamp in seconds, inclusive
+    pub(crate) from: Option<i64>,
+    /// Unix timestamp in seconds, inclusive
+    pub(crate) to: Option<i64>,
+    /// `json` (default) or `csv`
+    pub(crate) format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StatsReport {
+    stats: OfficeStats,
+    sessions: Vec<OfficeSession>,
+}
+
+/// Store a closed office
+pub async fn record(rb: &Rbatis, session: OfficeSession) {
+    let entry = SessionEntry::from(session);
+
+    if let Err(e) = rb.save(&entry, &[Skip::Column("id")]).await {
+        tracing::error!("Couldn't record office session {:?}: {}", entry, e);
+    }
+}
+
+/// Sessions opened in the range of `query`, oldest first
+pub async fn load(rb: &Rbatis, query: &StatsQuery) -> rbatis::Result<Vec<OfficeSession>> {
+    let mut wrapper = rb.new_wrapper();
+
+    if let Some(teacher) = query.teacher {
+        wrapper = wrapper.eq("teacher", teacher);
+    }
+    if let Some(subject) = query.subject {
+        wrapper = wrapper.eq("subject", subject);
+    }
+    if let Some(from) = query.from {
+        wrapper = wrapper.ge("opened_at", from);
+    }
+    if let Some(to) = query.to {
+        wrapper = wrapper.le("opened_at", to);
+    }
+
+    let entries = rb
+        .fetch_list_by_wrapper::<SessionEntry>(wrapper.order_by(true, &["opened_at"]))
+        .await?;
+
+    Ok(entries.into_iter().map(OfficeSession::from).collect())
+}
+
+/// Drop `discord_id` from the session, as its teacher or a visitor, keeping the durations
+fn pseudonymize(session: &mut OfficeSession, discord_id: u64) {
+    if session.teacher == discord_id {
+        session.teacher = ERASED;
+    }
+    for visit in &mut session.visits {
+        if visit.student == discord_id {
+            visit.student = ERASED;
+        }
+    }
+}
+
+/// Pseudonymize every session held or visited by `discord_id`, part of the erasure of a user
+pub async fn erase(rb: &Rbatis, discord_id: u64) -> rbatis::Result<()> {
+    // The pattern also matches longer ids, they are left as they are
+    let wrapper = rb
+        .new_wrapper()
+        .eq("teacher", discord_id)
+        .or()
+        .like("visits", format!("\"student\":{}", discord_id));
+
+    for entry in rb.fetch_list_by_wrapper::<SessionEntry>(wrapper).await? {
+        let id = entry.id;
+        let mut session = OfficeSession::from(entry);
+        pseudonymize(&mut session, discord_id);
+
+        let entry = SessionEntry {
+            id,
+            ..SessionEntry::from(session)
+        };
+        rb.update_by_column("id", &entry).await?;
+    }
+
+    Ok(())
+}
+
+/// One row per visit, sessions without visits get a row with empty visit fields
+fn to_csv(sessions: &[OfficeSession]) -> String {
+    let mut csv =
+        String::from("teacher,subject,opened_at,closed_at,student,waited,joined_at,left_at\n");
+
+    for session in sessions {
+        let prefix = format!(
+            "{},{},{},{}",
+            session.teacher,
+            session.subject.map(|s| s.to_string()).unwrap_or_default(),
+            session.opened_at,
+            session.closed_at
+        );
+
+        if session.visits.is_empty() {
+            csv.push_str(&format!("{},,,,\n", prefix));
+        }
+        for visit in &session.visits {
+            csv.push_str(&format!(
+                "{},{},{},{},{}\n",
+                prefix,
+                visit.student,
+                visit.waited.map(|w| w.to_string()).unwrap_or_default(),
+                visit.joined_at,
+                visit.left_at
+            ));
+        }
+    }
+
+    csv
+}
+
+/// Teachers only see their own sessions, the listed admins see everyone's
+#[get("/api/office-stats")]
+async fn export_stats(
+    query: web::Query<StatsQuery>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+) -> actix_web::Result<HttpResponse> {
+    let discord_id = session
+        .get::<u64>(DISCORD_ID)?
+        .ok_or_else(|| ErrorUnauthorized("not logged in"))?;
+
+    let mut query = query.into_inner();
+    if !admins.contains(discord_id) {
+        if !is_teacher(&rb, discord_id).await? {
+            return Err(ErrorForbidden("teacher only"));
+        }
+        query.teacher = Some(discord_id);
+    }
+
+    let sessions = load(&rb, &query).await.map_err(ErrorInternalServerError)?;
+
+    match query.format.as_deref() {
+        Some("csv") => Ok(HttpResponse::Ok()
+            .append_header((CONTENT_TYPE, "text/csv; charset=utf-8"))
+            .append_header((
+                CONTENT_DISPOSITION,
+                "attachment; filename=\"office-stats.csv\"",
+            ))
+            .body(to_csv(&sessions))),
+        _ => Ok(HttpResponse::Ok().json(StatsReport {
+            stats: OfficeStats::from_sessions(&sessions),
+            sessions,
+        })),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(export_stats);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pseudonymize, to_csv};
+    use crate::audit::ERASED;
+    use shared_lib::office::{OfficeSession, OfficeVisit};
+
+    #[test]
+    fn csv_rows() {
+        let session = |subject, visits| OfficeSession {
+            teacher: 1,
+            subject,
+            opened_at: 10,
+            closed_at: 20,
+            visits,
+        };
+        let visit = OfficeVisit {
+            student: 2,
+            waited: Some(3),
+            joined_at: 12,
+            left_at: 18,
+        };
+
+        assert_eq!(
+            to_csv(&[session(Some(5), vec![visit]), session(None, vec![])]),
+            "teacher,subject,opened_at,closed_at,student,waited,joined_at,left_at\n\
+             1,5,10,20,2,3,12,18\n\
+             1,,10,20,,,,\n"
+        );
+    }
+
+    #[test]
+    fn erased_visitors_keep_their_time() {
+        let visit = |student| OfficeVisit {
+            student,
+            waited: None,
+            joined_at: 12,
+            left_at: 18,
+        };
+        let mut session = OfficeSession {
+            teacher: 1,
+            subject: None,
+            opened_at: 10,
+            closed_at: 20,
+            visits: vec![visit(7), visit(70)],
+        };
+
+        pseudonymize(&mut session, 7);
+        assert_eq!(session.visits, vec![visit(ERASED), visit(70)]);
+        assert_eq!(session.teacher, 1);
+
+        pseudonymize(&mut session, 1);
+        assert_eq!(session.teacher, ERASED);
+    }
+}
@@ -0,0 +1,464 @@
+use actix::Addr;
+use actix_session::Session;
+use actix_web::{
+    error::ErrorInternalServerError,
+    get,
+    http::header::LOCATION,
+    post,
+    web::{self, Data},
+    HttpResponse,
+};
+use rbatis::{crud::CRUD, rbatis::Rbatis};
+use serde::{Deserialize, Serialize};
+use shared_lib::{
+    audit::{timestamp, AuditAction, AuditEvent},
+    link::LinkSigner,
+    socket::{message::ServerRequest, server::Server},
+};
+use std::sync::Arc;
+
+use crate::{
+    audit, metrics,
+    models::DevinciUser,
+    oauth::{adfs::ADFSAuth, discord::DiscordAuth},
+    office_stats, outbox,
+};
+
+/// Session key holding the linked discord id
+pub(crate) const DISCORD_ID: &str = "discord_id";
+/// Session key holding the ADFS access token
+const DEVINCI_TOKEN: &str = "devinci_token";
+
+/// Query sent back by an OAuth provider on its redirect uri
+#[derive(Deserialize)]
+pub struct Callback {
+    code: Option<String>,
+    error: Option<String>,
+}
+
+/// Query of the login route, `token` comes from a verification link sent by the bot
+#[derive(Deserialize)]
+pub struct LoginQuery {
+    token: Option<String>,
+}
+
+/// Steps of the onboarding, in the order the user goes through them
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnboardingState {
+    NotLogged,
+    DiscordLinked,
+    SchoolLinked,
+    Verified,
+}
+
+impl OnboardingState {
+    fn new(discord_linked: bool, school_linked: bool, verified: bool) -> Self {
+        match (discord_linked, school_linked, verified) {
+            (false, _, _) => OnboardingState::NotLogged,
+            (true, _, true) => OnboardingState::Verified,
+            (true, true, false) => OnboardingState::SchoolLinked,
+            (true, false, false) => OnboardingState::DiscordLinked,
+        }
+    }
+
+    /// Route the frontend has to follow to reach the next state
+    pub fn next_step(&self) -> Option<&'static str> {
+        match self {
+            OnboardingState::NotLogged => Some("/login"),
+            OnboardingState::DiscordLinked => Some("/login/adfs"),
+            // The ADFS callback verifies again, `/api/verify` is the POST retrying it in place
+            OnboardingState::SchoolLinked => Some("/login/adfs"),
+            OnboardingState::Verified => None,
+        }
+    }
+}
+
+/// Body of the status endpoint
+#[derive(Serialize)]
+struct Status {
+    state: OnboardingState,
+    next: Option<&'static str>,
+}
+
+impl From<OnboardingState> for Status {
+    fn from(state: OnboardingState) -> Self {
+        Status {
+            next: state.next_step(),
+            state,
+        }
+    }
+}
+
+/// Redirect to the frontend with a `success` or `error` code in the query
+fn redirect_front(result: Result<&str, &str>) -> HttpResponse {
+    let query = match result {
+        Ok(code) => format!("success={}", code),
+        Err(code) => format!("error={}", code),
+    };
+
+    HttpResponse::Found()
+        .append_header((LOCATION, format!("/?{}", query)))
+        .finish()
+}
+
+pub(crate) async fn fetch_user(
+    rb: &Rbatis,
+    discord_id: u64,
+) -> rbatis::Result<Option<DevinciUser>> {
+    rb.fetch_by_column::<Option<DevinciUser>, _>("discord_id", &discord_id)
+        .await
+}
+
+/// Find where the current session stands in the onboarding
+async fn current_state(session: &Session, rb: &Rbatis) -> actix_web::Result<OnboardingState> {
+    let discord_id = match session.get::<u64>(DISCORD_ID)? {
+        Some(id) => id,
+        None => return Ok(OnboardingState::NotLogged),
+    };
+
+    let verified = fetch_user(rb, discord_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .is_some_and(|user| user.verified);
+    let school_linked = session.get::<String>(DEVINCI_TOKEN)?.is_some();
+
+    Ok(OnboardingState::new(true, school_linked, verified))
+}
+
+/// Store the linked accounts and ask the bot to grant the roles
+///
+/// Returns the error code forwarded to the frontend on failure
+async fn verify(
+    session: &Session,
+    rb: &Rbatis,
+    server: &Addr<Server>,
+    auth_devinci: &ADFSAuth,
+) -> Result<DevinciUser, &'static str> {
+    let discord_id = match session.get::<u64>(DISCORD_ID) {
+        Ok(Some(id)) => id,
+        _ => return Err("discord_missing"),
+    };
+    let token = match session.get::<String>(DEVINCI_TOKEN) {
+        Ok(Some(token)) => token,
+        _ => return Err("school_missing"),
+    };
+
+    let mut user = auth_devinci
+        .get_devinci_user(&token)
+        .await
+        .map_err(|_| "adfs_failed")?;
+    user.discord_id = discord_id;
+
+    // An admin may have revoked the verification, relinking must not restore it
+    let existing = fetch_user(rb, discord_id).await.map_err(|_| "database")?;
+    let previous = existing.as_ref().map(DevinciUser::category);
+    let saved = match existing {
+        Some(existing) => {
+            user.verified = existing.verified;
+            rb.update_by_column("discord_id", &user).await.map(|_| ())
+        }
+        None => {
+            user.verified = true;
+            rb.save(&user, &[]).await.map(|_| ())
+        }
+    };
+    saved.map_err(|_| "database")?;
+
+    if !user.verified {
+        return Err("unverified");
+    }
+
+    let request = ServerRequest::Verify {
+        discord_id,
+        category: user.category(),
+        previous,
+        groups: user.group_list(),
+    };
+    outbox::enqueue(rb, server, request).await;
+
+    let event = AuditEvent::new(
+        discord_id,
+        discord_id,
+        AuditAction::UserVerified,
+        user.func.to_string(),
+    );
+    audit::record(rb, event).await;
+
+    Ok(user)
+}
+
+/// Erase the stored user and ask the bot to revoke every access it gave
+///
+/// The audit log and the office sessions keep the events without the user in them, the
+/// outbox only keeps the `Unlink` until the bot applies it. Returns `false` when there was
+/// nothing linked
+pub(crate) async fn unlink(
+    rb: &Rbatis,
+    server: &Addr<Server>,
+    actor: u64,
+    discord_id: u64,
+) -> actix_web::Result<bool> {
+    let user = match fetch_user(rb, discord_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        Some(user) => user,
+        None => return Ok(false),
+    };
+
+    rb.remove_by_column::<DevinciUser, _>("discord_id", &discord_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    // Recorded before the erasure, which pseudonymizes it with the rest
+    let event = AuditEvent::new(actor, discord_id, AuditAction::UserUnlinked, "");
+    audit::record(rb, event).await;
+    audit::erase(rb, discord_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    office_stats::erase(rb, discord_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    outbox::erase(rb, discord_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let request = ServerRequest::Unlink {
+        discord_id,
+        category: user.category(),
+    };
+    outbox::enqueue(rb, server, request).await;
+
+    Ok(true)
+}
+
+/// Start the onboarding, a verification link already proves the discord account
+#[get("/login")]
+async fn login(
+    query: web::Query<LoginQuery>,
+    session: Session,
+    auth: Data<DiscordAuth>,
+    auth_devinci: Data<ADFSAuth>,
+    signer: Data<LinkSigner>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(token) = &query.token {
+        return match signer.verify(token, timestamp()) {
+            Ok(discord_id) => {
+                session.insert(DISCORD_ID, discord_id)?;
+                session.remove(DEVINCI_TOKEN);
+
+                Ok(HttpResponse::Found()
+                    .append_header((LOCATION, auth_devinci.generate_authorize_url()))
+                    .finish())
+            }
+            Err(e) => Ok(redirect_front(Err(e.as_str()))),
+        };
+    }
+
+    Ok(HttpResponse::Found()
+        .append_header((LOCATION, auth.generate_authorize_url()))
+        .finish())
+}
+
+#[get("/login/adfs")]
+async fn login_adfs(session: Session, auth: Data<ADFSAuth>) -> actix_web::Result<HttpResponse> {
+    if session.get::<u64>(DISCORD_ID)?.is_none() {
+        return Ok(redirect_front(Err("discord_missing")));
+    }
+
+    Ok(HttpResponse::Found()
+        .append_header((LOCATION, auth.generate_authorize_url()))
+        .finish())
+}
+
+#[get("/discord")]
+async fn auth_discord(
+    info: web::Query<Callback>,
+    session: Session,
+    oauth_discord: Data<DiscordAuth>,
+    auth_devinci: Data<ADFSAuth>,
+) -> actix_web::Result<HttpResponse> {
+    let code = match (&info.code, &info.error) {
+        (Some(code), None) => code,
+        _ => {
+            metrics::oauth_login("discord", false);
+            return Ok(redirect_front(Err("discord_denied")));
+        }
+    };
+
+    let discord_id = match oauth_discord.get_token(code).await {
+        Ok(token) => oauth_discord.get_id(&token).await.ok(),
+        Err(_) => None,
+    };
+    let discord_id = discord_id.and_then(|id| id.parse::<u64>().ok());
+    metrics::oauth_login("discord", discord_id.is_some());
+
+    match discord_id {
+        Some(id) => {
+            session.insert(DISCORD_ID, id)?;
+            session.remove(DEVINCI_TOKEN);
+
+            Ok(HttpResponse::Found()
+                .append_header((LOCATION, auth_devinci.generate_authorize_url()))
+                .finish())
+        }
+        None => Ok(redirect_front(Err("discord_failed"))),
+    }
+}
+
+#[get("/adfs")]
+async fn auth_adfs(
+    info: web::Query<Callback>,
+    session: Session,
+    auth_devinci: Data<ADFSAuth>,
+    rb: Data<Arc<Rbatis>>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let code = match (&info.code, &info.error) {
+        (Some(code), None) => code,
+        _ => {
+            metrics::oauth_login("adfs", false);
+            return Ok(redirect_front(Err("adfs_denied")));
+        }
+    };
+
+    let token = auth_devinci.get_token(code).await;
+    metrics::oauth_login("adfs", token.is_ok());
+    let token = match token {
+        Ok(token) => token,
+        Err(_) => return Ok(redirect_front(Err("adfs_failed"))),
+    };
+    session.insert(DEVINCI_TOKEN, token)?;
+
+    match verify(&session, &rb, &server, &auth_devinci).await {
+        Ok(_) => Ok(redirect_front(Ok("verified"))),
+        Err(code) => Ok(redirect_front(Err(code))),
+    }
+}
+
+#[get("/api/status")]
+async fn status(session: Session, rb: Data<Arc<Rbatis>>) -> actix_web::Result<HttpResponse> {
+    let state = current_state(&session, &rb).await?;
+
+    Ok(HttpResponse::Ok().json(Status::from(state)))
+}
+
+/// Retry the last step when it failed during the ADFS callback
+#[post("/api/verify")]
+async fn retry_verify(
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    server: Data<Addr<Server>>,
+    auth_devinci: Data<ADFSAuth>,
+) -> actix_web::Result<HttpResponse> {
+    match verify(&session, &rb, &server, &auth_devinci).await {
+        Ok(user) => Ok(HttpResponse::Ok().json(user)),
+        Err(code) => Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": code }))),
+    }
+}
+
+#[get("/api/user")]
+async fn user_info(session: Session, rb: Data<Arc<Rbatis>>) -> actix_web::Result<HttpResponse> {
+    if let Some(discord_id) = session.get::<u64>(DISCORD_ID)? {
+        let user = fetch_user(&rb, discord_id)
+            .await
+            .map_err(ErrorInternalServerError)?;
+        if let Some(user) = user {
+            return Ok(HttpResponse::Ok().json(user));
+        }
+    }
+
+    Ok(HttpResponse::NotFound().finish())
+}
+
+/// Let a user remove the link between their accounts
+#[post("/api/unlink")]
+async fn unlink_self(
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let discord_id = match session.get::<u64>(DISCORD_ID)? {
+        Some(id) => id,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    unlink(&rb, &server, discord_id, discord_id).await?;
+    session.purge();
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Register every onboarding route
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(login)
+        .service(login_adfs)
+        .service(auth_discord)
+        .service(auth_adfs)
+        .service(status)
+        .service(retry_verify)
+        .service(user_info)
+        .service(unlink_self);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        unlink_self,
+        OnboardingState::{self, *},
+    };
+    use actix::Actor;
+    use actix_session::CookieSession;
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, TestRequest},
+        web::Data,
+        App,
+    };
+    use rbatis::rbatis::Rbatis;
+    use shared_lib::socket::server::Server;
+    use std::sync::Arc;
+
+    #[test]
+    fn onboarding_steps() {
+        let cases = [
+            ((false, false, false), NotLogged),
+            ((false, true, true), NotLogged),
+            ((true, false, false), DiscordLinked),
+            ((true, true, false), SchoolLinked),
+            ((true, false, true), Verified),
+            ((true, true, true), Verified),
+        ];
+
+        for ((discord, school, verified), expected) in cases {
+            assert_eq!(OnboardingState::new(discord, school, verified), expected);
+        }
+    }
+
+    #[test]
+    fn next_steps_can_be_followed() {
+        // The frontend navigates to them, they must be GET routes
+        assert_eq!(NotLogged.next_step(), Some("/login"));
+        assert_eq!(DiscordLinked.next_step(), Some("/login/adfs"));
+        assert_eq!(SchoolLinked.next_step(), Some("/login/adfs"));
+        assert_eq!(Verified.next_step(), None);
+    }
+
+    #[actix_web::test]
+    async fn unlinking_needs_a_session() {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(Arc::new(Rbatis::new())))
+                .app_data(Data::new(Server::default().start()))
+                .wrap(CookieSession::signed(&[0; 32]).secure(false))
+                .service(unlink_self),
+        )
+        .await;
+
+        let request = TestRequest::post().uri("/api/unlink").to_request();
+        let response = call_service(&app, request).await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
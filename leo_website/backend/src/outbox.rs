@@ -0,0 +1,447 @@
+use actix::Addr;
+use actix_session::Session;
+use actix_web::{
+    delete,
+    error::{ErrorInternalServerError, ErrorNotFound},
+    get,
+    web::{self, Data},
+    HttpResponse,
+};
+use lazy_static::lazy_static;
+use rbatis::{
+    crud::{Skip, CRUD},
+    plugin::page::PageRequest,
+    rbatis::Rbatis,
+};
+use serde::Deserialize;
+use shared_lib::{
+    audit::{timestamp, AuditAction, AuditEvent},
+    socket::{
+        health::GetStatus,
+        message::{ServerRequest, Traced},
+        server::Server,
+    },
+    trace::RequestId,
+};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+use crate::{
+    admin::{require_admin, Admins, MAX_PAGE_SIZE},
+    audit,
+    models::{OutboxEntry, OutboxStatus},
+};
+
+/// How often the due entries are sent again
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+/// Delay before the first retry in seconds, doubled on every attempt
+const RETRY_DELAY: i64 = 5;
+/// Longest delay between two attempts in seconds
+const MAX_RETRY_DELAY: i64 = 3600;
+/// Attempts before an entry is left to the admins
+const MAX_ATTEMPTS: u32 = 10;
+
+lazy_static! {
+    /// Held while flushing, the retry loop and the commands enqueued meanwhile would otherwise
+    /// send the same entries concurrently, in any order
+    static ref FLUSHING: Mutex<()> = Mutex::new(());
+}
+
+impl OutboxEntry {
+    fn new(request: &ServerRequest, now: i64) -> Self {
+        OutboxEntry {
+            id: None,
+            idempotency_key: format!("{:x}-{:016x}", now, rand::random::<u64>()),
+            request: serde_json::to_string(request).unwrap_or_default(),
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            created_at: now,
+            delivered_at: None,
+            request_id: RequestId::current().map(|id| id.to_string()),
+        }
+    }
+
+    /// Member the command is about, see `ServerRequest::member`
+    fn member(&self) -> Option<u64> {
+        serde_json::from_str::<ServerRequest>(&self.request)
+            .ok()
+            .and_then(|request| request.member())
+    }
+
+    /// Request to send for this attempt, and schedule the next one
+    fn attempt(&mut self, now: i64) -> Option<ServerRequest> {
+        let request = match serde_json::from_str::<ServerRequest>(&self.request) {
+            Ok(request) => request,
+            Err(e) => {
+                tracing::error!("Outbox entry {} is unreadable: {}", self.idempotency_key, e);
+                self.status = OutboxStatus::Failed;
+                return None;
+            }
+        };
+
+        self.attempts += 1;
+        self.next_attempt_at = now + retry_delay(self.attempts);
+        if self.attempts >= MAX_ATTEMPTS {
+            self.status = OutboxStatus::Failed;
+        }
+
+        Some(ServerRequest::Outbox {
+            key: self.idempotency_key.clone(),
+            request: Box::new(request),
+        })
+    }
+}
+
+/// Seconds to wait after the `attempts`th delivery
+fn retry_delay(attempts: u32) -> i64 {
+    (RETRY_DELAY << attempts.min(16)).min(MAX_RETRY_DELAY)
+}
+
+/// Pending `entries` to attempt at `now`, in the order of `entries`
+///
+/// A command waits for the earlier ones about the same member to be delivered or given up,
+/// a `Verify` retried after an `Unverify` would otherwise grant the roles again
+fn due(entries: Vec<OutboxEntry>, now: i64) -> Vec<OutboxEntry> {
+    let mut members = HashSet::new();
+
+    entries
+        .into_iter()
+        .filter(|entry| {
+            let first = entry.member().is_none_or(|member| members.insert(member));
+            first && entry.next_attempt_at <= now
+        })
+        .collect()
+}
+
+/// Store a command for the bot and try to deliver it right away
+///
+/// The command is sent directly when it can't be stored, it's then lost if the bot is offline
+pub async fn enqueue(rb: &Rbatis, server: &Addr<Server>, request: ServerRequest) {
+    let entry = OutboxEntry::new(&request, timestamp());
+
+    match rb.save(&entry, &[Skip::Column("id")]).await {
+        Ok(_) => flush(rb, server).await,
+        Err(e) => {
+            tracing::error!("Couldn't store {:?} in the outbox: {}", request, e);
+            server.do_send(Traced::new(request));
+        }
+    }
+}
+
+/// Drop every command about `discord_id`, part of the erasure of a user
+///
+/// Runs before the `Unlink` is queued, which supersedes the commands still pending
+pub async fn erase(rb: &Rbatis, discord_id: u64) -> rbatis::Result<()> {
+    // The pattern also matches longer ids, `member` tells them apart
+    let wrapper = rb
+        .new_wrapper()
+        .like("request", format!("\"discord_id\":{}", discord_id));
+
+    for entry in rb.fetch_list_by_wrapper::<OutboxEntry>(wrapper).await? {
+        if entry.member() == Some(discord_id) {
+            rb.remove_by_column::<OutboxEntry, _>("id", &entry.id)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Send every due entry, oldest first
+pub async fn flush(rb: &Rbatis, server: &Addr<Server>) {
+    let _flushing = FLUSHING.lock().await;
+
+    // Attempts made while no bot listens would only delay the delivery
+    match server.send(GetStatus).await {
+        Ok(sessions) if !sessions.is_empty() => (),
+        _ => return,
+    }
+
+    let now = timestamp();
+    let wrapper = rb
+        .new_wrapper()
+        .eq("status", OutboxStatus::Pending)
+        .order_by(true, &["id"]);
+
+    let entries = match rb.fetch_list_by_wrapper::<OutboxEntry>(wrapper).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::error!("Couldn't load the outbox: {}", e);
+            return;
+        }
+    };
+
+    for mut entry in due(entries, now) {
+        let attempts = entry.attempts;
+        let request = entry.attempt(now);
+
+        // Saved before sending so the attempt can't overwrite the Ack, and only if the entry
+        // wasn't acknowledged since it was loaded
+        let loaded = rb
+            .new_wrapper()
+            .eq("id", entry.id)
+            .eq("status", OutboxStatus::Pending)
+            .eq("attempts", attempts);
+        match rb
+            .update_by_wrapper(&entry, loaded, &[Skip::Column("id")])
+            .await
+        {
+            Ok(0) => continue,
+            Ok(_) => (),
+            Err(e) => {
+                tracing::error!("Couldn't update outbox entry {:?}: {}", entry, e);
+                continue;
+            }
+        }
+
+        if let Some(request) = request {
+            // Retries keep the request which queued the command
+            let request_id = entry.request_id.as_deref().and_then(RequestId::parse);
+            server.do_send(Traced {
+                request_id,
+                msg: request,
+            });
+        }
+    }
+}
+
+/// The bot applied the command of `key`
+pub async fn ack(rb: &Rbatis, key: &str) {
+    let mut entry = match rb
+        .fetch_by_column::<Option<OutboxEntry>, _>("idempotency_key", key)
+        .await
+    {
+        Ok(Some(entry)) => entry,
+        Ok(None) => {
+            tracing::warn!("Ack of unknown outbox entry {}", key);
+            return;
+        }
+        Err(e) => {
+            tracing::error!("Couldn't load outbox entry {}: {}", key, e);
+            return;
+        }
+    };
+
+    // Nothing is kept about an unlinked member once the bot revoked their access
+    if let Ok(ServerRequest::Unlink { .. }) = serde_json::from_str(&entry.request) {
+        if let Err(e) = rb.remove_by_column::<OutboxEntry, _>("id", &entry.id).await {
+            tracing::error!("Couldn't remove outbox entry {}: {}", key, e);
+        }
+        return;
+    }
+
+    entry.status = OutboxStatus::Delivered;
+    entry.delivered_at = Some(timestamp());
+
+    // Only the status is written, the attempts belong to `flush`. A replayed Ack keeps the
+    // first delivery time, and a command delivered on its last attempt isn't left failed
+    let undelivered = rb
+        .new_wrapper()
+        .eq("idempotency_key", key)
+        .ne("status", OutboxStatus::Delivered);
+    let others = [
+        "id",
+        "idempotency_key",
+        "request",
+        "attempts",
+        "next_attempt_at",
+        "created_at",
+        "request_id",
+    ]
+    .map(Skip::Column);
+
+    if let Err(e) = rb.update_by_wrapper(&entry, undelivered, &others).await {
+        tracing::error!("Couldn't acknowledge outbox entry {}: {}", key, e);
+    }
+}
+
+/// Retry the due entries forever
+pub async fn run(rb: Arc<Rbatis>, server: Addr<Server>) {
+    let mut interval = actix::clock::interval(FLUSH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        flush(&rb, &server).await;
+    }
+}
+
+#[derive(Deserialize)]
+struct OutboxQuery {
+    status: Option<OutboxStatus>,
+    page: Option<u64>,
+    size: Option<u64>,
+}
+
+#[get("/outbox")]
+async fn list_entries(
+    query: web::Query<OutboxQuery>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+) -> actix_web::Result<HttpResponse> {
+    require_admin(&session, &rb, &admins).await?;
+
+    let mut wrapper = rb.new_wrapper();
+    if let Some(status) = query.status {
+        wrapper = wrapper.eq("status", status);
+    }
+
+    let page = PageRequest::new(
+        query.page.unwrap_or(1).max(1),
+        query.size.unwrap_or(20).clamp(1, MAX_PAGE_SIZE),
+    );
+    let entries = rb
+        .fetch_page_by_wrapper::<OutboxEntry>(wrapper.order_by(false, &["id"]), &page)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+#[delete("/outbox/{id}")]
+async fn purge_entry(
+    path: web::Path<i64>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+) -> actix_web::Result<HttpResponse> {
+    let actor = require_admin(&session, &rb, &admins).await?;
+    let id = path.into_inner();
+
+    let removed = rb
+        .remove_by_column::<OutboxEntry, _>("id", &id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if removed == 0 {
+        return Err(ErrorNotFound("unknown outbox entry"));
+    }
+
+    let event = AuditEvent::new(actor, 0, AuditAction::OutboxPurged, format!("entry {}", id));
+    audit::record(&rb, event).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Drop every entry out of attempts
+#[delete("/outbox")]
+async fn purge_failed(
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+) -> actix_web::Result<HttpResponse> {
+    let actor = require_admin(&session, &rb, &admins).await?;
+
+    let wrapper = rb.new_wrapper().eq("status", OutboxStatus::Failed);
+    let removed = rb
+        .remove_by_wrapper::<OutboxEntry>(wrapper)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let details = format!("{} failed entries", removed);
+    let event = AuditEvent::new(actor, 0, AuditAction::OutboxPurged, details);
+    audit::record(&rb, event).await;
+
+    Ok(HttpResponse::Ok().json(removed))
+}
+
+/// Register the outbox routes, they are mounted inside the admin scope
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_entries)
+        .service(purge_entry)
+        .service(purge_failed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{due, retry_delay, MAX_ATTEMPTS};
+    use crate::models::{OutboxEntry, OutboxStatus};
+    use shared_lib::{category::Category, socket::message::ServerRequest};
+
+    #[test]
+    fn retries_back_off() {
+        let delays: Vec<i64> = (1..=4).map(retry_delay).collect();
+
+        assert_eq!(delays, vec![10, 20, 40, 80]);
+        assert_eq!(retry_delay(MAX_ATTEMPTS), 3600);
+    }
+
+    #[test]
+    fn attempts_keep_the_key() {
+        let mut entry = OutboxEntry::new(
+            &ServerRequest::Unlink {
+                discord_id: 7,
+                category: Category::default(),
+            },
+            100,
+        );
+        let other = OutboxEntry::new(
+            &ServerRequest::Unlink {
+                discord_id: 7,
+                category: Category::default(),
+            },
+            100,
+        );
+        assert_ne!(entry.idempotency_key, other.idempotency_key);
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let sent = entry.attempt(100);
+            assert!(matches!(
+                sent,
+                Some(ServerRequest::Outbox { key, request })
+                    if key == entry.idempotency_key
+                        && matches!(*request, ServerRequest::Unlink { discord_id: 7, .. })
+            ));
+            assert_eq!(entry.next_attempt_at, 100 + retry_delay(attempt));
+        }
+        assert_eq!(entry.status, OutboxStatus::Failed);
+    }
+
+    #[test]
+    fn unreadable_entries_fail() {
+        let mut entry = OutboxEntry::new(
+            &ServerRequest::Unlink {
+                discord_id: 7,
+                category: Category::default(),
+            },
+            100,
+        );
+        entry.request = "{".to_string();
+
+        assert!(entry.attempt(100).is_none());
+        assert_eq!((entry.status, entry.attempts), (OutboxStatus::Failed, 0));
+    }
+
+    #[test]
+    fn commands_about_a_member_stay_in_order() {
+        let entry = |id, request, next_attempt_at| OutboxEntry {
+            id: Some(id),
+            next_attempt_at,
+            ..OutboxEntry::new(&request, 100)
+        };
+        let unverify = |discord_id| ServerRequest::Unverify {
+            discord_id,
+            category: Category::default(),
+        };
+        let entries = vec![
+            // Failed at the bot, waiting for its retry
+            entry(
+                1,
+                ServerRequest::Verify {
+                    discord_id: 7,
+                    category: Category::default(),
+                    previous: None,
+                    groups: Vec::new(),
+                },
+                200,
+            ),
+            entry(2, unverify(7), 100),
+            entry(3, unverify(8), 100),
+            entry(4, ServerRequest::OfficeHours(Vec::new()), 100),
+        ];
+
+        let ids: Vec<_> = due(entries, 100).iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![Some(3), Some(4)]);
+    }
+}
@@ -0,0 +1,244 @@
+use actix::Addr;
+use actix_session::Session;
+use actix_web::{
+    error::ErrorInternalServerError,
+    post,
+    web::{self, Data},
+    HttpResponse,
+};
+use rbatis::{crud::CRUD, rbatis::Rbatis};
+use serde::{Deserialize, Serialize};
+use shared_lib::{
+    audit::{timestamp, AuditAction, AuditEvent},
+    category::Category,
+    socket::{message::ServerRequest, server::Server},
+};
+use std::sync::Arc;
+
+use crate::{
+    admin::{require_admin, Admins},
+    audit,
+    models::DevinciUser,
+    outbox,
+};
+
+/// Claims older than this are considered outdated by default (about a semester)
+const DEFAULT_FRESHNESS: i64 = 120 * 24 * 60 * 60;
+
+#[derive(Deserialize)]
+struct RolloverRequest {
+    /// Only preview the changes when false
+    #[serde(default)]
+    apply: bool,
+    /// Users whose type was set after this unix timestamp keep it
+    since: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct Promotion {
+    discord_id: u64,
+    first_name: String,
+    last_name: String,
+    from: Category,
+    to: Category,
+}
+
+#[derive(Serialize, Default)]
+struct RolloverReport {
+    applied: bool,
+    promoted: usize,
+    graduated: usize,
+    /// Users kept as is because their type comes from recent claims
+    fresh: usize,
+    promotions: Vec<Promotion>,
+    /// Users who couldn't be updated, applying again retries them
+    failed: Vec<u64>,
+}
+
+/// Compute the category of `user` for the next academic year
+///
+/// Returns `None` when the current one was derived from recent claims: the claims of the
+/// last login are applied then, the ADFS tokens aren't kept to fetch newer ones
+fn next_category(user: &DevinciUser, since: i64) -> Option<Category> {
+    if user.func_updated_at >= since {
+        return None;
+    }
+
+    let mut category = user.category();
+    category.year = category.year.filter(|y| *y < 5).map(|y| y + 1);
+
+    Some(category)
+}
+
+fn build_report(users: &[DevinciUser], since: i64) -> RolloverReport {
+    let mut report = RolloverReport::default();
+
+    // Unverified users have no roles to swap, a promotion would grant them back
+    for user in users.iter().filter(|user| user.verified) {
+        let to = match next_category(user, since) {
+            Some(to) => to,
+            None => {
+                report.fresh += 1;
+                continue;
+            }
+        };
+
+        match to.year {
+            Some(_) => report.promoted += 1,
+            None => report.graduated += 1,
+        }
+
+        report.promotions.push(Promotion {
+            discord_id: user.discord_id,
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            from: user.category(),
+            to,
+        });
+    }
+
+    report
+}
+
+/// Move every student to their next year, dry-run unless `apply` is set
+#[post("/rollover")]
+async fn rollover(
+    body: web::Json<RolloverRequest>,
+    session: Session,
+    rb: Data<Arc<Rbatis>>,
+    admins: Data<Admins>,
+    server: Data<Addr<Server>>,
+) -> actix_web::Result<HttpResponse> {
+    let actor = require_admin(&session, &rb, &admins).await?;
+
+    let now = timestamp();
+    let since = body.since.unwrap_or(now - DEFAULT_FRESHNESS);
+
+    let mut students = rb
+        .fetch_list_by_wrapper::<DevinciUser>(
+            rb.new_wrapper().between("func", 1, 5).eq("verified", true),
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut report = build_report(&students, since);
+
+    if body.apply {
+        for promotion in &report.promotions {
+            let user = match students
+                .iter_mut()
+                .find(|u| u.discord_id == promotion.discord_id)
+            {
+                Some(user) => user,
+                None => continue,
+            };
+
+            user.set_category(promotion.to.clone());
+            user.func_updated_at = now;
+            // The other users are still promoted, the failed ones are listed in the report
+            if let Err(e) = rb.update_by_column("discord_id", &*user).await {
+                tracing::error!("Couldn't promote {}: {}", user.discord_id, e);
+                report.failed.push(user.discord_id);
+                continue;
+            }
+
+            let request = ServerRequest::Promote {
+                discord_id: promotion.discord_id,
+                from: promotion.from.clone(),
+                to: promotion.to.clone(),
+            };
+            outbox::enqueue(&rb, &server, request).await;
+        }
+
+        report.applied = true;
+
+        let mut details = format!(
+            "{} promoted, {} graduated",
+            report.promoted, report.graduated
+        );
+        if !report.failed.is_empty() {
+            let failed: Vec<String> = report.failed.iter().map(u64::to_string).collect();
+            details.push_str(&format!(", failed: {}", failed.join(",")));
+        }
+        audit::record(
+            &rb,
+            AuditEvent::new(actor, 0, AuditAction::YearsRolledOver, details),
+        )
+        .await;
+    }
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(rollover);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_report, next_category};
+    use crate::models::DevinciUser;
+    use shared_lib::category::Category;
+
+    const SINCE: i64 = 1_000;
+
+    fn student(discord_id: u64, year: u8, updated_at: i64) -> DevinciUser {
+        let mut user = DevinciUser {
+            discord_id,
+            first_name: "Jean".to_string(),
+            last_name: "Marchand".to_string(),
+            mail: String::new(),
+            func: 0,
+            school: None,
+            program: None,
+            year: None,
+            apprentice: false,
+            staff: None,
+            groups: String::new(),
+            verified: true,
+            func_updated_at: updated_at,
+        };
+        user.set_category(Category {
+            school: Some("esilv".to_string()),
+            year: Some(year),
+            ..Category::default()
+        });
+
+        user
+    }
+
+    #[test]
+    fn students_move_up_a_year() {
+        let next = |user: &DevinciUser| next_category(user, SINCE).map(|c| c.year);
+
+        assert_eq!(next(&student(1, 1, 0)), Some(Some(2)));
+        assert_eq!(next(&student(1, 5, 0)), Some(None));
+        assert_eq!(next(&student(1, 3, SINCE)), None);
+        assert_eq!(
+            next_category(&student(1, 4, 0), SINCE)
+                .unwrap()
+                .school
+                .as_deref(),
+            Some("esilv")
+        );
+    }
+
+    #[test]
+    fn report_counts() {
+        let mut unverified = student(4, 2, 0);
+        unverified.verified = false;
+        let users = [
+            student(1, 1, 0),
+            student(2, 5, 0),
+            student(3, 2, SINCE + 1),
+            unverified,
+        ];
+
+        let report = build_report(&users, SINCE);
+        let promoted: Vec<u64> = report.promotions.iter().map(|p| p.discord_id).collect();
+
+        assert_eq!((report.promoted, report.graduated, report.fresh), (1, 1, 1));
+        assert_eq!(promoted, [1, 2]);
+        assert!(!report.applied && report.failed.is_empty());
+    }
+}
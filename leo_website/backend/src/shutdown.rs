@@ -0,0 +1,48 @@
+use actix::Addr;
+use actix_web::dev::ServerHandle;
+use rbatis::rbatis::Rbatis;
+use shared_lib::{
+    shutdown,
+    socket::{message::Close, server::Server, session::Listener},
+};
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    bot::{BotHandler, Drain},
+    outbox,
+};
+
+/// Stop everything once the process is asked to, within `timeout`
+///
+/// The bot sockets stop accepting first, then the HTTP requests and the bot messages in flight
+/// are finished. Meanwhile the due outbox entries get a last chance and the sessions are told
+/// goodbye, a WebSocket session is an HTTP request the graceful stop would wait for
+pub async fn run(
+    http: ServerHandle,
+    listener: Listener,
+    rb: Arc<Rbatis>,
+    server: Addr<Server>,
+    bot_handler: Addr<BotHandler>,
+    timeout: Duration,
+) {
+    shutdown::signal().await;
+    tracing::info!("Shutting down within {:?}", timeout);
+
+    let graceful = async {
+        listener.close();
+        let bot = async {
+            if bot_handler.send(Drain).await.is_err() {
+                tracing::warn!("Bot handler is gone, its messages in flight are lost");
+            }
+            // Commands queued after the flush stay in the outbox for the next start
+            outbox::flush(&rb, &server).await;
+            server.send(Close).await.ok();
+        };
+        futures::join!(http.stop(true), bot);
+    };
+
+    if actix::clock::timeout(timeout, graceful).await.is_err() {
+        tracing::warn!("Shutdown deadline reached, stopping now");
+        http.stop(false).await;
+    }
+}
@@ -0,0 +1,77 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures::FutureExt;
+use shared_lib::trace::{self, RequestId};
+use std::{future::Future, time::Instant};
+use tracing::Instrument;
+
+use crate::metrics;
+
+/// Header carrying the request ID, reused when a proxy already set it
+const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Route counted for the requests that didn't match any
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Handle every HTTP request in a span carrying its request ID, echoed in the response, and
+/// count it in the metrics
+///
+/// The ID is the current one of the task meanwhile, so the bot commands sent by the
+/// request carry it over the socket
+pub fn traced<S, B>(
+    req: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(RequestId::parse)
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "http",
+        request_id = request_id.as_str(),
+        method = %req.method(),
+        path = req.path(),
+    );
+    let method = req.method().to_string();
+    let start = Instant::now();
+
+    let response = span.in_scope(|| trace::sync_scope(request_id.clone(), || service.call(req)));
+    let header = HeaderValue::from_str(request_id.as_str()).ok();
+
+    let response = trace::scope(Some(request_id), response).map(move |response| {
+        let duration = start.elapsed();
+        let elapsed = duration.as_millis() as u64;
+
+        match response {
+            Ok(mut response) => {
+                let status = response.status().as_u16();
+                let route = response.request().match_pattern();
+                let route = route.as_deref().unwrap_or(UNMATCHED_ROUTE);
+                metrics::observe_request(&method, route, status, duration);
+
+                tracing::info!(status, elapsed, "Handled");
+                if let Some(header) = header {
+                    let name = HeaderName::from_static(REQUEST_ID_HEADER);
+                    response.headers_mut().insert(name, header);
+                }
+                Ok(response)
+            }
+            Err(e) => {
+                let status = e.as_response_error().status_code().as_u16();
+                metrics::observe_request(&method, UNMATCHED_ROUTE, status, duration);
+
+                tracing::warn!(error = %e, elapsed, "Failed");
+                Err(e)
+            }
+        }
+    });
+
+    response.instrument(span)
+}
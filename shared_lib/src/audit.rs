@@ -0,0 +1,144 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix::Message;
+use serde::{Deserialize, Serialize};
+
+/// Kind of action recorded in the audit log
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    SubjectOpened,
+    SubjectClosed,
+    OfficeOpened,
+    OfficeClosed,
+    UserVerified,
+    UserUnverified,
+    UserUnlinked,
+    FuncUpdated,
+    RolesResynced,
+    YearsRolledOver,
+    MemberKicked,
+    OutboxPurged,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::SubjectOpened => "subject_opened",
+            AuditAction::SubjectClosed => "subject_closed",
+            AuditAction::OfficeOpened => "office_opened",
+            AuditAction::OfficeClosed => "office_closed",
+            AuditAction::UserVerified => "user_verified",
+            AuditAction::UserUnverified => "user_unverified",
+            AuditAction::UserUnlinked => "user_unlinked",
+            AuditAction::FuncUpdated => "func_updated",
+            AuditAction::RolesResynced => "roles_resynced",
+            AuditAction::YearsRolledOver => "years_rolled_over",
+            AuditAction::MemberKicked => "member_kicked",
+            AuditAction::OutboxPurged => "outbox_purged",
+        }
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "subject_opened" => Ok(AuditAction::SubjectOpened),
+            "subject_closed" => Ok(AuditAction::SubjectClosed),
+            "office_opened" => Ok(AuditAction::OfficeOpened),
+            "office_closed" => Ok(AuditAction::OfficeClosed),
+            "user_verified" => Ok(AuditAction::UserVerified),
+            "user_unverified" => Ok(AuditAction::UserUnverified),
+            "user_unlinked" => Ok(AuditAction::UserUnlinked),
+            "func_updated" => Ok(AuditAction::FuncUpdated),
+            "roles_resynced" => Ok(AuditAction::RolesResynced),
+            "years_rolled_over" => Ok(AuditAction::YearsRolledOver),
+            "member_kicked" => Ok(AuditAction::MemberKicked),
+            "outbox_purged" => Ok(AuditAction::OutboxPurged),
+            _ => Err(format!("unknown audit action: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Current unix timestamp in seconds
+pub fn timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Something worth keeping a trace of, done by `actor` on `target`
+///
+/// Both ids are discord snowflakes (user, channel...)
+#[derive(Serialize, Deserialize, Message, Debug, Clone, PartialEq)]
+#[rtype(result = "()")]
+pub struct AuditEvent {
+    pub actor: u64,
+    pub target: u64,
+    pub action: AuditAction,
+    /// Unix timestamp in seconds
+    pub timestamp: i64,
+    pub details: String,
+}
+
+impl AuditEvent {
+    /// Create an event happening now
+    pub fn new(actor: u64, target: u64, action: AuditAction, details: impl Into<String>) -> Self {
+        AuditEvent {
+            actor,
+            target,
+            action,
+            timestamp: timestamp(),
+            details: details.into(),
+        }
+    }
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] <@{}> -> {}", self.action, self.actor, self.target)?;
+
+        if !self.details.is_empty() {
+            write!(f, " ({})", self.details)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditAction, AuditEvent};
+
+    #[test]
+    fn action_round_trip() {
+        let action = AuditAction::OfficeOpened;
+
+        assert_eq!(action.as_str().parse::<AuditAction>(), Ok(action));
+        assert_eq!(
+            serde_json::to_string(&action).unwrap(),
+            format!("\"{}\"", action)
+        );
+        assert!("unknown".parse::<AuditAction>().is_err());
+    }
+
+    #[test]
+    fn event_display() {
+        let event = AuditEvent::new(1, 2, AuditAction::SubjectOpened, "💛");
+
+        assert_eq!(event.to_string(), "[subject_opened] <@1> -> 2 (💛)");
+    }
+}
@@ -0,0 +1,120 @@
+//! Stand-in for the backend, to develop the bot without the website and its database
+//!
+//! `mock_backend [ENDPOINT] [--record FILE] [--replay FILE]`
+//!
+//! Listens on `ENDPOINT` or `BOT_SOCKET`, prints every message of the bot and sends the
+//! requests typed on stdin, one JSON message per line.
+
+use std::{
+    env, io, process,
+    sync::{Arc, Mutex},
+};
+
+use actix::prelude::*;
+use actix_web::{App, HttpServer};
+use shared_lib::socket::{
+    health::Heartbeat,
+    message::{BotResponse, ServerRequest, Traced},
+    mock::{console, load, replay, Options, Side, Tape, Traffic},
+    router::Router,
+    server::Server,
+    session::{listen, websocket_route},
+    transport::Endpoint,
+};
+
+struct MockBackend {
+    tape: Arc<Mutex<Tape>>,
+}
+
+impl Actor for MockBackend {
+    type Context = Context<Self>;
+}
+
+impl Handler<Traced<BotResponse>> for MockBackend {
+    type Result = ();
+
+    fn handle(&mut self, msg: Traced<BotResponse>, _: &mut Context<Self>) {
+        self.tape.lock().unwrap().log(Traffic::Response(msg.msg));
+    }
+}
+
+/// `host:port` of a WebSocket url, the HTTP server has to bind it
+fn websocket_address(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?']).next()?;
+
+    match (authority.contains(':'), scheme) {
+        (true, _) => Some(authority.to_string()),
+        (false, "wss") => Some(format!("{}:443", authority)),
+        (false, _) => Some(format!("{}:80", authority)),
+    }
+}
+
+fn main() -> io::Result<()> {
+    let options = Options::from_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    let endpoint: Endpoint = options
+        .endpoint
+        .clone()
+        .or_else(|| env::var("BOT_SOCKET").ok())
+        .unwrap_or_else(|| "tcp://0.0.0.0:1234".to_string())
+        .parse()
+        .expect("bot socket url");
+    let endpoint = endpoint.with_secret(env::var("SOCKET_SECRET").ok());
+    let tape = Tape::new(Side::Backend, options.record.as_deref())?;
+    let tape = Arc::new(Mutex::new(tape));
+
+    let system = System::new();
+    system.block_on(start(endpoint, tape, options))?;
+    // Until stdin is closed
+    system.run()
+}
+
+async fn start(endpoint: Endpoint, tape: Arc<Mutex<Tape>>, options: Options) -> io::Result<()> {
+    let backend = MockBackend { tape: tape.clone() }.start();
+    let router = Router::new().route_all(backend.recipient());
+    let server = Server::default().with_router(router).start();
+    let heartbeat = Heartbeat::from_env().expect("heartbeat settings");
+
+    listen(&endpoint, server.clone(), heartbeat).await?;
+    if let Endpoint::WebSocket { url, .. } = &endpoint {
+        let address = websocket_address(url).expect("websocket url");
+        let server = server.clone();
+        let http = HttpServer::new(move || {
+            App::new().service(
+                websocket_route(&endpoint, server.clone(), heartbeat)
+                    .unwrap()
+                    .unwrap(),
+            )
+        })
+        .bind(address)?
+        .run();
+        actix_web::rt::spawn(http);
+    }
+    println!("Waiting for the bot");
+
+    match options.replay {
+        Some(path) => {
+            let exchanges = load(&path)?;
+            let differences = replay(&tape, &exchanges, |traffic| {
+                if let Traffic::Request(request) = traffic {
+                    server.do_send(request);
+                }
+            })
+            .await;
+
+            for difference in &differences {
+                println!("{}", difference);
+            }
+            process::exit(if differences.is_empty() { 0 } else { 1 });
+        }
+        None => console(move |request: ServerRequest| {
+            tape.lock().unwrap().log(Traffic::Request(request.clone()));
+            server.do_send(request);
+        }),
+    }
+
+    Ok(())
+}
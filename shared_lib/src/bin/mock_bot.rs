@@ -0,0 +1,123 @@
+//! Stand-in for the discord bot, to develop the backend without a discord server
+//!
+//! `mock_bot [ENDPOINT] [--record FILE] [--replay FILE]`
+//!
+//! Connects to `ENDPOINT` or `BACKEND_SOCKET`, prints every request and sends the responses
+//! typed on stdin, one JSON message per line. Outbox commands are acknowledged right away.
+
+use std::{
+    env, process,
+    sync::{Arc, Mutex},
+};
+
+use actix::prelude::*;
+use shared_lib::socket::{
+    client::connect,
+    health::Heartbeat,
+    message::{BotResponse, ServerRequest, Traced},
+    mock::{console, load, replay, Options, Side, Tape, Traffic},
+    router::Router,
+    transport::Endpoint,
+};
+
+struct MockBot {
+    tape: Arc<Mutex<Tape>>,
+    client: Recipient<Traced<BotResponse>>,
+}
+
+impl MockBot {
+    fn send(&self, response: Traced<BotResponse>) {
+        self.tape
+            .lock()
+            .unwrap()
+            .log(Traffic::Response(response.msg.clone()));
+        self.client.do_send(response).ok();
+    }
+}
+
+impl Actor for MockBot {
+    type Context = Context<Self>;
+}
+
+impl Handler<Traced<ServerRequest>> for MockBot {
+    type Result = ();
+
+    fn handle(&mut self, msg: Traced<ServerRequest>, _: &mut Context<Self>) {
+        self.tape
+            .lock()
+            .unwrap()
+            .log(Traffic::Request(msg.msg.clone()));
+
+        // The backend retries unacknowledged commands
+        if let ServerRequest::Outbox { key, .. } = msg.msg {
+            self.send(Traced {
+                request_id: msg.request_id,
+                msg: BotResponse::Ack { key },
+            });
+        }
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let options = Options::from_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    let backend: Endpoint = options
+        .endpoint
+        .clone()
+        .or_else(|| env::var("BACKEND_SOCKET").ok())
+        .unwrap_or_else(|| "tcp://127.0.0.1:1234".to_string())
+        .parse()
+        .expect("backend socket url");
+    let backend = backend.with_secret(env::var("SOCKET_SECRET").ok());
+    let tape = Tape::new(Side::Bot, options.record.as_deref())?;
+    let tape = Arc::new(Mutex::new(tape));
+
+    let system = System::new();
+    system.block_on(start(backend, tape, options))?;
+    // Until the backend disconnects or stdin is closed
+    system.run()
+}
+
+async fn start(backend: Endpoint, tape: Arc<Mutex<Tape>>, options: Options) -> std::io::Result<()> {
+    // The mock and the socket client need each other's address
+    let ctx = Context::<MockBot>::new();
+    let router = Router::new().route_all(ctx.address().recipient());
+    let client = connect(
+        &backend,
+        router,
+        Heartbeat::from_env().expect("heartbeat settings"),
+    )
+    .await?;
+    ctx.run(MockBot {
+        tape: tape.clone(),
+        client: client.clone().recipient(),
+    });
+    println!("Connected to {}", backend);
+
+    match options.replay {
+        Some(path) => {
+            let exchanges = load(&path)?;
+            let differences = replay(&tape, &exchanges, |traffic| {
+                if let Traffic::Response(response) = traffic {
+                    client.do_send(response);
+                }
+            })
+            .await;
+
+            for difference in &differences {
+                println!("{}", difference);
+            }
+            process::exit(if differences.is_empty() { 0 } else { 1 });
+        }
+        None => console(move |response: BotResponse| {
+            tape.lock()
+                .unwrap()
+                .log(Traffic::Response(response.clone()));
+            client.do_send(response);
+        }),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+/// Kind of staff member
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StaffKind {
+    Staff,
+    Intervenant,
+}
+
+impl StaffKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StaffKind::Staff => "staff",
+            StaffKind::Intervenant => "intervenant",
+        }
+    }
+}
+
+/// Category of a user derived from their school groups
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Category {
+    pub school: Option<String>,
+    pub program: Option<String>,
+    pub year: Option<u8>,
+    pub apprentice: bool,
+    pub staff: Option<StaffKind>,
+}
+
+impl Category {
+    /// Keys of the roles a verified member of this category holds
+    ///
+    /// The bot maps them to discord roles through its `roles` config
+    pub fn role_keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+
+        if let Some(staff) = self.staff {
+            keys.push("teacher".to_string());
+            keys.push(format!("staff:{}", staff.as_str()));
+        }
+        if let Some(year) = self.year {
+            keys.push(format!("a{}", year));
+        }
+        if let Some(school) = &self.school {
+            keys.push(format!("school:{}", school));
+        }
+        if let Some(program) = &self.program {
+            keys.push(format!("program:{}", program));
+        }
+        if self.apprentice {
+            keys.push("apprentice".to_string());
+        }
+
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Category, StaffKind};
+
+    #[test]
+    fn role_keys() {
+        let student = Category {
+            school: Some("esilv".to_string()),
+            program: None,
+            year: Some(3),
+            apprentice: true,
+            staff: None,
+        };
+        let staff = Category {
+            staff: Some(StaffKind::Intervenant),
+            ..Category::default()
+        };
+
+        assert_eq!(
+            student.role_keys(),
+            vec!["a3", "school:esilv", "apprentice"]
+        );
+        assert_eq!(staff.role_keys(), vec!["teacher", "staff:intervenant"]);
+        assert!(Category::default().role_keys().is_empty());
+    }
+}
@@ -1 +1,9 @@
+pub mod audit;
+pub mod category;
+pub mod link;
+pub mod metrics;
+pub mod office;
+pub mod shutdown;
 pub mod socket;
+pub mod trace;
+pub mod user;
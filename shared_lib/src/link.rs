@@ -0,0 +1,117 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Reason a verification link is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkError {
+    Malformed,
+    BadSignature,
+    Expired,
+}
+
+impl LinkError {
+    /// Error code forwarded to the frontend
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkError::Malformed | LinkError::BadSignature => "link_invalid",
+            LinkError::Expired => "link_expired",
+        }
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Signs the verification links the bot sends to new members
+///
+/// A token is `<discord id>.<expiration timestamp>.<hex HMAC-SHA256>`, the
+/// bot and the backend share the secret
+#[derive(Clone)]
+pub struct LinkSigner {
+    secret: Vec<u8>,
+}
+
+impl LinkSigner {
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        LinkSigner {
+            secret: secret.as_ref().to_vec(),
+        }
+    }
+
+    fn mac(&self, discord_id: u64, expires_at: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("any key length");
+        mac.update(format!("{}.{}", discord_id, expires_at).as_bytes());
+        mac
+    }
+
+    /// Token binding a link to `discord_id` until `expires_at`
+    pub fn sign(&self, discord_id: u64, expires_at: i64) -> String {
+        let signature = self.mac(discord_id, expires_at).finalize().into_bytes();
+
+        format!("{}.{}.{}", discord_id, expires_at, hex::encode(signature))
+    }
+
+    /// Discord id bound to `token` if it's genuine and not expired at `now`
+    pub fn verify(&self, token: &str, now: i64) -> Result<u64, LinkError> {
+        let mut parts = token.splitn(3, '.');
+        let (discord_id, expires_at, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(id), Some(expires), Some(signature)) => (id, expires, signature),
+            _ => return Err(LinkError::Malformed),
+        };
+
+        let discord_id = discord_id
+            .parse::<u64>()
+            .map_err(|_| LinkError::Malformed)?;
+        let expires_at = expires_at
+            .parse::<i64>()
+            .map_err(|_| LinkError::Malformed)?;
+        let signature = hex::decode(signature).map_err(|_| LinkError::Malformed)?;
+
+        self.mac(discord_id, expires_at)
+            .verify_slice(&signature)
+            .map_err(|_| LinkError::BadSignature)?;
+
+        match now <= expires_at {
+            true => Ok(discord_id),
+            false => Err(LinkError::Expired),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LinkError, LinkSigner};
+
+    #[test]
+    fn sign_and_verify() {
+        let signer = LinkSigner::new("secret");
+        let token = signer.sign(42, 100);
+
+        assert_eq!(signer.verify(&token, 100), Ok(42));
+        assert_eq!(signer.verify(&token, 101), Err(LinkError::Expired));
+        assert_eq!(
+            LinkSigner::new("other").verify(&token, 0),
+            Err(LinkError::BadSignature)
+        );
+    }
+
+    #[test]
+    fn tampered_tokens() {
+        let signer = LinkSigner::new("secret");
+        let token = signer.sign(42, 100);
+
+        let other_id = token.replacen("42", "43", 1);
+        assert_eq!(signer.verify(&other_id, 0), Err(LinkError::BadSignature));
+        let later = token.replacen("100", "999", 1);
+        assert_eq!(signer.verify(&later, 0), Err(LinkError::BadSignature));
+
+        assert_eq!(signer.verify("42.100", 0), Err(LinkError::Malformed));
+        assert_eq!(signer.verify("42.100.zz", 0), Err(LinkError::Malformed));
+    }
+}
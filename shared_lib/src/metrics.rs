@@ -0,0 +1,53 @@
+use actix_web::HttpResponse;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_int_counter_vec, register_int_gauge, Encoder, IntCounterVec, IntGauge, TextEncoder,
+};
+
+lazy_static! {
+    /// Socket sessions open, the connected bots on the backend and the backend link on the bot
+    pub static ref SOCKET_SESSIONS: IntGauge =
+        register_int_gauge!("leo_socket_sessions", "Socket sessions open").unwrap();
+    /// Socket frames by direction, `in` or `out`, and message kind
+    pub static ref SOCKET_FRAMES: IntCounterVec = register_int_counter_vec!(
+        "leo_socket_frames_total",
+        "Socket frames received and sent",
+        &["direction", "kind"]
+    )
+    .unwrap();
+}
+
+/// Kind counted for the frames that couldn't be decoded
+pub const UNSUPPORTED_KIND: &str = "unsupported";
+
+/// Every metric of the process in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        tracing::error!("Couldn't encode the metrics: {}", e);
+    }
+
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+/// Answer of the `/metrics` endpoints, once the metrics read at scrape time are set
+pub fn response() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, SOCKET_FRAMES};
+
+    #[test]
+    fn rendered_as_text() {
+        SOCKET_FRAMES.with_label_values(&["in", "Rendered"]).inc();
+
+        let text = render();
+        assert!(text.contains("# TYPE leo_socket_frames_total counter"));
+        assert!(text.contains(r#"leo_socket_frames_total{direction="in",kind="Rendered"} 1"#));
+    }
+}
@@ -0,0 +1,280 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Weekly office hours declared by a teacher
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OfficeHours {
+    /// Set by the backend, ignored on creation
+    pub id: i64,
+    pub teacher: u64,
+    /// Id of the subjects message whose channels get the announcement
+    pub subject: u64,
+    /// 0 for monday
+    pub weekday: u8,
+    /// Minutes since midnight, local time
+    pub start: u16,
+    /// Minutes since midnight, local time
+    pub end: u16,
+    /// IANA name of the timezone, e.g. `Europe/Paris`
+    pub timezone: String,
+}
+
+impl OfficeHours {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.weekday as usize >= WEEKDAYS.len() {
+            return Err(format!("invalid weekday: {}", self.weekday));
+        }
+        if self.start >= self.end || self.end > MINUTES_PER_DAY {
+            return Err("office hours must start before they end".to_string());
+        }
+        self.timezone.parse::<Tz>()?;
+
+        Ok(())
+    }
+
+    /// Whether the office is scheduled at `now`
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let tz = match self.timezone.parse::<Tz>() {
+            Ok(tz) => tz,
+            Err(_) => return false,
+        };
+
+        let local = now.with_timezone(&tz);
+        let minutes = (local.hour() * 60 + local.minute()) as u16;
+
+        local.weekday().num_days_from_monday() == self.weekday as u32
+            && (self.start..self.end).contains(&minutes)
+    }
+}
+
+/// Change asked by a teacher with `!hours`, answered with `ServerRequest::OfficeHoursChanged`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum HoursChange {
+    Added,
+    Removed(i64),
+}
+
+/// Time spent by a student in an office, timestamps in unix seconds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OfficeVisit {
+    pub student: u64,
+    /// Seconds spent in the waiting room, none when the student walked in
+    pub waited: Option<i64>,
+    pub joined_at: i64,
+    pub left_at: i64,
+}
+
+impl OfficeVisit {
+    pub fn duration(&self) -> i64 {
+        (self.left_at - self.joined_at).max(0)
+    }
+}
+
+/// An office from its creation to its deletion
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OfficeSession {
+    pub teacher: u64,
+    /// Subjects message of the office hours the office was opened for
+    pub subject: Option<u64>,
+    pub opened_at: i64,
+    pub closed_at: i64,
+    pub visits: Vec<OfficeVisit>,
+}
+
+/// Summary of office sessions
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OfficeStats {
+    pub sessions: usize,
+    /// Seconds the offices were open
+    pub open_time: i64,
+    pub visits: usize,
+    /// Visits coming from the waiting room
+    pub admitted: usize,
+    pub students: usize,
+    /// Seconds, over the admitted visits
+    pub average_wait: i64,
+    /// Seconds
+    pub average_visit: i64,
+}
+
+impl OfficeStats {
+    pub fn from_sessions(sessions: &[OfficeSession]) -> Self {
+        let visits: Vec<&OfficeVisit> = sessions.iter().flat_map(|s| &s.visits).collect();
+        let waits: Vec<i64> = visits.iter().filter_map(|v| v.waited).collect();
+        let mut students: Vec<u64> = visits.iter().map(|v| v.student).collect();
+        students.sort_unstable();
+        students.dedup();
+
+        let average = |total: i64, count: usize| match count {
+            0 => 0,
+            n => total / n as i64,
+        };
+
+        OfficeStats {
+            sessions: sessions.len(),
+            open_time: sessions
+                .iter()
+                .map(|s| (s.closed_at - s.opened_at).max(0))
+                .sum(),
+            visits: visits.len(),
+            admitted: waits.len(),
+            students: students.len(),
+            average_wait: average(waits.iter().sum(), waits.len()),
+            average_visit: average(visits.iter().map(|v| v.duration()).sum(), visits.len()),
+        }
+    }
+}
+
+/// Parse `mon`...`sun` (or a full english name) into a weekday, 0 for monday
+pub fn parse_weekday(s: &str) -> Option<u8> {
+    let s = s.to_lowercase();
+
+    WEEKDAYS
+        .iter()
+        .position(|d| s.starts_with(d))
+        .map(|d| d as u8)
+}
+
+/// Parse `HH:MM` into minutes since midnight, `24:00` is accepted
+pub fn parse_time(s: &str) -> Option<u16> {
+    let (hours, minutes) = s.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u16>().ok()?, minutes.parse::<u16>().ok()?);
+
+    match hours.checked_mul(60)?.checked_add(minutes)? {
+        total if minutes < 60 && total <= MINUTES_PER_DAY => Some(total),
+        _ => None,
+    }
+}
+
+/// Format minutes since midnight as `HH:MM`
+pub fn format_time(minutes: u16) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Short name of a weekday, 0 for monday
+pub fn format_weekday(weekday: u8) -> &'static str {
+    WEEKDAYS.get(weekday as usize).copied().unwrap_or("?")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        format_time, parse_time, parse_weekday, OfficeHours, OfficeSession, OfficeStats,
+        OfficeVisit,
+    };
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn hours() -> OfficeHours {
+        OfficeHours {
+            id: 0,
+            teacher: 1,
+            subject: 2,
+            weekday: 1,
+            start: 14 * 60,
+            end: 16 * 60,
+            timezone: "Europe/Paris".to_string(),
+        }
+    }
+
+    #[test]
+    fn validate() {
+        assert!(hours().validate().is_ok());
+        assert!(OfficeHours {
+            weekday: 7,
+            ..hours()
+        }
+        .validate()
+        .is_err());
+        assert!(OfficeHours {
+            end: 14 * 60,
+            ..hours()
+        }
+        .validate()
+        .is_err());
+        assert!(OfficeHours {
+            end: 24 * 60 + 1,
+            ..hours()
+        }
+        .validate()
+        .is_err());
+        assert!(OfficeHours {
+            timezone: "Mars/Olympus".to_string(),
+            ..hours()
+        }
+        .validate()
+        .is_err());
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 11, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn is_active() {
+        let hours = hours();
+
+        // 2021-11-16 is a tuesday, Paris is UTC+1 in winter
+        assert!(hours.is_active(at(16, 13, 0)));
+        assert!(hours.is_active(at(16, 14, 59)));
+        assert!(!hours.is_active(at(16, 15, 0)));
+        assert!(!hours.is_active(at(16, 12, 59)));
+        assert!(!hours.is_active(at(17, 13, 30)));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_weekday("Monday"), Some(0));
+        assert_eq!(parse_weekday("sun"), Some(6));
+        assert_eq!(parse_weekday("2"), None);
+        assert_eq!(parse_time("09:30"), Some(570));
+        assert_eq!(parse_time("24:00"), Some(1440));
+        assert_eq!(parse_time("24:01"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("noon"), None);
+        assert_eq!(format_time(570), "09:30");
+    }
+
+    #[test]
+    fn stats() {
+        let visit = |student, waited, joined_at, left_at| OfficeVisit {
+            student,
+            waited,
+            joined_at,
+            left_at,
+        };
+        let session = |visits| OfficeSession {
+            teacher: 1,
+            subject: None,
+            opened_at: 0,
+            closed_at: 3600,
+            visits,
+        };
+        let sessions = [
+            session(vec![
+                visit(2, Some(60), 100, 700),
+                visit(3, None, 800, 1000),
+            ]),
+            session(vec![visit(2, Some(180), 0, 1000)]),
+            session(vec![]),
+        ];
+
+        assert_eq!(
+            OfficeStats::from_sessions(&sessions),
+            OfficeStats {
+                sessions: 3,
+                open_time: 3 * 3600,
+                visits: 3,
+                admitted: 2,
+                students: 2,
+                average_wait: 120,
+                average_visit: 600,
+            }
+        );
+        assert_eq!(OfficeStats::from_sessions(&[]), OfficeStats::default());
+    }
+}
@@ -0,0 +1,37 @@
+use std::{env, time::Duration};
+
+/// Time given to a graceful shutdown when `SHUTDOWN_TIMEOUT` isn't set
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read the `SHUTDOWN_TIMEOUT` environment var, in seconds
+///
+/// Everything left to do once it's elapsed is abandoned
+pub fn timeout_from_env() -> Duration {
+    env::var("SHUTDOWN_TIMEOUT")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map_or(DEFAULT_TIMEOUT, Duration::from_secs)
+}
+
+/// Wait for the process to be asked to stop, with SIGTERM or Ctrl-C
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                let terminate = Box::pin(terminate.recv());
+                let interrupt = Box::pin(tokio::signal::ctrl_c());
+                futures::future::select(terminate, interrupt).await;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "Couldn't listen to SIGTERM");
+                tokio::signal::ctrl_c().await.ok();
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
@@ -1,32 +1,46 @@
-use actix::{clock::Instant, io::FramedWrite, prelude::*};
+use actix::{io::FramedWrite, prelude::*};
+use futures::channel::oneshot;
 use tokio_util::codec::FramedRead;
 
-use std::{io, net, str::FromStr, time::Duration};
+use std::{io, time::Instant};
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::{
-    io::{split, WriteHalf},
+    io::{split, AsyncRead, AsyncWrite},
     net::TcpStream,
 };
 
+use crate::metrics::SOCKET_SESSIONS;
+
 use super::{
     codec::ClientCodec,
-    message::{BotResponse, ServerRequest},
+    health::{now_millis, GetLinkStatus, Heartbeat, LinkStats},
+    message::{BotResponse, Close, ServerRequest, SocketError, Traced},
+    router::Router,
+    transport::{connect_websocket, Endpoint, Writer},
 };
 
 pub struct ChatClient {
-    hb: Instant,
-    framed: FramedWrite<BotResponse, WriteHalf<TcpStream>, ClientCodec>,
+    heartbeat: Heartbeat,
+    stats: LinkStats,
+    framed: FramedWrite<Traced<BotResponse>, Writer, ClientCodec>,
+    router: Router<ServerRequest>,
+    /// Waiting for the client to stop after a `Close`
+    closing: Vec<oneshot::Sender<()>>,
 }
 
 impl Actor for ChatClient {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Context<Self>) {
-        // start heartbeats otherwise server will disconnect after 10 seconds
+        SOCKET_SESSIONS.inc();
+        // start heartbeats otherwise server will disconnect after its timeout
         self.hb(ctx)
     }
 
     fn stopped(&mut self, _: &mut Context<Self>) {
-        println!("Disconnected");
+        tracing::info!("Disconnected");
+        SOCKET_SESSIONS.dec();
 
         // Stop application on disconnect
         System::current().stop();
@@ -35,45 +49,160 @@ impl Actor for ChatClient {
 
 impl ChatClient {
     fn hb(&self, ctx: &mut Context<Self>) {
-        ctx.run_later(Duration::new(1, 0), |act, ctx| {
-            act.framed.write(BotResponse::Ping);
-            act.hb(ctx);
+        ctx.run_interval(self.heartbeat.interval, |act, ctx| {
+            // the server is gone if it stopped answering
+            if act.stats.timed_out(act.heartbeat.timeout, Instant::now()) {
+                tracing::warn!("Server heartbeat failed, disconnecting!");
+                ctx.stop();
+            }
 
-            // client should also check for a timeout here, similar to the
-            // server code
+            act.framed
+                .write(Traced::untraced(BotResponse::Ping(now_millis())));
         });
     }
 }
 
 impl actix::io::WriteHandler<io::Error> for ChatClient {}
 
+/// Write responses coming from the bot to the server
+impl Handler<Traced<BotResponse>> for ChatClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: Traced<BotResponse>, _: &mut Context<Self>) {
+        self.stats.sent();
+        self.framed.write(msg);
+    }
+}
+
+/// Write a response outside of any request, see `Traced::new` otherwise
+impl Handler<BotResponse> for ChatClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: BotResponse, ctx: &mut Context<Self>) {
+        Handler::<Traced<BotResponse>>::handle(self, Traced::untraced(msg), ctx);
+    }
+}
+
+/// Let the backend know the bot is going away, the client stops once the goodbye is written
+impl Handler<Close> for ChatClient {
+    type Result = ResponseFuture<()>;
+
+    fn handle(&mut self, _: Close, _: &mut Context<Self>) -> Self::Result {
+        self.framed.write(Traced::untraced(BotResponse::Goodbye));
+        self.framed.close();
+
+        // Resolves when the actor drops the sender on stop
+        let (sender, closed) = oneshot::channel();
+        self.closing.push(sender);
+        Box::pin(async move {
+            closed.await.ok();
+        })
+    }
+}
+
+impl Handler<GetLinkStatus> for ChatClient {
+    type Result = MessageResult<GetLinkStatus>;
+
+    fn handle(&mut self, _: GetLinkStatus, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.stats.status())
+    }
+}
+
+type Frame = Result<Result<Traced<ServerRequest>, SocketError>, io::Error>;
+
 /// Server communication
-impl StreamHandler<Result<ServerRequest, io::Error>> for ChatClient {
-    fn handle(&mut self, msg: Result<ServerRequest, io::Error>, ctx: &mut Context<Self>) {
+impl StreamHandler<Frame> for ChatClient {
+    fn handle(&mut self, msg: Frame, ctx: &mut Context<Self>) {
         match msg {
-            Ok(ServerRequest::Ping) => self.hb = Instant::now(),
-            Ok(ServerRequest::GetUser(user)) => {
-                println!("Get User: {}", user);
+            Ok(Ok(Traced {
+                msg: ServerRequest::Ping(sent_at),
+                ..
+            })) => {
+                self.stats.heartbeat();
+                self.framed
+                    .write(Traced::untraced(BotResponse::Pong(sent_at)));
+            }
+            Ok(Ok(Traced {
+                msg: ServerRequest::Pong(sent_at),
+                ..
+            })) => self.stats.pong(sent_at),
+            Ok(Ok(Traced {
+                msg: ServerRequest::Goodbye,
+                ..
+            })) => tracing::info!("Server is shutting down"),
+            Ok(Ok(Traced {
+                msg: ServerRequest::Error(e),
+                request_id,
+            })) => {
+                let request_id = request_id.as_ref().map(|id| id.as_str());
+                tracing::warn!(request_id, error = ?e, "Server couldn't handle a message")
+            }
+            Ok(Ok(request)) => {
+                self.stats.received();
+                let request_id = request.request_id.clone();
+                let span = request.span();
+
+                if let Err(e) = self.router.dispatch(request) {
+                    span.in_scope(|| tracing::warn!(error = ?e, "Unhandled server request"));
+                    self.framed.write(Traced {
+                        request_id,
+                        msg: BotResponse::Error(e),
+                    });
+                }
+            }
+            Ok(Err(e)) => {
+                tracing::warn!(error = ?e, "Couldn't decode server request");
+                self.framed.write(Traced::untraced(BotResponse::Error(e)));
             }
-            _ => ctx.stop(),
+            Err(_) => ctx.stop(),
         }
     }
 }
 
-/// Define tcp client that will connect to tcp listener
-/// chat actors.
-pub async fn tcp_client(s: &str) -> Addr<ChatClient> {
-    // Connect to server
-    let addr = net::SocketAddr::from_str(s).unwrap();
-
-    let stream = TcpStream::connect(&addr).await.unwrap();
-
+/// Start a client talking to the backend over `stream`
+fn start<S>(stream: S, router: Router<ServerRequest>, heartbeat: Heartbeat) -> Addr<ChatClient>
+where
+    S: AsyncRead + AsyncWrite + 'static,
+{
     ChatClient::create(|ctx| {
         let (r, w) = split(stream);
         ChatClient::add_stream(FramedRead::new(r, ClientCodec), ctx);
         ChatClient {
-            hb: Instant::now(),
-            framed: actix::io::FramedWrite::new(w, ClientCodec, ctx),
+            heartbeat,
+            stats: LinkStats::new(),
+            framed: FramedWrite::new(Box::new(w), ClientCodec, ctx),
+            router,
+            closing: Vec::new(),
         }
     })
 }
+
+/// Connect to the backend listening on `endpoint`
+///
+/// Every request other than heartbeats is dispatched with `router`
+pub async fn connect(
+    endpoint: &Endpoint,
+    router: Router<ServerRequest>,
+    heartbeat: Heartbeat,
+) -> io::Result<Addr<ChatClient>> {
+    endpoint.validate()?;
+
+    let client = match endpoint {
+        Endpoint::Tcp(address) => start(TcpStream::connect(address).await?, router, heartbeat),
+        #[cfg(unix)]
+        Endpoint::Unix(path) => start(UnixStream::connect(path).await?, router, heartbeat),
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix sockets aren't supported here",
+            ))
+        }
+        Endpoint::WebSocket { url, secret } => {
+            let stream = connect_websocket(url, secret.as_deref()).await?;
+            start(stream, router, heartbeat)
+        }
+    };
+
+    Ok(client)
+}
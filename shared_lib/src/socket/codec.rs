@@ -1,44 +1,126 @@
 use actix_codec::{Decoder, Encoder};
 use bytes::{Buf, BufMut, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::io;
+
+use crate::{
+    metrics::{SOCKET_FRAMES, UNSUPPORTED_KIND},
+    socket::{
+        message::{BotResponse, ServerRequest, SocketError, Traced},
+        router::Routable,
+    },
+};
+
+/// Size of the length prefix of the frames
+const PREFIX_LEN: usize = 4;
+/// Biggest frame sent or accepted, a bigger length means the stream is out of sync
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+fn too_big(len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("frame of {} bytes, the limit is {}", len, MAX_FRAME_LEN),
+    )
+}
+
+/// Read the next length-prefixed frame of `src`, if it is complete
+///
+/// A frame that can't be decoded is consumed and returned as an error, so the
+/// connection survives a peer sending messages we don't know about
+fn decode_frame<M: DeserializeOwned + Routable>(
+    src: &mut BytesMut,
+) -> io::Result<Option<Result<Traced<M>, SocketError>>> {
+    if src.len() < PREFIX_LEN {
+        return Ok(None);
+    }
+
+    let size = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+    if size > MAX_FRAME_LEN {
+        return Err(too_big(size));
+    }
+    if src.len() < size + PREFIX_LEN {
+        src.reserve(size + PREFIX_LEN - src.len());
+        return Ok(None);
+    }
+
+    src.advance(PREFIX_LEN);
+    let buf = src.split_to(size);
+
+    let msg = from_frame::<M>(&buf).map_err(|e| SocketError::Unsupported(e.to_string()));
+    let kind = match &msg {
+        Ok(msg) => msg.msg.kind(),
+        Err(_) => UNSUPPORTED_KIND,
+    };
+    SOCKET_FRAMES.with_label_values(&["in", kind]).inc();
+
+    Ok(Some(msg))
+}
+
+/// Messages of a request are sent as `{"request_id": .., "msg": ..}`, the others as is
+fn from_frame<M: DeserializeOwned>(buf: &[u8]) -> serde_json::Result<Traced<M>> {
+    let mut value: Value = serde_json::from_slice(buf)?;
 
-use crate::socket::message::{BotResponse, ServerRequest};
+    let traced = match &mut value {
+        Value::Object(fields) if fields.len() == 2 && fields.contains_key("request_id") => {
+            fields.remove("msg").zip(fields.remove("request_id"))
+        }
+        _ => None,
+    };
+
+    match traced {
+        Some((msg, request_id)) => Ok(Traced {
+            request_id: serde_json::from_value(request_id)?,
+            msg: serde_json::from_value(msg)?,
+        }),
+        None => Ok(Traced::untraced(serde_json::from_value(value)?)),
+    }
+}
+
+fn encode_frame<M: Serialize + Routable>(msg: Traced<M>, dst: &mut BytesMut) -> io::Result<()> {
+    let kind = msg.msg.kind();
+    let msg = match msg.request_id {
+        Some(request_id) => json!({ "request_id": request_id, "msg": msg.msg }).to_string(),
+        None => serde_json::to_string(&msg.msg).unwrap(),
+    };
+    let msg_ref: &[u8] = msg.as_ref();
+    if msg_ref.len() > MAX_FRAME_LEN {
+        return Err(too_big(msg_ref.len()));
+    }
+    SOCKET_FRAMES.with_label_values(&["out", kind]).inc();
+
+    dst.reserve(msg_ref.len() + PREFIX_LEN);
+    dst.put_u32(msg_ref.len() as u32);
+    dst.put(msg_ref);
+
+    Ok(())
+}
 
 /// Codec for Client -> Server transport
 pub struct ClientCodec;
 
 impl Decoder for ClientCodec {
-    type Item = ServerRequest;
+    type Item = Result<Traced<ServerRequest>, SocketError>;
     type Error = std::io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let size = {
-            if src.len() < 2 {
-                return Ok(None);
-            }
-            src.get_u16() as usize
-        };
-
-        if src.len() >= size {
-            let buf = src.split_to(size);
-            Ok(Some(serde_json::from_slice::<Self::Item>(&buf)?))
-        } else {
-            Ok(None)
-        }
+        decode_frame(src)
     }
 }
 
-impl Encoder<BotResponse> for ClientCodec {
+impl Encoder<Traced<BotResponse>> for ClientCodec {
     type Error = std::io::Error;
 
-    fn encode(&mut self, msg: BotResponse, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let msg = serde_json::to_string(&msg).unwrap();
-        let msg_ref: &[u8] = msg.as_ref();
+    fn encode(&mut self, msg: Traced<BotResponse>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        encode_frame(msg, dst)
+    }
+}
 
-        dst.reserve(msg_ref.len() + 2);
-        dst.put_u16(msg_ref.len() as u16);
-        dst.put(msg_ref);
+impl Encoder<BotResponse> for ClientCodec {
+    type Error = std::io::Error;
 
-        Ok(())
+    fn encode(&mut self, msg: BotResponse, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        encode_frame(Traced::untraced(msg), dst)
     }
 }
 
@@ -46,37 +128,30 @@ impl Encoder<BotResponse> for ClientCodec {
 pub struct ServerCodec;
 
 impl Decoder for ServerCodec {
-    type Item = BotResponse;
+    type Item = Result<Traced<BotResponse>, SocketError>;
     type Error = std::io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let size = {
-            if src.len() < 2 {
-                return Ok(None);
-            }
-            src.get_u16() as usize
-        };
-
-        if src.len() >= size {
-            let buf = src.split_to(size);
-            Ok(Some(serde_json::from_slice::<BotResponse>(&buf)?))
-        } else {
-            Ok(None)
-        }
+        decode_frame(src)
     }
 }
 
-impl Encoder<ServerRequest> for ServerCodec {
+impl Encoder<Traced<ServerRequest>> for ServerCodec {
     type Error = std::io::Error;
 
-    fn encode(&mut self, msg: ServerRequest, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let msg = serde_json::to_string(&msg).unwrap();
-        let msg_ref: &[u8] = msg.as_ref();
+    fn encode(
+        &mut self,
+        msg: Traced<ServerRequest>,
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        encode_frame(msg, dst)
+    }
+}
 
-        dst.reserve(msg_ref.len() + 2);
-        dst.put_u16(msg_ref.len() as u16);
-        dst.put(msg_ref);
+impl Encoder<ServerRequest> for ServerCodec {
+    type Error = std::io::Error;
 
-        Ok(())
+    fn encode(&mut self, msg: ServerRequest, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        encode_frame(Traced::untraced(msg), dst)
     }
 }
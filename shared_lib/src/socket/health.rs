@@ -0,0 +1,187 @@
+use std::{
+    env,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::timestamp;
+
+/// How often heartbeats are sent, and how long the peer may stay silent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// Read the `HEARTBEAT_INTERVAL` and `HEARTBEAT_TIMEOUT` environment vars, in seconds
+    ///
+    /// Unset or empty vars keep the default, other values must be valid
+    pub fn from_env() -> Result<Self, String> {
+        let default = Heartbeat::default();
+        let seconds = |key: &str, default: Duration| match env::var(key) {
+            Ok(value) if !value.trim().is_empty() => value
+                .trim()
+                .parse::<u64>()
+                .map(Duration::from_secs)
+                .map_err(|_| format!("{} is not a number of seconds: {}", key, value)),
+            _ => Ok(default),
+        };
+
+        let heartbeat = Heartbeat {
+            interval: seconds("HEARTBEAT_INTERVAL", default.interval)?,
+            timeout: seconds("HEARTBEAT_TIMEOUT", default.timeout)?,
+        };
+        heartbeat.validate()?;
+
+        Ok(heartbeat)
+    }
+
+    /// A zero interval can't be scheduled, and a timeout within the interval drops the peer
+    /// between two pings
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval.is_zero() {
+            return Err("HEARTBEAT_INTERVAL must be at least a second".to_string());
+        }
+        if self.timeout <= self.interval {
+            return Err("HEARTBEAT_TIMEOUT must be longer than the interval".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// State of one end of the socket
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LinkStatus {
+    /// Unix timestamp of the connection
+    pub connected_at: i64,
+    /// Unix timestamp of the last heartbeat of the peer
+    pub last_heartbeat: i64,
+    /// Round trip time of the last ping in milliseconds, unknown until the first pong
+    pub rtt: Option<u64>,
+    /// Messages other than heartbeats sent to the peer
+    pub sent: u64,
+    /// Messages other than heartbeats received from the peer
+    pub received: u64,
+}
+
+/// Ask a session or a client for its `LinkStatus`
+#[derive(Message)]
+#[rtype(result = "LinkStatus")]
+pub struct GetLinkStatus;
+
+/// Ask the server for the `LinkStatus` of every bot session
+#[derive(Message)]
+#[rtype(result = "Vec<LinkStatus>")]
+pub struct GetStatus;
+
+/// Milliseconds since the unix epoch, carried by pings and echoed by pongs
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Counters kept by each end of the socket
+pub(crate) struct LinkStats {
+    status: LinkStatus,
+    /// Monotonic time of the last heartbeat, for the timeout
+    hb: Instant,
+}
+
+impl LinkStats {
+    pub(crate) fn new() -> Self {
+        let now = timestamp();
+
+        LinkStats {
+            status: LinkStatus {
+                connected_at: now,
+                last_heartbeat: now,
+                rtt: None,
+                sent: 0,
+                received: 0,
+            },
+            hb: Instant::now(),
+        }
+    }
+
+    /// The peer pinged us or answered our ping
+    pub(crate) fn heartbeat(&mut self) {
+        self.hb = Instant::now();
+        self.status.last_heartbeat = timestamp();
+    }
+
+    /// The peer answered the ping we sent at `sent_at`
+    pub(crate) fn pong(&mut self, sent_at: u64) {
+        self.heartbeat();
+        self.status.rtt = Some(now_millis().saturating_sub(sent_at));
+    }
+
+    pub(crate) fn sent(&mut self) {
+        self.status.sent += 1;
+    }
+
+    pub(crate) fn received(&mut self) {
+        self.status.received += 1;
+    }
+
+    /// Whether the peer stayed silent for longer than `timeout` at `now`
+    pub(crate) fn timed_out(&self, timeout: Duration, now: Instant) -> bool {
+        now.saturating_duration_since(self.hb) > timeout
+    }
+
+    pub(crate) fn status(&self) -> LinkStatus {
+        self.status.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{now_millis, Heartbeat, LinkStats};
+
+    #[test]
+    fn pongs_measure_round_trips() {
+        let mut stats = LinkStats::new();
+        assert_eq!(stats.status().rtt, None);
+
+        stats.pong(now_millis() - 25);
+        stats.sent();
+        stats.received();
+        stats.received();
+
+        let status = stats.status();
+        assert!(matches!(status.rtt, Some(rtt) if (25..1000).contains(&rtt)));
+        assert_eq!((status.sent, status.received), (1, 2));
+        let timeout = Heartbeat::default().timeout;
+        assert!(!stats.timed_out(timeout, Instant::now()));
+        assert!(stats.timed_out(timeout, Instant::now() + timeout * 2));
+    }
+
+    #[test]
+    fn heartbeats_leave_time_to_answer() {
+        let heartbeat = |interval, timeout| Heartbeat {
+            interval: Duration::from_secs(interval),
+            timeout: Duration::from_secs(timeout),
+        };
+
+        assert_eq!(Heartbeat::default().validate(), Ok(()));
+        assert!(heartbeat(0, 10).validate().is_err());
+        assert!(heartbeat(5, 5).validate().is_err());
+        assert!(heartbeat(5, 2).validate().is_err());
+    }
+}
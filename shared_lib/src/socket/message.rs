@@ -1,21 +1,211 @@
 use actix::{Addr, Message};
 use serde::{Deserialize, Serialize};
 
-use crate::socket::session::Session;
+use crate::{
+    audit::AuditEvent,
+    category::Category,
+    office::{self, HoursChange, OfficeHours},
+    socket::{
+        router::{routable, Routable},
+        session::Session,
+    },
+    trace::RequestId,
+    user::LinkedUser,
+};
 
-#[derive(Serialize, Deserialize, Message, Debug)]
-#[rtype(result = "()")]
-pub enum ServerRequest {
-    Ping,
-    GetUser(String),
+/// Error sent back to the peer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SocketError {
+    /// No handler is registered for this kind of message
+    Unsupported(String),
 }
 
-/// Messages TODO
-#[derive(Serialize, Deserialize, Message, Debug)]
-#[rtype(result = "()")]
-pub enum BotResponse {
-    Ping,
-    User(String),
+routable! {
+    pub mod server_request;
+    #[derive(Serialize, Deserialize, Message, Debug, Clone)]
+    #[rtype(result = "()")]
+    pub enum ServerRequest {
+        /// Heartbeat carrying the time it was sent at, in milliseconds
+        Ping(u64),
+        /// Answer to a `BotResponse::Ping`, echoing its time
+        Pong(u64),
+        /// The bot couldn't handle a message
+        Error(SocketError),
+        /// The backend is shutting down and closes the socket after this frame
+        Goodbye,
+        GetUser(String),
+        /// A user has linked their school account and must receive their roles
+        Verify {
+            discord_id: u64,
+            category: Category,
+            /// Category the current roles were granted for, the ones it alone grants are revoked
+            #[serde(default)]
+            previous: Option<Category>,
+            /// Every ADFS group of the user, some subjects are only open to some groups
+            #[serde(default)]
+            groups: Vec<String>,
+        },
+        /// A user lost their verification and must be stripped of their roles
+        Unverify {
+            discord_id: u64,
+            /// Category the roles were granted for, the other roles are kept
+            #[serde(default)]
+            category: Category,
+        },
+        /// A student moved to another year
+        Promote {
+            discord_id: u64,
+            from: Category,
+            to: Category,
+        },
+        /// A user unlinked their accounts, every access given by the bot is revoked
+        Unlink {
+            discord_id: u64,
+            /// Category the roles were granted for, the other roles are kept
+            #[serde(default)]
+            category: Category,
+        },
+        /// Every scheduled office hours, replaces the ones known by the bot
+        OfficeHours(Vec<office::OfficeHours>),
+        /// Answer to `BotResponse::AddOfficeHours` and `BotResponse::RemoveOfficeHours`, to be
+        /// posted as a reply to `message`
+        OfficeHoursChanged {
+            channel: u64,
+            message: u64,
+            change: HoursChange,
+            result: Result<(), String>,
+        },
+        /// Answer to `BotResponse::GetOfficeStats`, to be posted in `channel`
+        OfficeStats {
+            channel: u64,
+            teacher: u64,
+            stats: office::OfficeStats,
+        },
+        /// Answer to `BotResponse::Whois`, only shown to the member who ran the command
+        Whois {
+            /// Token of the `/whois` interaction
+            token: String,
+            discord_id: u64,
+            user: Option<LinkedUser>,
+        },
+        /// Command stored in the backend outbox, acknowledged with `BotResponse::Ack`
+        ///
+        /// Deliveries are retried until acknowledged, `key` lets the bot skip the replays
+        Outbox {
+            key: String,
+            request: Box<ServerRequest>,
+        },
+    }
+}
+
+routable! {
+    pub mod bot_response;
+    /// Messages TODO
+    #[derive(Serialize, Deserialize, Message, Debug, Clone)]
+    #[rtype(result = "()")]
+    pub enum BotResponse {
+        /// Heartbeat carrying the time it was sent at, in milliseconds
+        Ping(u64),
+        /// Answer to a `ServerRequest::Ping`, echoing its time
+        Pong(u64),
+        /// The backend couldn't handle a message
+        Error(SocketError),
+        /// The bot is shutting down and closes the socket after this frame
+        Goodbye,
+        User(String),
+        Audit(AuditEvent),
+        /// Ask for the scheduled office hours
+        GetOfficeHours,
+        /// Store office hours, asked by the `!hours` command `message` posted in `channel`
+        AddOfficeHours {
+            hours: OfficeHours,
+            channel: u64,
+            message: u64,
+        },
+        /// Delete office hours, asked by the `!hours` command `message` posted in `channel`
+        RemoveOfficeHours {
+            id: i64,
+            teacher: u64,
+            channel: u64,
+            message: u64,
+        },
+        /// An office was closed
+        OfficeSession(office::OfficeSession),
+        /// Ask for the stats of the sessions of `teacher` opened between `from` and `to`
+        GetOfficeStats {
+            teacher: u64,
+            from: i64,
+            to: i64,
+            channel: u64,
+        },
+        /// Ask for the school account linked to `discord_id`
+        Whois {
+            discord_id: u64,
+            /// Token of the `/whois` interaction to answer, valid for 15 minutes
+            token: String,
+        },
+        /// A member joined the guild, answered with `ServerRequest::Verify` if they're verified
+        MemberJoined {
+            discord_id: u64,
+        },
+        /// The command of the outbox with this key was applied
+        Ack {
+            key: String,
+        },
+    }
+}
+
+impl ServerRequest {
+    /// Member whose roles the command changes, the commands about a member must be applied
+    /// in order
+    pub fn member(&self) -> Option<u64> {
+        match self {
+            ServerRequest::Verify { discord_id, .. }
+            | ServerRequest::Unverify { discord_id, .. }
+            | ServerRequest::Promote { discord_id, .. }
+            | ServerRequest::Unlink { discord_id, .. } => Some(*discord_id),
+            ServerRequest::Outbox { request, .. } => request.member(),
+            _ => None,
+        }
+    }
+}
+
+/// Message sent over the socket with the request it belongs to, if any
+#[derive(Debug, Clone)]
+pub struct Traced<M> {
+    pub request_id: Option<RequestId>,
+    pub msg: M,
+}
+
+impl<M> Message for Traced<M> {
+    type Result = ();
+}
+
+impl<M> Traced<M> {
+    /// `msg` as part of the request of the current task
+    pub fn new(msg: M) -> Self {
+        Traced {
+            request_id: RequestId::current(),
+            msg,
+        }
+    }
+
+    /// `msg` outside of any request
+    pub fn untraced(msg: M) -> Self {
+        Traced {
+            request_id: None,
+            msg,
+        }
+    }
+}
+
+impl<M: Routable> Traced<M> {
+    /// Span of the handling of the message, carrying its request ID
+    pub fn span(&self) -> tracing::Span {
+        let request_id = self.request_id.as_ref().map(RequestId::as_str);
+
+        tracing::info_span!("message", kind = self.msg.kind(), request_id)
+    }
 }
 
 /// New session is created
@@ -31,3 +221,16 @@ pub struct Connect {
 pub struct Disconnect {
     pub id: usize,
 }
+
+/// Say goodbye to the peer and close the socket once the pending frames are written
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Close;
+
+/// Message received from the bot on session `id`
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Received {
+    pub id: usize,
+    pub msg: Traced<BotResponse>,
+}
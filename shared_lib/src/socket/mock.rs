@@ -0,0 +1,363 @@
+//! Pieces of the `mock_bot` and `mock_backend` dev binaries
+//!
+//! Traffic is printed as one JSON message per line, the same format the console reads, and
+//! can be recorded to JSONL files replayed later as regression tests
+
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix::System;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::socket::message::{BotResponse, ServerRequest};
+
+/// Time left to the peer to answer the last replayed message
+const REPLAY_GRACE: Duration = Duration::from_secs(2);
+
+/// A message going either way on the socket
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Traffic {
+    Request(ServerRequest),
+    Response(BotResponse),
+}
+
+impl Traffic {
+    /// JSON of the message without the parts that change on every run
+    pub fn normalized(&self) -> Value {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+
+        // Outbox keys are random, only the command matters
+        for path in ["/Request/Outbox/key", "/Response/Ack/key"] {
+            if let Some(key) = value.pointer_mut(path) {
+                *key = Value::Null;
+            }
+        }
+
+        value
+    }
+}
+
+/// Line of a recording
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Exchange {
+    /// Milliseconds since the start of the recording
+    pub at: u64,
+    #[serde(flatten)]
+    pub traffic: Traffic,
+}
+
+/// End of the socket a mock plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Bot,
+    Backend,
+}
+
+impl Side {
+    /// Whether this side sends `traffic`
+    pub fn sends(self, traffic: &Traffic) -> bool {
+        matches!(
+            (self, traffic),
+            (Side::Bot, Traffic::Response(_)) | (Side::Backend, Traffic::Request(_))
+        )
+    }
+}
+
+/// Log of the traffic seen by a mock, optionally recorded to a JSONL file
+pub struct Tape {
+    side: Side,
+    start: Instant,
+    file: Option<BufWriter<File>>,
+    received: Vec<Traffic>,
+}
+
+impl Tape {
+    pub fn new(side: Side, record: Option<&Path>) -> io::Result<Self> {
+        let file = match record {
+            Some(path) => Some(BufWriter::new(File::create(path)?)),
+            None => None,
+        };
+
+        Ok(Tape {
+            side,
+            start: Instant::now(),
+            file,
+            received: Vec::new(),
+        })
+    }
+
+    /// Print and record a message sent or received by this side
+    pub fn log(&mut self, traffic: Traffic) {
+        let arrow = if self.side.sends(&traffic) {
+            "->"
+        } else {
+            "<-"
+        };
+        let message = match &traffic {
+            Traffic::Request(request) => serde_json::to_string(request),
+            Traffic::Response(response) => serde_json::to_string(response),
+        };
+        println!("{} {}", arrow, message.unwrap_or_default());
+
+        if let Some(file) = &mut self.file {
+            let exchange = Exchange {
+                at: self.start.elapsed().as_millis() as u64,
+                traffic: traffic.clone(),
+            };
+            let written = serde_json::to_writer(&mut *file, &exchange)
+                .map_err(io::Error::from)
+                .and_then(|_| writeln!(file))
+                .and_then(|_| file.flush());
+
+            if let Err(e) = written {
+                println!("Couldn't record {:?}: {}", exchange, e);
+            }
+        }
+
+        if !self.side.sends(&traffic) {
+            self.received.push(traffic);
+        }
+    }
+
+    /// Messages received from the peer so far
+    pub fn received(&self) -> &[Traffic] {
+        &self.received
+    }
+}
+
+/// Read a recording
+pub fn load(path: &Path) -> io::Result<Vec<Exchange>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+/// Play the messages `tape`'s side sent in `exchanges` with their original timing, with
+/// `send`, then check the peer answered the same way
+///
+/// Returns the differences, empty when the peer behaved like in the recording
+pub async fn replay<F>(tape: &Arc<Mutex<Tape>>, exchanges: &[Exchange], send: F) -> Vec<String>
+where
+    F: Fn(Traffic),
+{
+    let side = tape.lock().unwrap().side;
+    let start = Instant::now();
+
+    for exchange in exchanges.iter().filter(|e| side.sends(&e.traffic)) {
+        // Acks are sent by the mock itself, with the keys of this run
+        if let Traffic::Response(BotResponse::Ack { .. }) = exchange.traffic {
+            continue;
+        }
+
+        let at = Duration::from_millis(exchange.at);
+        actix::clock::sleep(at.saturating_sub(start.elapsed())).await;
+
+        tape.lock().unwrap().log(exchange.traffic.clone());
+        send(exchange.traffic.clone());
+    }
+
+    let expected: Vec<Value> = exchanges
+        .iter()
+        .filter(|e| !side.sends(&e.traffic))
+        .map(|e| e.traffic.normalized())
+        .collect();
+    let deadline = Instant::now() + REPLAY_GRACE;
+    while tape.lock().unwrap().received().len() < expected.len() && Instant::now() < deadline {
+        actix::clock::sleep(Duration::from_millis(50)).await;
+    }
+
+    let received: Vec<Value> = tape
+        .lock()
+        .unwrap()
+        .received()
+        .iter()
+        .map(Traffic::normalized)
+        .collect();
+
+    diff(&expected, &received)
+}
+
+fn diff(expected: &[Value], received: &[Value]) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    for i in 0..expected.len().max(received.len()) {
+        match (expected.get(i), received.get(i)) {
+            (Some(e), Some(r)) if e == r => (),
+            (Some(e), Some(r)) => differences.push(format!("#{}: expected {}, got {}", i, e, r)),
+            (Some(e), None) => differences.push(format!("#{}: expected {}, got nothing", i, e)),
+            (None, Some(r)) => differences.push(format!("#{}: unexpected {}", i, r)),
+            (None, None) => (),
+        }
+    }
+
+    differences
+}
+
+/// Send every JSON message typed on stdin with `send`, stops the system at the end of input
+///
+/// Piping a file of messages gives a scripted session
+pub fn console<M, F>(send: F)
+where
+    M: DeserializeOwned,
+    F: Fn(M) + Send + 'static,
+{
+    let system = System::current();
+
+    std::thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match serde_json::from_str(&line) {
+                Ok(msg) => send(msg),
+                Err(e) => println!("Invalid message: {}", e),
+            }
+        }
+
+        // Let the peer answer the last messages
+        std::thread::sleep(REPLAY_GRACE);
+        system.stop();
+    });
+}
+
+/// Command line of the mocks: `[ENDPOINT] [--record FILE] [--replay FILE]`
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub endpoint: Option<String>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+}
+
+impl Options {
+    pub fn from_args() -> Result<Self, String> {
+        Self::parse(env::args().skip(1))
+    }
+
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+
+        while let Some(arg) = args.next() {
+            let mut file = || {
+                args.next()
+                    .map(PathBuf::from)
+                    .ok_or_else(|| format!("{} needs a file", arg))
+            };
+
+            match arg.as_str() {
+                "--record" => options.record = Some(file()?),
+                "--replay" => options.replay = Some(file()?),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ => options.endpoint = Some(arg),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{diff, load, replay, Options, Side, Tape, Traffic};
+    use crate::{
+        category::Category,
+        socket::message::{BotResponse, ServerRequest},
+    };
+
+    fn outbox(key: &str) -> Traffic {
+        Traffic::Request(ServerRequest::Outbox {
+            key: key.to_string(),
+            request: Box::new(ServerRequest::Unlink {
+                discord_id: 7,
+                category: Category::default(),
+            }),
+        })
+    }
+
+    #[test]
+    fn keys_are_ignored() {
+        assert_eq!(outbox("a").normalized(), outbox("b").normalized());
+        assert_ne!(
+            outbox("a").normalized(),
+            Traffic::Request(ServerRequest::Unlink {
+                discord_id: 7,
+                category: Category::default()
+            })
+            .normalized()
+        );
+    }
+
+    #[test]
+    fn options() {
+        let args = ["ws://leo/bot", "--record", "a.jsonl"].map(String::from);
+
+        assert_eq!(
+            Options::parse(args.into_iter()),
+            Ok(Options {
+                endpoint: Some("ws://leo/bot".to_string()),
+                record: Some("a.jsonl".into()),
+                replay: None,
+            })
+        );
+        assert!(Options::parse(["--replay".to_string()].into_iter()).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn record_then_replay() {
+        let path = std::env::temp_dir().join(format!("leo-mock-{}.jsonl", std::process::id()));
+
+        let mut tape = Tape::new(Side::Bot, Some(&path)).unwrap();
+        tape.log(Traffic::Response(BotResponse::GetOfficeHours));
+        tape.log(outbox("a"));
+        tape.log(Traffic::Response(BotResponse::Ack {
+            key: "a".to_string(),
+        }));
+        drop(tape);
+
+        let exchanges = load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(exchanges.len(), 3);
+
+        // A peer answering with another key still matches
+        let tape = Arc::new(Mutex::new(Tape::new(Side::Bot, None).unwrap()));
+        let sent = Mutex::new(Vec::new());
+        let peer = tape.clone();
+        let differences = replay(&tape, &exchanges, |traffic| {
+            sent.lock().unwrap().push(traffic);
+            peer.lock().unwrap().log(outbox("b"));
+        })
+        .await;
+
+        assert!(differences.is_empty(), "{:?}", differences);
+        assert!(matches!(
+            sent.lock().unwrap().as_slice(),
+            [Traffic::Response(BotResponse::GetOfficeHours)]
+        ));
+    }
+
+    #[test]
+    fn differences() {
+        let a = outbox("a").normalized();
+        let b = Traffic::Response(BotResponse::GetOfficeHours).normalized();
+
+        let same = [a.clone()];
+        assert!(diff(&same, &same).is_empty());
+        assert_eq!(diff(&[a.clone(), b.clone()], &[b]).len(), 2);
+        assert_eq!(diff(&[], &[a]).len(), 1);
+    }
+}
@@ -1,8 +1,12 @@
 pub mod client;
 pub mod codec;
+pub mod health;
 pub mod message;
+pub mod mock;
+pub mod router;
 pub mod server;
 pub mod session;
+pub mod transport;
 
 #[cfg(test)]
 mod tests {
@@ -10,9 +14,18 @@ mod tests {
     use bytes::{BufMut, BytesMut};
     use serde_json::json;
 
-    use crate::socket::{codec::ServerCodec, message::ServerRequest};
+    use crate::{
+        socket::{
+            codec::{ServerCodec, MAX_FRAME_LEN},
+            message::{ServerRequest, Traced},
+        },
+        trace::RequestId,
+    };
 
-    use super::{codec::ClientCodec, message::BotResponse};
+    use super::{
+        codec::ClientCodec,
+        message::{BotResponse, SocketError},
+    };
 
     #[test]
     fn client_codec_encode() {
@@ -24,13 +37,13 @@ mod tests {
         let mut bytes = BytesMut::new();
 
         assert!(codec.encode(user_msg, &mut bytes,).is_ok());
-        assert!(codec.encode(BotResponse::Ping, &mut bytes).is_ok());
+        assert!(codec.encode(BotResponse::Ping(0), &mut bytes).is_ok());
     }
 
     #[test]
     fn client_codec_decode() {
         let mut codec = ClientCodec;
-        let content = b"\0\"{\"GetUser\":\"{\\\"discord_id\\\":123}\"}\0\x06\"Ping\"";
+        let content = b"\0\0\0\"{\"GetUser\":\"{\\\"discord_id\\\":123}\"}\0\0\0\x0a{\"Ping\":0}";
 
         let mut bytes = BytesMut::new();
         bytes.reserve(content.len());
@@ -39,8 +52,16 @@ mod tests {
         let user_result = codec.decode(&mut bytes).unwrap();
         let ping_result = codec.decode(&mut bytes).unwrap();
 
-        assert!(matches!(user_result, Some(ServerRequest::GetUser(u)) if u.contains("discord_id")));
-        assert!(matches!(ping_result, Some(ServerRequest::Ping)));
+        assert!(
+            matches!(user_result, Some(Ok(Traced { msg: ServerRequest::GetUser(u), .. })) if u.contains("discord_id"))
+        );
+        assert!(matches!(
+            ping_result,
+            Some(Ok(Traced {
+                msg: ServerRequest::Ping(0),
+                ..
+            }))
+        ));
     }
 
     #[test]
@@ -53,13 +74,13 @@ mod tests {
         let mut bytes = BytesMut::new();
 
         assert!(codec.encode(user_msg, &mut bytes,).is_ok());
-        assert!(codec.encode(ServerRequest::Ping, &mut bytes).is_ok());
+        assert!(codec.encode(ServerRequest::Ping(0), &mut bytes).is_ok());
     }
 
     #[test]
     fn server_codec_decode() {
         let mut codec = ServerCodec;
-        let content = b"\0\x1f{\"User\":\"{\\\"discord_id\\\":123}\"}\0\x06\"Ping\"";
+        let content = b"\0\0\0\x1f{\"User\":\"{\\\"discord_id\\\":123}\"}\0\0\0\x0a{\"Ping\":0}";
         let mut bytes = BytesMut::new();
         bytes.reserve(content.len());
         bytes.put(&content[..]);
@@ -67,7 +88,104 @@ mod tests {
         let user_result = codec.decode(&mut bytes).unwrap();
         let ping_result = codec.decode(&mut bytes).unwrap();
 
-        assert!(matches!(user_result, Some(BotResponse::User(u)) if u.contains("discord_id")));
-        assert!(matches!(ping_result, Some(BotResponse::Ping)));
+        assert!(
+            matches!(user_result, Some(Ok(Traced { msg: BotResponse::User(u), .. })) if u.contains("discord_id"))
+        );
+        assert!(matches!(
+            ping_result,
+            Some(Ok(Traced {
+                msg: BotResponse::Ping(0),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn server_codec_waits_for_whole_frames() {
+        let mut frame = BytesMut::new();
+        ClientCodec
+            .encode(BotResponse::GetOfficeHours, &mut frame)
+            .unwrap();
+
+        let mut bytes = frame.split_to(3);
+        assert!(ServerCodec.decode(&mut bytes).unwrap().is_none());
+
+        bytes.unsplit(frame);
+        assert!(matches!(
+            ServerCodec.decode(&mut bytes).unwrap(),
+            Some(Ok(Traced {
+                msg: BotResponse::GetOfficeHours,
+                ..
+            }))
+        ));
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn frames_over_64_kib() {
+        let schedule = "x".repeat(100 * 1024);
+        let mut bytes = BytesMut::new();
+        ServerCodec
+            .encode(ServerRequest::GetUser(schedule.clone()), &mut bytes)
+            .unwrap();
+
+        assert!(matches!(
+            ClientCodec.decode(&mut bytes).unwrap(),
+            Some(Ok(Traced { msg: ServerRequest::GetUser(u), .. })) if u == schedule
+        ));
+        assert!(bytes.is_empty());
+
+        let mut bytes = BytesMut::new();
+        bytes.put_u32(MAX_FRAME_LEN as u32 + 1);
+        assert!(ClientCodec.decode(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn server_codec_skips_unknown_messages() {
+        let content = b"\0\0\0\x0a\"Teleport\"\0\0\0\x0a{\"Ping\":0}";
+        let mut bytes = BytesMut::new();
+        bytes.put(&content[..]);
+
+        assert!(matches!(
+            ServerCodec.decode(&mut bytes).unwrap(),
+            Some(Err(SocketError::Unsupported(_)))
+        ));
+        assert!(matches!(
+            ServerCodec.decode(&mut bytes).unwrap(),
+            Some(Ok(Traced {
+                msg: BotResponse::Ping(0),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn request_ids_cross_the_socket() {
+        let request_id = RequestId::new();
+        let mut bytes = BytesMut::new();
+        ServerCodec
+            .encode(
+                Traced {
+                    request_id: Some(request_id.clone()),
+                    msg: ServerRequest::Ping(0),
+                },
+                &mut bytes,
+            )
+            .unwrap();
+        ServerCodec
+            .encode(ServerRequest::Ping(1), &mut bytes)
+            .unwrap();
+
+        assert!(matches!(
+            ClientCodec.decode(&mut bytes).unwrap(),
+            Some(Ok(Traced { request_id: Some(id), msg: ServerRequest::Ping(0) })) if id == request_id
+        ));
+        assert!(matches!(
+            ClientCodec.decode(&mut bytes).unwrap(),
+            Some(Ok(Traced {
+                request_id: None,
+                msg: ServerRequest::Ping(1)
+            }))
+        ));
     }
 }
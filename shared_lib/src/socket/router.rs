@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+
+use actix::{Message, Recipient};
+
+use crate::socket::message::{SocketError, Traced};
+
+/// Message whose variants can be routed by name
+pub trait Routable: Message<Result = ()> + Send + 'static {
+    /// Name of every variant
+    const KINDS: &'static [&'static str];
+
+    /// Name of the variant of `self`
+    fn kind(&self) -> &'static str;
+}
+
+/// Fields of one variant of `M`, handlers register for it with `Router::route`
+pub trait Variant<M: Routable>: Routable + Into<M> {
+    /// Name of the variant, one of `M::KINDS`
+    const KIND: &'static str;
+
+    /// Fields of `msg`, if it is this variant
+    fn from_message(msg: M) -> Option<Self>;
+}
+
+/// Declare an enum of messages and implement `Routable` for it, each kind being named after
+/// its variant
+///
+/// `module` gets a struct per variant, holding its fields, so handlers only receive the variants
+/// they are routed. The field types are named from `module`, a type sharing the name of a variant
+/// is written with its path
+macro_rules! routable {
+    (
+        pub mod $module:ident;
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident
+                $(($tuple:ty))?
+                $({$($(#[$field_meta:meta])* $field:ident: $field_ty:ty),* $(,)?})?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        pub enum $name {
+            $(
+                $(#[$variant_meta])*
+                $variant
+                $(($tuple))?
+                $({$($(#[$field_meta])* $field: $field_ty),*})?
+            ),*
+        }
+
+        #[doc = concat!("Variants of [`", stringify!($name), "`], routed to their own handlers")]
+        pub mod $module {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $(
+                $crate::socket::router::routable!(
+                    @variant $name $variant $(($tuple))? $({$($field: $field_ty),*})?
+                );
+            )*
+        }
+
+        impl $crate::socket::router::Routable for $name {
+            const KINDS: &'static [&'static str] = &[$(stringify!($variant)),*];
+
+            fn kind(&self) -> &'static str {
+                match self {
+                    $($name::$variant { .. } => stringify!($variant)),*
+                }
+            }
+        }
+    };
+
+    (@variant $name:ident $variant:ident $($fields:tt)?) => {
+        $crate::socket::router::routable!(@struct $name $variant $($fields)?);
+
+        impl ::actix::Message for $variant {
+            type Result = ();
+        }
+
+        impl $crate::socket::router::Routable for $variant {
+            const KINDS: &'static [&'static str] = &[stringify!($variant)];
+
+            fn kind(&self) -> &'static str {
+                stringify!($variant)
+            }
+        }
+
+        impl $crate::socket::router::Variant<$name> for $variant {
+            const KIND: &'static str = stringify!($variant);
+
+            fn from_message(msg: $name) -> Option<Self> {
+                $crate::socket::router::routable!(@from msg $name $variant $($fields)?)
+            }
+        }
+
+        impl From<$variant> for $name {
+            fn from(msg: $variant) -> Self {
+                $crate::socket::router::routable!(@into msg $name $variant $($fields)?)
+            }
+        }
+    };
+
+    (@struct $name:ident $variant:ident) => {
+        #[doc = concat!("Fields of [`", stringify!($name), "::", stringify!($variant), "`]")]
+        #[derive(Debug, Clone)]
+        pub struct $variant;
+    };
+    (@struct $name:ident $variant:ident ($tuple:ty)) => {
+        #[doc = concat!("Fields of [`", stringify!($name), "::", stringify!($variant), "`]")]
+        #[derive(Debug, Clone)]
+        pub struct $variant(pub $tuple);
+    };
+    (@struct $name:ident $variant:ident {$($field:ident: $field_ty:ty),*}) => {
+        #[doc = concat!("Fields of [`", stringify!($name), "::", stringify!($variant), "`]")]
+        #[derive(Debug, Clone)]
+        pub struct $variant {
+            $(pub $field: $field_ty),*
+        }
+    };
+
+    (@from $msg:ident $name:ident $variant:ident) => {
+        match $msg {
+            $name::$variant => Some($variant),
+            _ => None,
+        }
+    };
+    (@from $msg:ident $name:ident $variant:ident ($tuple:ty)) => {
+        match $msg {
+            $name::$variant(value) => Some($variant(value)),
+            _ => None,
+        }
+    };
+    (@from $msg:ident $name:ident $variant:ident {$($field:ident: $field_ty:ty),*}) => {
+        match $msg {
+            $name::$variant { $($field),* } => Some($variant { $($field),* }),
+            _ => None,
+        }
+    };
+
+    (@into $msg:ident $name:ident $variant:ident) => {{
+        let $variant = $msg;
+        $name::$variant
+    }};
+    (@into $msg:ident $name:ident $variant:ident ($tuple:ty)) => {
+        $name::$variant($msg.0)
+    };
+    (@into $msg:ident $name:ident $variant:ident {$($field:ident: $field_ty:ty),*}) => {
+        $name::$variant {
+            $($field: $msg.$field),*
+        }
+    };
+}
+pub(crate) use routable;
+
+/// Hands a message to the handler of its variant
+type Route<M> = Box<dyn Fn(Traced<M>) + Send>;
+
+/// Registry of the handlers of each kind of message received over the socket
+///
+/// Heartbeats and errors are handled by the library and never reach the handlers, the others
+/// arrive with the request they belong to
+pub struct Router<M: Routable> {
+    routes: HashMap<&'static str, Route<M>>,
+}
+
+impl<M: Routable> Default for Router<M> {
+    fn default() -> Self {
+        Router {
+            routes: HashMap::new(),
+        }
+    }
+}
+
+impl<M: Routable> Router<M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send the messages of variant `V` to `recipient`, replacing the previous handler
+    pub fn route<V: Variant<M>>(mut self, recipient: Recipient<Traced<V>>) -> Self {
+        let route = move |Traced { request_id, msg }: Traced<M>| {
+            if let Some(msg) = V::from_message(msg) {
+                if recipient.do_send(Traced { request_id, msg }).is_err() {
+                    tracing::warn!(kind = V::KIND, "Handler is gone, dropping message");
+                }
+            }
+        };
+
+        self.routes.insert(V::KIND, Box::new(route));
+        self
+    }
+
+    /// Send the messages of every kind to `recipient`, for handlers of the whole enum
+    pub fn route_all(mut self, recipient: Recipient<Traced<M>>) -> Self {
+        for &kind in M::KINDS {
+            let recipient = recipient.clone();
+            let route = move |msg: Traced<M>| {
+                if recipient.do_send(msg).is_err() {
+                    tracing::warn!(kind, "Handler is gone, dropping message");
+                }
+            };
+
+            self.routes.insert(kind, Box::new(route));
+        }
+        self
+    }
+
+    /// Hand `msg` to the handler of its kind
+    pub fn dispatch(&self, msg: Traced<M>) -> Result<(), SocketError> {
+        let kind = msg.msg.kind();
+
+        match self.routes.get(kind) {
+            Some(route) => {
+                route(msg);
+                Ok(())
+            }
+            None => Err(SocketError::Unsupported(kind.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use actix::{Actor, Context, Handler};
+
+    use super::{Routable, Router, Variant};
+    use crate::socket::message::{
+        bot_response, server_request, BotResponse, ServerRequest, SocketError, Traced,
+    };
+
+    #[derive(Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Actor for Recorder {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Traced<bot_response::MemberJoined>> for Recorder {
+        type Result = ();
+
+        fn handle(&mut self, msg: Traced<bot_response::MemberJoined>, _: &mut Context<Self>) {
+            let joined = format!("joined {}", msg.msg.discord_id);
+            self.0.lock().unwrap().push(joined);
+        }
+    }
+
+    impl Handler<Traced<BotResponse>> for Recorder {
+        type Result = ();
+
+        fn handle(&mut self, msg: Traced<BotResponse>, _: &mut Context<Self>) {
+            self.0.lock().unwrap().push(msg.msg.kind().to_string());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn dispatch_by_kind() {
+        let recorder = Recorder::default();
+        let received = recorder.0.clone();
+        let router =
+            Router::new().route::<bot_response::MemberJoined>(recorder.start().recipient());
+
+        let dispatch = |msg| router.dispatch(Traced::untraced(msg));
+        assert_eq!(
+            dispatch(BotResponse::MemberJoined { discord_id: 7 }),
+            Ok(())
+        );
+        assert_eq!(
+            dispatch(BotResponse::User(String::new())),
+            Err(SocketError::Unsupported("User".to_string()))
+        );
+
+        actix::clock::sleep(std::time::Duration::from_millis(10)).await;
+        assert_eq!(*received.lock().unwrap(), ["joined 7"]);
+    }
+
+    #[actix_rt::test]
+    async fn whole_enums_get_every_kind() {
+        let recorder = Recorder::default();
+        let received = recorder.0.clone();
+        let router = Router::new().route_all(recorder.start().recipient());
+
+        let dispatch = |msg| router.dispatch(Traced::untraced(msg));
+        assert_eq!(dispatch(BotResponse::GetOfficeHours), Ok(()));
+        assert_eq!(dispatch(BotResponse::User(String::new())), Ok(()));
+
+        actix::clock::sleep(std::time::Duration::from_millis(10)).await;
+        assert_eq!(*received.lock().unwrap(), ["GetOfficeHours", "User"]);
+    }
+
+    #[test]
+    fn variants_convert_both_ways() {
+        let ack = BotResponse::Ack {
+            key: "1".to_string(),
+        };
+        assert!(bot_response::Goodbye::from_message(ack.clone()).is_none());
+
+        let fields = bot_response::Ack::from_message(ack).unwrap();
+        assert_eq!(fields.key, "1");
+        assert!(matches!(fields.into(), BotResponse::Ack { key } if key == "1"));
+
+        let ping: ServerRequest = server_request::Ping(3).into();
+        assert!(matches!(
+            server_request::Ping::from_message(ping),
+            Some(server_request::Ping(3))
+        ));
+    }
+
+    #[test]
+    fn kinds_are_named_after_variants() {
+        let kinds = [
+            BotResponse::Goodbye.kind(),
+            BotResponse::Ping(0).kind(),
+            BotResponse::Ack { key: String::new() }.kind(),
+            ServerRequest::OfficeHours(Vec::new()).kind(),
+        ];
+        assert_eq!(kinds, ["Goodbye", "Ping", "Ack", "OfficeHours"]);
+
+        assert_eq!(BotResponse::KINDS.len(), 14);
+        assert!(ServerRequest::KINDS.contains(&"Outbox"));
+    }
+}
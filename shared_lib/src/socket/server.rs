@@ -4,13 +4,16 @@ use actix::prelude::*;
 use rand::{prelude::ThreadRng, Rng};
 
 use crate::socket::{
-    message::{Connect, Disconnect},
+    health::{GetLinkStatus, GetStatus, LinkStatus},
+    message::{BotResponse, Close, Connect, Disconnect, Received, ServerRequest, Traced},
+    router::Router,
     session::Session,
 };
 
 pub struct Server {
     sessions: HashMap<usize, Addr<Session>>,
     rng: ThreadRng,
+    router: Router<BotResponse>,
 }
 
 impl Default for Server {
@@ -18,10 +21,19 @@ impl Default for Server {
         Self {
             sessions: HashMap::new(),
             rng: rand::thread_rng(),
+            router: Router::new(),
         }
     }
 }
 
+impl Server {
+    /// Dispatch the messages sent by the bot with `router`
+    pub fn with_router(mut self, router: Router<BotResponse>) -> Self {
+        self.router = router;
+        self
+    }
+}
+
 impl Actor for Server {
     type Context = Context<Self>;
 }
@@ -31,7 +43,7 @@ impl Handler<Connect> for Server {
     type Result = usize;
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
-        println!("Bot connected");
+        tracing::info!("Bot connected");
 
         // register session with random id
         let id = self.rng.gen::<usize>();
@@ -47,9 +59,109 @@ impl Handler<Disconnect> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        println!("Bot disconnected");
+        tracing::info!("Bot disconnected");
 
         // remove address
         self.sessions.remove(&msg.id);
     }
 }
+
+/// Handler for Traced message.
+///
+/// Forwards the request to every connected bot session
+impl Handler<Traced<ServerRequest>> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: Traced<ServerRequest>, _: &mut Context<Self>) {
+        let _span = msg.span().entered();
+
+        if self.sessions.is_empty() {
+            tracing::warn!(request = ?msg.msg, "No bot connected, dropping request");
+        }
+
+        for session in self.sessions.values() {
+            session.do_send(msg.clone());
+        }
+    }
+}
+
+/// Handler for ServerRequest message.
+///
+/// Forwards a request outside of any HTTP request, see `Traced::new` otherwise
+impl Handler<ServerRequest> for Server {
+    type Result = ();
+
+    fn handle(&mut self, msg: ServerRequest, ctx: &mut Context<Self>) {
+        Handler::<Traced<ServerRequest>>::handle(self, Traced::untraced(msg), ctx);
+    }
+}
+
+/// Handler for GetStatus message.
+///
+/// Collects the link status of every connected bot session
+impl Handler<GetStatus> for Server {
+    type Result = ResponseFuture<Vec<LinkStatus>>;
+
+    fn handle(&mut self, _: GetStatus, _: &mut Context<Self>) -> Self::Result {
+        let requests: Vec<_> = self
+            .sessions
+            .values()
+            .map(|session| session.send(GetLinkStatus))
+            .collect();
+
+        Box::pin(async move {
+            futures::future::join_all(requests)
+                .await
+                .into_iter()
+                .filter_map(Result::ok)
+                .collect()
+        })
+    }
+}
+
+/// Handler for Close message.
+///
+/// Says goodbye to every connected bot session, resolves once they all wrote it
+impl Handler<Close> for Server {
+    type Result = ResponseFuture<()>;
+
+    fn handle(&mut self, _: Close, _: &mut Context<Self>) -> Self::Result {
+        let closing: Vec<_> = self
+            .sessions
+            .values()
+            .map(|session| session.send(Close))
+            .collect();
+
+        Box::pin(async move {
+            futures::future::join_all(closing).await;
+        })
+    }
+}
+
+/// Handler for Received message.
+///
+/// Messages nobody handles are answered with an error instead of closing the session
+impl Handler<Received> for Server {
+    type Result = ();
+
+    fn handle(&mut self, Received { id, msg }: Received, _: &mut Context<Self>) {
+        let _span = msg.span().entered();
+
+        if let BotResponse::Error(e) = msg.msg {
+            tracing::warn!(error = ?e, "Bot couldn't handle a message");
+            return;
+        }
+
+        let request_id = msg.request_id.clone();
+        if let Err(e) = self.router.dispatch(msg) {
+            tracing::warn!(error = ?e, "Unhandled bot message");
+
+            if let Some(session) = self.sessions.get(&id) {
+                session.do_send(Traced {
+                    request_id,
+                    msg: ServerRequest::Error(e),
+                });
+            }
+        }
+    }
+}
@@ -1,34 +1,46 @@
-use std::{
-    net,
-    str::FromStr,
-    time::{Duration, Instant},
-};
+use std::{io, path::PathBuf, time::Instant};
 
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::{
-    io::{split, WriteHalf},
-    net::{TcpListener, TcpStream},
+    io::{split, AsyncRead, AsyncWrite},
+    net::TcpListener,
+    task::JoinHandle,
 };
 
 use actix::{io::FramedWrite, prelude::*};
+use actix_web::{web, HttpRequest, Resource};
+use futures::channel::oneshot;
 use tokio_util::codec::FramedRead;
 
-use crate::socket::{
-    codec::ServerCodec,
-    message::{BotResponse, Connect, Disconnect, ServerRequest},
-    server::Server,
+use crate::{
+    metrics::SOCKET_SESSIONS,
+    socket::{
+        codec::ServerCodec,
+        health::{now_millis, GetLinkStatus, Heartbeat, LinkStats},
+        message::{
+            BotResponse, Close, Connect, Disconnect, Received, ServerRequest, SocketError, Traced,
+        },
+        server::Server,
+        transport::{accept_websocket, Endpoint, Writer},
+    },
 };
 
 pub struct Session {
     id: usize,
     addr: Addr<Server>,
-    hb: Instant,
-    framed: FramedWrite<ServerRequest, WriteHalf<TcpStream>, ServerCodec>,
+    heartbeat: Heartbeat,
+    stats: LinkStats,
+    framed: FramedWrite<Traced<ServerRequest>, Writer, ServerCodec>,
+    /// Waiting for the session to stop after a `Close`
+    closing: Vec<oneshot::Sender<()>>,
 }
 
 impl Actor for Session {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        SOCKET_SESSIONS.inc();
         self.hb(ctx);
 
         let addr = ctx.address();
@@ -47,6 +59,7 @@ impl Actor for Session {
     }
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        SOCKET_SESSIONS.dec();
         self.addr.do_send(Disconnect { id: self.id });
         Running::Stop
     }
@@ -54,38 +67,101 @@ impl Actor for Session {
 
 impl actix::io::WriteHandler<std::io::Error> for Session {}
 
+type Frame = Result<Result<Traced<BotResponse>, SocketError>, std::io::Error>;
+
 /// To use `Framed` we have to define Io type and Codec
-impl StreamHandler<Result<BotResponse, std::io::Error>> for Session {
-    fn handle(&mut self, msg: Result<BotResponse, std::io::Error>, ctx: &mut Context<Self>) {
+impl StreamHandler<Frame> for Session {
+    fn handle(&mut self, msg: Frame, ctx: &mut Context<Self>) {
         match msg {
-            // we update heartbeat time on ping from peer
-            Ok(BotResponse::Ping) => self.hb = Instant::now(),
-            _ => ctx.stop(),
+            // we answer pings so the peer can measure the round trip
+            Ok(Ok(Traced {
+                msg: BotResponse::Ping(sent_at),
+                ..
+            })) => {
+                self.stats.heartbeat();
+                self.framed
+                    .write(Traced::untraced(ServerRequest::Pong(sent_at)));
+            }
+            Ok(Ok(Traced {
+                msg: BotResponse::Pong(sent_at),
+                ..
+            })) => self.stats.pong(sent_at),
+            Ok(Ok(Traced {
+                msg: BotResponse::Goodbye,
+                ..
+            })) => tracing::info!("Bot is shutting down"),
+            Ok(Ok(msg)) => {
+                self.stats.received();
+                self.addr.do_send(Received { id: self.id, msg });
+            }
+            Ok(Err(e)) => {
+                tracing::warn!(error = ?e, "Couldn't decode bot message");
+                self.framed.write(Traced::untraced(ServerRequest::Error(e)));
+            }
+            Err(_) => ctx.stop(),
         }
     }
 }
 
+/// Write requests coming from the server to the bot
+impl Handler<Traced<ServerRequest>> for Session {
+    type Result = ();
+
+    fn handle(&mut self, msg: Traced<ServerRequest>, _: &mut Context<Self>) {
+        self.stats.sent();
+        self.framed.write(msg);
+    }
+}
+
+/// Let the bot know the backend is going away, the session stops once the goodbye is written
+impl Handler<Close> for Session {
+    type Result = ResponseFuture<()>;
+
+    fn handle(&mut self, _: Close, _: &mut Context<Self>) -> Self::Result {
+        self.framed.write(Traced::untraced(ServerRequest::Goodbye));
+        self.framed.close();
+
+        // Resolves when the actor drops the sender on stop
+        let (sender, closed) = oneshot::channel();
+        self.closing.push(sender);
+        Box::pin(async move {
+            closed.await.ok();
+        })
+    }
+}
+
+impl Handler<GetLinkStatus> for Session {
+    type Result = MessageResult<GetLinkStatus>;
+
+    fn handle(&mut self, _: GetLinkStatus, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.stats.status())
+    }
+}
+
 impl Session {
     pub fn new(
         addr: Addr<Server>,
-        framed: FramedWrite<ServerRequest, WriteHalf<TcpStream>, ServerCodec>,
+        framed: FramedWrite<Traced<ServerRequest>, Writer, ServerCodec>,
+        heartbeat: Heartbeat,
     ) -> Session {
         Session {
             id: 0,
             addr,
-            hb: Instant::now(),
+            heartbeat,
+            stats: LinkStats::new(),
             framed,
+            closing: Vec::new(),
         }
     }
-    /// helper method that sends ping to client every second.
+    /// helper method that sends ping to client every heartbeat interval.
     ///
     /// also this method check heartbeats from client
     fn hb(&self, ctx: &mut Context<Self>) {
-        ctx.run_interval(Duration::new(1, 0), |act, ctx| {
+        ctx.run_interval(self.heartbeat.interval, |act, ctx| {
             // check client heartbeats
-            if Instant::now().duration_since(act.hb) > Duration::new(10, 0) {
+            if act.stats.timed_out(act.heartbeat.timeout, Instant::now()) {
                 // heartbeat timed out
-                println!("Client heartbeat failed, disconnecting!");
+                tracing::warn!("Client heartbeat failed, disconnecting!");
 
                 // notify chat server
                 act.addr.do_send(Disconnect { id: act.id });
@@ -94,33 +170,141 @@ impl Session {
                 ctx.stop();
             }
 
-            act.framed.write(ServerRequest::Ping);
+            act.framed
+                .write(Traced::untraced(ServerRequest::Ping(now_millis())));
             // if we can not send message to sink, sink is closed (disconnected)
         });
     }
 }
 
-/// Define tcp server that will accept incoming tcp connection and create
-/// chat actors.
-pub fn tcp_server(s: &str, server: Addr<Server>) {
-    // Create server listener
-    let addr = net::SocketAddr::from_str(s).unwrap();
-
-    actix_web::rt::spawn(async move {
-        let listener = TcpListener::bind(&addr).await.unwrap();
-
-        loop {
-            let server = server.clone();
-            match listener.accept().await {
-                Ok((stream, _)) => {
-                    Session::create(|ctx| {
-                        let (r, w) = split(stream);
-                        Session::add_stream(FramedRead::new(r, ServerCodec), ctx);
-                        Session::new(server, FramedWrite::new(w, ServerCodec, ctx))
-                    });
+/// Start a session talking to the bot over `stream`
+fn start<S>(stream: S, server: Addr<Server>, heartbeat: Heartbeat)
+where
+    S: AsyncRead + AsyncWrite + 'static,
+{
+    Session::create(|ctx| {
+        let (r, w) = split(stream);
+        Session::add_stream(FramedRead::new(r, ServerCodec), ctx);
+        Session::new(
+            server,
+            FramedWrite::new(Box::new(w), ServerCodec, ctx),
+            heartbeat,
+        )
+    });
+}
+
+/// Accept loop started by `listen`, dropping it leaves the loop running
+pub struct Listener {
+    task: Option<JoinHandle<()>>,
+    /// Socket file to remove once closed
+    path: Option<PathBuf>,
+}
+
+impl Listener {
+    /// Stop accepting bot connections, the sessions already open are left alone
+    pub fn close(self) {
+        if let Some(task) = self.task {
+            task.abort();
+        }
+        if let Some(path) = self.path {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// Accept the bot connections on `endpoint` and create chat actors.
+///
+/// WebSocket endpoints are served by the HTTP server, see `websocket_route`
+pub async fn listen(
+    endpoint: &Endpoint,
+    server: Addr<Server>,
+    heartbeat: Heartbeat,
+) -> io::Result<Listener> {
+    endpoint.validate()?;
+
+    let listener = match endpoint {
+        Endpoint::Tcp(address) => {
+            let listener = TcpListener::bind(address).await?;
+
+            let task = actix_web::rt::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => start(stream, server.clone(), heartbeat),
+                        Err(e) => tracing::error!(error = %e, "Couldn't accept the bot"),
+                    }
                 }
-                Err(e) => println!("couldn't get client: {:?}", e),
+            });
+
+            Listener {
+                task: Some(task),
+                path: None,
             }
         }
-    });
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            // The file left by a previous run would make the bind fail
+            std::fs::remove_file(path).ok();
+            let listener = UnixListener::bind(path)?;
+
+            let task = actix_web::rt::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => start(stream, server.clone(), heartbeat),
+                        Err(e) => tracing::error!(error = %e, "Couldn't accept the bot"),
+                    }
+                }
+            });
+
+            Listener {
+                task: Some(task),
+                path: Some(path.clone()),
+            }
+        }
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix sockets aren't supported here",
+            ))
+        }
+        // Closed with the HTTP server
+        Endpoint::WebSocket { .. } => Listener {
+            task: None,
+            path: None,
+        },
+    };
+
+    Ok(listener)
+}
+
+/// Route accepting the bot on a WebSocket `endpoint`, to mount on the HTTP server
+///
+/// Other transports have no route, a WebSocket without a secret is an error
+pub fn websocket_route(
+    endpoint: &Endpoint,
+    server: Addr<Server>,
+    heartbeat: Heartbeat,
+) -> io::Result<Option<Resource>> {
+    endpoint.validate()?;
+    let (path, secret) = match (endpoint.websocket_path(), endpoint) {
+        (
+            Some(path),
+            Endpoint::WebSocket {
+                secret: Some(secret),
+                ..
+            },
+        ) => (path, secret.clone()),
+        _ => return Ok(None),
+    };
+
+    let handler = move |req: HttpRequest, payload: web::Payload| {
+        let accepted = accept_websocket(&req, payload, &secret).map(|(stream, response)| {
+            start(stream, server.clone(), heartbeat);
+            response
+        });
+
+        async move { accepted }
+    };
+
+    Ok(Some(web::resource(path).route(web::get().to(handler))))
 }
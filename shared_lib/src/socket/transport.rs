@@ -0,0 +1,266 @@
+use std::{fmt, io, path::PathBuf, str::FromStr};
+
+use actix_http::ws::{OpCode, Parser};
+use actix_web::{
+    body::BodyStream,
+    error::ErrorUnauthorized,
+    http::header::AUTHORIZATION,
+    web::{self, Bytes, BytesMut},
+    HttpRequest, HttpResponse,
+};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{duplex, split, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio_util::io::ReaderStream;
+
+/// Write half of any transport
+pub type Writer = Box<dyn AsyncWrite + Unpin>;
+
+/// Bytes buffered between a WebSocket and its session
+const PIPE_SIZE: usize = 64 * 1024;
+/// Biggest WebSocket frame accepted from the bot
+const MAX_FRAME_SIZE: usize = 1 << 20;
+
+/// Where the bot link listens or connects, selected by the scheme of a URL
+///
+/// - `tcp://127.0.0.1:1234`, a bare `127.0.0.1:1234` is also TCP
+/// - `unix:///run/leo/bot.sock`
+/// - `ws://example.com/bot`, served by a route of the HTTP server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(String),
+    Unix(PathBuf),
+    /// The secret is sent as a bearer token, a public route can't trust the network
+    WebSocket {
+        url: String,
+        secret: Option<String>,
+    },
+}
+
+impl Endpoint {
+    /// Share `secret` between both ends of a WebSocket, other transports ignore it
+    pub fn with_secret(mut self, secret: Option<String>) -> Self {
+        if let Endpoint::WebSocket {
+            secret: current, ..
+        } = &mut self
+        {
+            *current = secret;
+        }
+        self
+    }
+
+    /// Path of the route serving a WebSocket endpoint
+    pub fn websocket_path(&self) -> Option<&str> {
+        let url = match self {
+            Endpoint::WebSocket { url, .. } => url,
+            _ => return None,
+        };
+        let rest = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+        let path = rest.find('/').map_or("/", |start| &rest[start..]);
+
+        Some(path.split('?').next().unwrap_or(path))
+    }
+
+    /// A WebSocket is reachable by anyone, it can't be used without a secret
+    pub fn validate(&self) -> io::Result<()> {
+        match self {
+            Endpoint::WebSocket { secret: None, .. } => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the WebSocket transport needs a secret",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once("://") {
+            Some(("tcp", address)) => Ok(Endpoint::Tcp(address.to_string())),
+            Some(("unix", path)) => Ok(Endpoint::Unix(path.into())),
+            Some(("ws", _)) | Some(("wss", _)) => Ok(Endpoint::WebSocket {
+                url: s.to_string(),
+                secret: None,
+            }),
+            Some((scheme, _)) => Err(format!("unsupported socket scheme {}", scheme)),
+            None => Ok(Endpoint::Tcp(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(address) => write!(f, "tcp://{}", address),
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+            Endpoint::WebSocket { url, .. } => write!(f, "{}", url),
+        }
+    }
+}
+
+/// Compare every byte whatever the first difference, so the time taken doesn't tell how
+/// much of a guess was right
+fn same_secret(token: &[u8], secret: &[u8]) -> bool {
+    token.len() == secret.len()
+        && token
+            .iter()
+            .zip(secret)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Answer the WebSocket handshake of the bot
+///
+/// The returned stream carries the bytes of the binary messages, so the session reads
+/// the same frames as on the other transports
+pub(crate) fn accept_websocket(
+    req: &HttpRequest,
+    mut payload: web::Payload,
+    secret: &str,
+) -> actix_web::Result<(DuplexStream, HttpResponse)> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !token.is_some_and(|token| same_secret(token.as_bytes(), secret.as_bytes())) {
+        return Err(ErrorUnauthorized("bad socket secret"));
+    }
+
+    let mut handshake = actix_http::ws::handshake(req.head())?;
+    let (local, remote) = duplex(PIPE_SIZE);
+    let (reader, mut writer) = split(remote);
+
+    // Bot -> session
+    actix_web::rt::spawn(async move {
+        let mut buf = BytesMut::new();
+
+        while let Some(Ok(chunk)) = payload.next().await {
+            buf.extend_from_slice(&chunk);
+
+            loop {
+                let bytes = match Parser::parse(&mut buf, true, MAX_FRAME_SIZE) {
+                    Ok(Some((_, OpCode::Binary | OpCode::Continue, Some(bytes)))) => bytes,
+                    Ok(Some((_, OpCode::Close, _))) | Err(_) => return,
+                    Ok(Some(_)) => continue,
+                    Ok(None) => break,
+                };
+
+                if writer.write_all(&bytes).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    // Session -> bot, closed once the session stops so the bot hangs up and ends the request
+    let frames = ReaderStream::new(reader)
+        .map(|chunk| {
+            let mut frame = BytesMut::new();
+            Parser::write_message(&mut frame, chunk?, OpCode::Binary, true, false);
+            Ok::<Bytes, io::Error>(frame.freeze())
+        })
+        .chain(futures::stream::once(async {
+            let mut frame = BytesMut::new();
+            Parser::write_close(&mut frame, None, false);
+            Ok(frame.freeze())
+        }));
+    let response = handshake.message_body(BodyStream::new(frames))?;
+
+    Ok((local, HttpResponse::from(response).map_into_boxed_body()))
+}
+
+/// Open a WebSocket to the backend, bridged to a stream like `accept_websocket`
+pub(crate) async fn connect_websocket(url: &str, secret: Option<&str>) -> io::Result<DuplexStream> {
+    let mut request = awc::Client::new().ws(url);
+    if let Some(secret) = secret {
+        request = request.bearer_auth(secret);
+    }
+
+    let (_, framed) = request
+        .connect()
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e.to_string()))?;
+    let (mut sink, mut stream) = framed.split();
+    let (local, remote) = duplex(PIPE_SIZE);
+    let (reader, mut writer) = split(remote);
+
+    // Backend -> client
+    actix_web::rt::spawn(async move {
+        while let Some(Ok(frame)) = stream.next().await {
+            let bytes = match frame {
+                awc::ws::Frame::Binary(bytes) => bytes,
+                awc::ws::Frame::Close(_) => return,
+                _ => continue,
+            };
+
+            if writer.write_all(&bytes).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    // Client -> backend
+    actix_web::rt::spawn(async move {
+        let mut chunks = ReaderStream::new(reader);
+
+        while let Some(Ok(chunk)) = chunks.next().await {
+            if sink.send(awc::ws::Message::Binary(chunk)).await.is_err() {
+                return;
+            }
+        }
+        sink.send(awc::ws::Message::Close(None)).await.ok();
+    });
+
+    Ok(local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{same_secret, Endpoint};
+
+    #[test]
+    fn endpoints_from_urls() {
+        let cases = [
+            (
+                "127.0.0.1:1234",
+                Endpoint::Tcp("127.0.0.1:1234".to_string()),
+            ),
+            ("tcp://leo:1234", Endpoint::Tcp("leo:1234".to_string())),
+            (
+                "unix:///run/leo.sock",
+                Endpoint::Unix("/run/leo.sock".into()),
+            ),
+        ];
+        for (url, endpoint) in cases {
+            assert_eq!(url.parse(), Ok(endpoint), "{}", url);
+        }
+
+        let ws: Endpoint = "ws://leo.example/api/bot?v=1".parse().unwrap();
+        assert_eq!(ws.websocket_path(), Some("/api/bot"));
+        assert_eq!(
+            ws.with_secret(Some("s".to_string())),
+            Endpoint::WebSocket {
+                url: "ws://leo.example/api/bot?v=1".to_string(),
+                secret: Some("s".to_string())
+            }
+        );
+        assert_eq!(
+            "ws://leo.example"
+                .parse::<Endpoint>()
+                .unwrap()
+                .websocket_path(),
+            Some("/")
+        );
+        assert!("http://leo.example".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn secrets_match_exactly() {
+        assert!(same_secret(b"secret", b"secret"));
+        assert!(!same_secret(b"secreT", b"secret"));
+        assert!(!same_secret(b"secret!", b"secret"));
+        assert!(!same_secret(b"", b"secret"));
+    }
+}
@@ -0,0 +1,149 @@
+use std::{env, fmt, future::Future, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+/// Longest request ID accepted from a client
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+/// Identifier following one request across the backend, the socket and the bot
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn new() -> Self {
+        RequestId(format!("{:016x}", rand::random::<u64>()))
+    }
+
+    /// Reuse the ID chosen by a client, if it's short and printable
+    pub fn parse(id: &str) -> Option<Self> {
+        let valid = !id.is_empty()
+            && id.len() <= MAX_REQUEST_ID_LEN
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        valid.then(|| RequestId(id.to_string()))
+    }
+
+    /// ID of the request handled by the current task
+    pub fn current() -> Option<Self> {
+        REQUEST_ID.try_with(Clone::clone).ok()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Run `future` as part of the request `id`, `RequestId::current` returns it meanwhile
+pub async fn scope<F: Future>(id: Option<RequestId>, future: F) -> F::Output {
+    match id {
+        Some(id) => REQUEST_ID.scope(id, future).await,
+        None => future.await,
+    }
+}
+
+/// Run `f` as part of the request `id`, for the synchronous parts of a request
+pub fn sync_scope<R>(id: RequestId, f: impl FnOnce() -> R) -> R {
+    REQUEST_ID.sync_scope(id, f)
+}
+
+/// How the logs are written, from the `LOG_FORMAT` environment var
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// One line of text per event, for people
+    #[default]
+    Human,
+    /// One JSON object per event, for log collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "" | "human" | "text" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format {}", other)),
+        }
+    }
+}
+
+impl LogFormat {
+    pub fn from_env() -> Self {
+        match env::var("LOG_FORMAT").map(|format| format.parse()) {
+            Ok(Ok(format)) => format,
+            Ok(Err(e)) => {
+                eprintln!("{}, logging as text", e);
+                LogFormat::Human
+            }
+            Err(_) => LogFormat::Human,
+        }
+    }
+}
+
+/// Send the logs of the process, `log` records included, to stdout in `format`
+///
+/// Levels are filtered by `RUST_LOG`, `info` by default
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    let installed = match format {
+        LogFormat::Human => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    };
+
+    if let Err(e) = installed {
+        eprintln!("Couldn't install the logger: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scope, LogFormat, RequestId};
+
+    #[test]
+    fn request_ids_from_clients() {
+        assert!(RequestId::parse("3f2a-b_9").is_some());
+        assert!(RequestId::parse("").is_none());
+        assert!(RequestId::parse("a b").is_none());
+        assert!(RequestId::parse(&"a".repeat(65)).is_none());
+        assert_ne!(RequestId::new(), RequestId::new());
+    }
+
+    #[test]
+    fn log_formats() {
+        assert_eq!("JSON".parse(), Ok(LogFormat::Json));
+        assert_eq!("human".parse(), Ok(LogFormat::Human));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[actix_rt::test]
+    async fn current_request() {
+        let id = RequestId::new();
+
+        assert_eq!(RequestId::current(), None);
+        let current = scope(Some(id.clone()), async { RequestId::current() }).await;
+        assert_eq!(current, Some(id));
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::category::Category;
+
+/// School account linked to a discord member, as stored by the backend
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LinkedUser {
+    pub discord_id: u64,
+    pub first_name: String,
+    pub last_name: String,
+    pub mail: String,
+    pub category: Category,
+    pub verified: bool,
+}
+
+impl fmt::Display for LinkedUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "<@{}> is {} {} ({})",
+            self.discord_id, self.first_name, self.last_name, self.mail
+        )?;
+
+        let keys = self.category.role_keys();
+        if !keys.is_empty() {
+            write!(f, ", {}", keys.join(" "))?;
+        }
+        if !self.verified {
+            f.write_str(", not verified")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinkedUser;
+    use crate::category::Category;
+
+    #[test]
+    fn display() {
+        let mut user = LinkedUser {
+            discord_id: 1,
+            first_name: "Ada".to_string(),
+            last_name: "Lovelace".to_string(),
+            mail: "ada@edu.devinci.fr".to_string(),
+            category: Category {
+                year: Some(2),
+                ..Category::default()
+            },
+            verified: true,
+        };
+        assert_eq!(
+            user.to_string(),
+            "<@1> is Ada Lovelace (ada@edu.devinci.fr), a2"
+        );
+
+        user.category = Category::default();
+        user.verified = false;
+        assert_eq!(
+            user.to_string(),
+            "<@1> is Ada Lovelace (ada@edu.devinci.fr), not verified"
+        );
+    }
+}
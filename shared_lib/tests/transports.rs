@@ -0,0 +1,216 @@
+use std::{
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix::{Actor, Addr, Context, Handler, Message};
+use actix_web::{App, HttpServer};
+use shared_lib::socket::{
+    client::connect,
+    health::{GetStatus, Heartbeat},
+    message::{bot_response, server_request, BotResponse, Close, ServerRequest, Traced},
+    router::Router,
+    server::Server,
+    session::{listen, websocket_route},
+    transport::Endpoint,
+};
+use shared_lib::{category::Category, trace::RequestId};
+
+/// Messages of the bot received by the backend
+type Received = Arc<Mutex<Vec<Traced<bot_response::GetOfficeHours>>>>;
+
+/// Actor keeping every message it receives
+struct Recorder<M>(Arc<Mutex<Vec<M>>>);
+
+impl<M: 'static> Actor for Recorder<M> {
+    type Context = Context<Self>;
+}
+
+impl<M: Message<Result = ()> + 'static> Handler<M> for Recorder<M> {
+    type Result = ();
+
+    fn handle(&mut self, msg: M, _: &mut Context<Self>) {
+        self.0.lock().unwrap().push(msg);
+    }
+}
+
+fn recorder<M: Message<Result = ()> + Send + 'static>() -> (Addr<Recorder<M>>, Arc<Mutex<Vec<M>>>) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+
+    (Recorder(received.clone()).start(), received)
+}
+
+async fn eventually(check: impl Fn() -> bool) {
+    for _ in 0..200 {
+        if check() {
+            return;
+        }
+        actix::clock::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("timed out");
+}
+
+/// Backend server dispatching `GetOfficeHours` to the returned messages
+fn backend() -> (Addr<Server>, Received) {
+    let (handler, received) = recorder();
+    let router = Router::new().route::<bot_response::GetOfficeHours>(handler.recipient());
+
+    (Server::default().with_router(router).start(), received)
+}
+
+/// Connect a bot to `endpoint` and send a message each way, the bot's one part of a request
+async fn exchange(endpoint: &Endpoint, server: Addr<Server>, backend: Received) {
+    let (handler, bot) = recorder();
+    let router = Router::new().route::<server_request::Unlink>(handler.recipient());
+    let client = connect(endpoint, router, Heartbeat::default())
+        .await
+        .unwrap();
+
+    // The server drops the requests sent before the session registers
+    let mut sessions = Vec::new();
+    for _ in 0..200 {
+        sessions = server.send(GetStatus).await.unwrap();
+        if !sessions.is_empty() {
+            break;
+        }
+        actix::clock::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(sessions.len(), 1);
+
+    let request_id = RequestId::new();
+    client.do_send(Traced {
+        request_id: Some(request_id.clone()),
+        msg: BotResponse::GetOfficeHours,
+    });
+    server.do_send(ServerRequest::Unlink {
+        discord_id: 7,
+        category: Category::default(),
+    });
+
+    eventually(|| {
+        matches!(
+            backend.lock().unwrap().as_slice(),
+            [Traced { request_id: Some(id), msg: bot_response::GetOfficeHours }] if *id == request_id
+        )
+    })
+    .await;
+    eventually(|| {
+        matches!(
+            bot.lock().unwrap().as_slice(),
+            [Traced {
+                request_id: None,
+                msg: server_request::Unlink { discord_id: 7, .. }
+            }]
+        )
+    })
+    .await;
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+#[actix_rt::test]
+async fn tcp_transport() {
+    let endpoint: Endpoint = format!("tcp://127.0.0.1:{}", free_port()).parse().unwrap();
+    let (server, received) = backend();
+
+    listen(&endpoint, server.clone(), Heartbeat::default())
+        .await
+        .unwrap();
+    exchange(&endpoint, server, received).await;
+}
+
+#[cfg(unix)]
+#[actix_rt::test]
+async fn unix_transport() {
+    let path = std::env::temp_dir().join(format!("leo-bot-{}.sock", std::process::id()));
+    let endpoint: Endpoint = format!("unix://{}", path.display()).parse().unwrap();
+    let (server, received) = backend();
+
+    listen(&endpoint, server.clone(), Heartbeat::default())
+        .await
+        .unwrap();
+    exchange(&endpoint, server, received).await;
+
+    std::fs::remove_file(path).ok();
+}
+
+#[actix_rt::test]
+async fn websocket_transport() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}/bot", listener.local_addr().unwrap());
+    let endpoint = url
+        .parse::<Endpoint>()
+        .unwrap()
+        .with_secret(Some("secret".to_string()));
+    let (server, received) = backend();
+
+    // WebSocket endpoints only listen through the HTTP server, but need a secret
+    let open = url.parse::<Endpoint>().unwrap();
+    assert!(listen(&open, server.clone(), Heartbeat::default())
+        .await
+        .is_err());
+    assert!(websocket_route(&open, server.clone(), Heartbeat::default()).is_err());
+    assert!(connect(&open, Router::new(), Heartbeat::default())
+        .await
+        .is_err());
+    listen(&endpoint, server.clone(), Heartbeat::default())
+        .await
+        .unwrap();
+
+    let routes = (endpoint.clone(), server.clone());
+    let http = HttpServer::new(move || {
+        let (endpoint, server) = routes.clone();
+        App::new().service(
+            websocket_route(&endpoint, server, Heartbeat::default())
+                .unwrap()
+                .unwrap(),
+        )
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    let handle = http.handle();
+    actix_rt::spawn(http);
+
+    let intruder = endpoint.clone().with_secret(Some("guess".to_string()));
+    assert!(connect(&intruder, Router::new(), Heartbeat::default())
+        .await
+        .is_err());
+
+    exchange(&endpoint, server.clone(), received).await;
+
+    // The bot holds an HTTP request, a graceful stop waits for the goodbye
+    server.send(Close).await.unwrap();
+    actix::clock::timeout(Duration::from_secs(5), handle.stop(true))
+        .await
+        .expect("the bot session kept the HTTP server up");
+}
+
+#[actix_rt::test]
+async fn closing_says_goodbye() {
+    let endpoint: Endpoint = format!("tcp://127.0.0.1:{}", free_port()).parse().unwrap();
+    let (server, received) = backend();
+
+    let listener = listen(&endpoint, server.clone(), Heartbeat::default())
+        .await
+        .unwrap();
+    exchange(&endpoint, server.clone(), received).await;
+
+    // No new bot is accepted, the connected one is told goodbye
+    listener.close();
+    actix::clock::sleep(Duration::from_millis(10)).await;
+    assert!(connect(&endpoint, Router::new(), Heartbeat::default())
+        .await
+        .is_err());
+
+    server.send(Close).await.unwrap();
+    assert!(server.send(GetStatus).await.unwrap().is_empty());
+}